@@ -4,6 +4,8 @@ use embassy_time::{Instant, Timer};
 use esp_hal::{delay::Delay, gpio::{Flex, Level, Pull}};
 use log::error;
 
+use crate::fixed::Tenths;
+
 // enum containing dht responses
 #[derive(Debug)]
 pub enum DhtError {
@@ -13,22 +15,33 @@ pub enum DhtError {
 }
 
 
+// sensors using the same single-wire protocol, they only differ in how the bytes are encoded
+#[derive(Clone, Copy)]
+pub enum DhtModel {
+    // integer byte followed by a decimal byte, sign in the highest bit of the temperature decimal
+    Dht11,
+    // 16-bit values in tenths, sign in the highest bit of the temperature
+    Dht22,
+}
+
 const DHT_BUFFER_SIZE: usize = 5;
 
 // dht struct contains the pin it uses, buffer for data stored from sensors
 // and a delay to use inside the critical section
 pub struct Dht11<'a> {
     pin: Flex<'a>,
+    model: DhtModel,
     buffer: [u8; DHT_BUFFER_SIZE],
     delay: Delay,
 }
 
 impl<'a> Dht11<'a> {
-    pub fn new(pin: Flex<'a>) -> Self {
-        Self { pin, buffer: [0u8; DHT_BUFFER_SIZE], delay: Delay::new() }
+    pub fn new(pin: Flex<'a>, model: DhtModel) -> Self {
+        Self { pin, model, buffer: [0u8; DHT_BUFFER_SIZE], delay: Delay::new() }
     }
 
-    pub async fn read(&mut self) -> Result<(u8, u8), DhtError> {
+    // returns (temperature in °C, relative humidity in %)
+    pub async fn read(&mut self) -> Result<(Tenths, Tenths), DhtError> {
         // clear the buffer
         self.buffer.fill(0);
 
@@ -76,20 +89,42 @@ impl<'a> Dht11<'a> {
 
 match result {
     Ok(_) => {
-        // test if checksum matches
-        if self.buffer[4] != (self.buffer[0] + self.buffer[1] + self.buffer[2] + self.buffer[3]) {
+        // test if checksum matches (the sum is allowed to overflow)
+        let sum = self.buffer[0]
+            .wrapping_add(self.buffer[1])
+            .wrapping_add(self.buffer[2])
+            .wrapping_add(self.buffer[3]);
+        if self.buffer[4] != sum {
             Err(DhtError::ChecksumMismatch)
-        // dht11 does not support decimal places, so only two bytes are returned
         } else {
-            Ok((self.buffer[2], self.buffer[0])) 
+            Ok(self.decode())
         }
     },
     Err(error) => Err(error)
 }
     }
 
+    // turning the raw bytes into temperature and humidity, keeping the decimal places and sign
+    fn decode(&self) -> (Tenths, Tenths) {
+        let b = &self.buffer;
+        match self.model {
+            DhtModel::Dht11 => {
+                let humidity = b[0] as i16 * 10 + (b[1] % 10) as i16;
+                let temperature = b[2] as i16 * 10 + (b[3] & 0x0F) as i16;
+                let temperature = if b[3] & 0x80 != 0 { -temperature } else { temperature };
+                (Tenths(temperature), Tenths(humidity))
+            },
+            DhtModel::Dht22 => {
+                let humidity = u16::from_be_bytes([b[0], b[1]]) as i16;
+                let temperature = u16::from_be_bytes([b[2] & 0x7F, b[3]]) as i16;
+                let temperature = if b[2] & 0x80 != 0 { -temperature } else { temperature };
+                (Tenths(temperature), Tenths(humidity))
+            },
+        }
+    }
+
     // try to read from dht 3 times
-    pub async fn read_with_retry(&mut self, retry_count: u8) -> Result<(u8, u8), DhtError> {
+    pub async fn read_with_retry(&mut self, retry_count: u8) -> Result<(Tenths, Tenths), DhtError> {
         let mut last_error: DhtError = DhtError::NoResponse;
         for i in 0..retry_count {
             match self.read().await {
@@ -0,0 +1,37 @@
+use core::fmt;
+
+extern crate alloc;
+use alloc::fmt::Write;
+use heapless::String;
+
+// signed fixed-point value with one decimal place, -5.3 is stored as -53
+// sensors report at most one decimal, and this avoids floats on the hot path
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Tenths(pub i16);
+
+impl Tenths {
+    pub const fn from_int(value: i16) -> Self {
+        Self(value * 10)
+    }
+
+    // only used for the mqtt payload, the display works on tenths directly
+    pub fn to_f64(self) -> f64 {
+        f64::from(self.0) / 10.0
+    }
+
+    // rounded to the nearest integer, used where the display has no room for decimals
+    pub fn round(&self) -> i16 {
+        if self.0 >= 0 { (self.0 + 5) / 10 } else { (self.0 - 5) / 10 }
+    }
+}
+
+impl fmt::Display for Tenths {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // formatting into a buffer first, so width and alignment apply to the whole number
+        let mut buf = String::<8>::new();
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        let _ = write!(buf, "{}{}.{}", sign, abs / 10, abs % 10);
+        f.pad(&buf)
+    }
+}
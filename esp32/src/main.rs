@@ -42,10 +42,13 @@ use esp_hal::{
 };
 
 mod dht11;
-use dht11::Dht11;
+use dht11::{Dht11, DhtModel};
+
+mod fixed;
 
 mod mqtt;
-use mqtt::{Mqtt, MqttResponse, MQTT_CMD_CHANNEL, MQTT_RESP_CHANNEL};
+use mqtt::{parse_command, Mqtt, MqttResponse, DEVICE_COMMAND_CHANNEL, MQTT_CMD_CHANNEL, MQTT_RESP_CHANNEL};
+use iiot_protocol::{PAYLOAD_LEN, TOPIC_LEN};
 use rust_mqtt::packet::v5::publish_packet::QualityOfService;
 
 mod ui;
@@ -150,9 +153,12 @@ async fn mqtt_task(mqtt: &'static mut Mqtt<'static>) {
     info!("mqtt_task begins");
     let command_receiver = MQTT_CMD_CHANNEL.receiver();
     let response_sender = MQTT_RESP_CHANNEL.sender();
-    let mut payload_buffer = String::<3>::new(); // passed values are u8, so 0-255, 3 characters at most
+    let mut topic_buffer = String::<TOPIC_LEN>::new();
+    let mut payload_buffer = [0u8; PAYLOAD_LEN];
     let mut should_reconnect = true;
     let mut cached_message = None;
+    // last sequence number of each value type, numbering restarts from 1 after a reboot
+    let mut sequence = [0u32; 4];
     let mut already_sent_error = false;
     let mut last_ping = Instant::now();
 
@@ -185,6 +191,28 @@ async fn mqtt_task(mqtt: &'static mut Mqtt<'static>) {
                 }
             } 
         }
+        // command stage, commands are received even while mqtt is disabled, so the server can enable it again
+        // waiting only briefly, the messages are small enough to arrive in a single read
+        if let Some(client) = &mut mqtt.client {
+            match with_timeout(Duration::from_millis(100), client.receive_message()).await {
+                Ok(Ok((topic, payload))) => match parse_command(topic, payload) {
+                    Some(command) => {
+                        info!("MQTT: Received command {:?}", command);
+                        if DEVICE_COMMAND_CHANNEL.try_send(command).is_err() {
+                            error!("MQTT: Command queue is full, dropping the command");
+                        }
+                    },
+                    None => error!("MQTT: Ignoring invalid message on {}", topic),
+                },
+                Ok(Err(reason)) => {
+                    should_reconnect = true;
+                    error!("Receiving failed - {:?} - reconnecting", reason);
+                    continue;
+                },
+                // nothing received
+                Err(_) => {}
+            }
+        }
         // checking if mqtt is enabled before receiving from queue
         if !MQTT_ENABLED.load(Ordering::Acquire) {
             continue;
@@ -193,14 +221,21 @@ async fn mqtt_task(mqtt: &'static mut Mqtt<'static>) {
         // trying to take the cached message, or awaiting one from the channel for 5 seconds
         while let Ok(msg) = match cached_message.take() {
             Some(msg) => Ok(msg),
-            None => with_timeout(Duration::from_secs(5), command_receiver.receive()).await, 
+            None => with_timeout(Duration::from_secs(5), command_receiver.receive()).await.map(|mut msg| {
+                // numbered only once, so a resent cached message keeps its number and can be recognized as a duplicate
+                let seq = &mut sequence[msg.topic as usize];
+                *seq = seq.wrapping_add(1);
+                msg.seq = *seq;
+                msg
+            }),
         } {
             // this should never panic because safety is guaranteed inside mqtt struct
             let client = mqtt.client.as_mut().expect("Client uninitialized");
             info!("MQTT: Sending message, queue length: {}", command_receiver.len());
             // sending the message
+            let topic = msg.topic(&mut topic_buffer);
             match client.send_message(
-                msg.topic(),
+                topic,
                 msg.payload(&mut payload_buffer),
                 QualityOfService::QoS1,
                 false
@@ -217,7 +252,7 @@ async fn mqtt_task(mqtt: &'static mut Mqtt<'static>) {
                         already_sent_error = true;
                         response_sender.send(MqttResponse { status: Err(()), topic: msg.topic }).await;
                     }
-                    error!("MQTT: Sending to {} failed, caching the message and reconnecting...", msg.topic());
+                    error!("MQTT: Sending to {} failed, caching the message and reconnecting...", topic);
                     cached_message = Some(msg);
                     should_reconnect = true;
                     break;
@@ -341,7 +376,7 @@ display.init().await.unwrap();
     // setting up peripherals
     info!("Initializing DHT11");
     let dht_pin = Flex::new(peripherals.GPIO18);
-    let mut dht = Dht11::new(dht_pin);
+    let mut dht = Dht11::new(dht_pin, DhtModel::Dht11);
     let _ = dht.read().await; // dummy read for initialization
     info!("DHT11 initialized!");
 
@@ -402,6 +437,7 @@ display.init().await.unwrap();
     let sensor_receiver = SENSOR_CHANNEL.receiver();
     let mqtt_receiver = MQTT_RESP_CHANNEL.receiver();
     let button_receiver = BUTTON_CHANNEL.receiver();
+    let command_receiver = DEVICE_COMMAND_CHANNEL.receiver();
     // UI loop
     loop {
         match ui.state {
@@ -410,7 +446,13 @@ display.init().await.unwrap();
                 Either4::First(button) => ui.handle_button_press(button).await,
                 Either4::Second(msg) => ui.handle_sensor_message(msg).await,
                 Either4::Third(resp) => ui.handle_mqtt_response(resp).await,
-                Either4::Fourth(_) => ui.tick().await,
+                Either4::Fourth(_) => {
+                    // commands from the server are applied between updates, like a change in the setup menu
+                    while let Ok(command) = command_receiver.try_receive() {
+                        ui.handle_command(command).await;
+                    }
+                    ui.tick().await
+                },
             }
             // in setup state it only listens for buttons (so it does not drop any signals from sensors and mqtt)
             UiState::SelectingDelay | UiState::ModifyingDelay => {
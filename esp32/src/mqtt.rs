@@ -8,11 +8,14 @@ use heapless::String;
 use alloc::fmt::Write;
 
 use embassy_net::{tcp::{ConnectError, TcpSocket}, IpAddress, Stack};
-use embassy_time::Duration;
+use embassy_time::{Duration, Instant};
 use rust_mqtt::{client::{client::MqttClient, client_config::ClientConfig}, packet::v5::{publish_packet::QualityOfService, reason_codes::ReasonCode}, utils::rng_generator::CountingRng};
 use log::info;
 use anyhow::Result;
 
+use iiot_protocol::{Command, Reading, Topic, DEFAULT_DEVICE, PAYLOAD_LEN, TOPIC_LEN};
+
+use crate::fixed::Tenths;
 use crate::ui::ValueType;
 
 // static ip provided by private vpn
@@ -23,12 +26,15 @@ const SOCKET_BUFFER_LEN: usize = 4096;
 static RX_BUFFER: StaticBuffer<SOCKET_BUFFER_LEN> = StaticBuffer::<SOCKET_BUFFER_LEN>::new();
 static TX_BUFFER: StaticBuffer<SOCKET_BUFFER_LEN> = StaticBuffer::<SOCKET_BUFFER_LEN>::new();
 
-const MQTT_BUFFER_LEN: usize = 80;
+// fits the topic and the longest json payload
+const MQTT_BUFFER_LEN: usize = 192;
 static RECV_BUFFER: StaticBuffer<MQTT_BUFFER_LEN> = StaticBuffer::<MQTT_BUFFER_LEN>::new();
 static WRITE_BUFFER: StaticBuffer<MQTT_BUFFER_LEN> = StaticBuffer::<MQTT_BUFFER_LEN>::new();
 
 pub static MQTT_CMD_CHANNEL: Channel<CriticalSectionRawMutex, MqttMessage, 20> = Channel::new();
 pub static MQTT_RESP_CHANNEL: Channel<CriticalSectionRawMutex, MqttResponse, 20> = Channel::new();
+// commands received from the server on {device}/command, applied by the ui
+pub static DEVICE_COMMAND_CHANNEL: Channel<CriticalSectionRawMutex, Command, 4> = Channel::new();
 
 
 // basic error handling
@@ -100,7 +106,7 @@ impl<'a> Mqtt<'a> {
         );
         config.add_max_subscribe_qos(QualityOfService::QoS1);
         config.add_client_id("clientId-ESP32-IIOT");
-        config.max_packet_size = 100;
+        config.max_packet_size = MQTT_BUFFER_LEN as u32;
         // creating the client
         let mut client = Client::new(
             socket,
@@ -111,6 +117,10 @@ impl<'a> Mqtt<'a> {
             config,
         );
         client.connect_to_broker().await?;
+        // commands from the server arrive on {device}/command, the layout is defined by the protocol crate
+        let mut topic = String::<TOPIC_LEN>::new();
+        let _ = write!(topic, "{}", Topic::Command { device: DEFAULT_DEVICE });
+        client.subscribe_to_topic(&topic).await?;
         info!("MQTT: Connected");
         // saving the client
         self.client = Some(client);
@@ -126,22 +136,51 @@ pub struct MqttResponse {
     pub topic: ValueType
 }
 
+const FIRMWARE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
 #[derive(Clone, Copy)]
 pub struct MqttMessage {
     pub topic: ValueType,
-    pub value: u8,
+    pub value: Tenths,
+    // uptime when the value was read, so messages cached during an outage keep their original time
+    pub captured_at: Instant,
+    // per-sensor sequence number, assigned by mqtt_task when the message leaves the queue
+    pub seq: u32,
 }
 
-
 impl MqttMessage {
-    pub fn topic(&self) -> &str {
-        self.topic.topic()
+    pub fn new(topic: ValueType, value: Tenths) -> Self {
+        Self { topic, value, captured_at: Instant::now(), seq: 0 }
     }
 
-    pub fn payload<'a>(&self, buf: &'a mut String<3>) -> &'a [u8] {
+    // {device}/{sensor}, the layout is defined by the protocol crate
+    pub fn topic<'a>(&self, buf: &'a mut String<TOPIC_LEN>) -> &'a str {
         buf.clear();
-        let _ = write!(buf, "{}", self.value);
-        buf.as_bytes()
+        let _ = write!(buf, "{}", Topic::Reading { device: DEFAULT_DEVICE, sensor: self.topic.sensor() });
+        buf.as_str()
+    }
+
+    // json reading from the protocol crate, with the uptime when the value was read (ts) and when it is sent (up),
+    // the board has no clock so the server derives the time of the reading from the difference
+    pub fn payload<'a>(&self, buf: &'a mut [u8; PAYLOAD_LEN]) -> &'a [u8] {
+        let reading = Reading {
+            ts: Some(self.captured_at.as_millis()),
+            up: Some(Instant::now().as_millis()),
+            seq: Some(self.seq),
+            fw: Some(FIRMWARE_VERSION),
+            ..Reading::new(self.topic.sensor(), self.value.to_f64())
+        };
+        // PAYLOAD_LEN fits the longest reading, so encoding cannot run out of space
+        let len = reading.encode(buf).unwrap_or(0);
+        &buf[..len]
+    }
+}
+
+// decodes a message received on the command topic, messages on other topics and invalid payloads give None
+pub fn parse_command(topic: &str, payload: &[u8]) -> Option<Command> {
+    match Topic::parse(topic) {
+        Some(Topic::Command { device }) if device == DEFAULT_DEVICE => Command::decode(payload).ok(),
+        _ => None,
     }
 }
 
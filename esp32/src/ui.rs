@@ -17,7 +17,9 @@ use heapless::String;
 use alloc::fmt::Write;
 use log::{error, info};
 
-use crate::{dht11::Dht11, mqtt::{MqttMessage, MqttResponse}, ButtonType, GraphicsDisplay, SensorMessage};
+use iiot_protocol::{Command, SensorKind};
+
+use crate::{dht11::Dht11, fixed::Tenths, mqtt::{MqttMessage, MqttResponse}, ButtonType, GraphicsDisplay, SensorMessage};
 
 
 pub static BUTTON_CHANNEL: Channel<CriticalSectionRawMutex, ButtonType, 10> = Channel::new();
@@ -75,13 +77,13 @@ pub enum ValueType {
 }
 
 impl ValueType {
-    // returns on which topic each value should be sent over mqtt
-    pub fn topic(&self) -> &str {
+    // sensor kind the value is sent as over mqtt, topics and units come from the protocol crate
+    pub fn sensor(&self) -> SensorKind {
         match self {
-            Self::Contact => "esp32/contact",
-            Self::Humidity => "esp32/humidity",
-            Self::Motion => "esp32/motion",
-            Self::Temperature => "esp32/temperature",
+            Self::Contact => SensorKind::Contact,
+            Self::Humidity => SensorKind::Humidity,
+            Self::Motion => SensorKind::Motion,
+            Self::Temperature => SensorKind::Temperature,
         }
     }
     // returns on which line should each value is displayed
@@ -173,9 +175,10 @@ impl ReadTrackers {
 }
 
 // struct containing current sensor values
+// dht values are empty until the first successful read
 struct CurrentValues {
-    pub temperature: u8,
-    pub humidity: u8,
+    pub temperature: Option<Tenths>,
+    pub humidity: Option<Tenths>,
     pub motion: u8,
     pub contact: u8,
 }
@@ -184,8 +187,8 @@ impl CurrentValues {
     // creating the struct with default values
     pub fn new() -> Self {
         Self {
-            temperature: 255,
-            humidity: 255,
+            temperature: None,
+            humidity: None,
             motion: 0,
             contact: 0,
         }
@@ -297,6 +300,27 @@ impl<'a> Ui<'a> {
         }
     }
 
+    // handler for commands from the server, applied like the same change in the setup menu
+    pub async fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::SetDelay { sensor, secs } => {
+                let secs = secs.clamp(GENERIC_MIN_READ_DELAY, GENERIC_MAX_READ_DELAY);
+                match sensor {
+                    SensorKind::Temperature | SensorKind::Humidity => self.dht_delay = secs,
+                    SensorKind::Motion => MOTION_READ_DELAY.store(secs, Ordering::Release),
+                    SensorKind::Contact => CONTACT_READ_DELAY.store(secs, Ordering::Release),
+                }
+            },
+            Command::SetEnabled { sensor, enabled } => match sensor {
+                SensorKind::Temperature | SensorKind::Humidity => self.dht_enabled = enabled,
+                SensorKind::Motion => MOTION_ENABLED.store(enabled, Ordering::Release),
+                SensorKind::Contact => CONTACT_ENABLED.store(enabled, Ordering::Release),
+            },
+            Command::SetMqtt { enabled } => MQTT_ENABLED.store(enabled, Ordering::Release),
+        }
+        self.redraw().await;
+    }
+
     // handler for mqtt response
     pub async fn handle_mqtt_response(&mut self, resp: MqttResponse) {
         match self.state {
@@ -346,14 +370,14 @@ impl<'a> Ui<'a> {
                 // - update current value
                 // - display value (inverted for blinking)
                 SensorMessage::MotionSensor => {
-                    self.send(MqttMessage { topic: ValueType::Motion, value: 1 }).await;
+                    self.send(MqttMessage::new(ValueType::Motion, Tenths::from_int(1))).await;
                     self.read_trackers.motion.reset();
                     self.current_values.motion = 1;
                     self.draw_inverted_value(ValueType::Motion);
                     let _ = self.display.flush().await;
                 },
                 SensorMessage::ContactSensor => {
-                    self.send(MqttMessage { topic: ValueType::Contact, value: 1 }).await;
+                    self.send(MqttMessage::new(ValueType::Contact, Tenths::from_int(1))).await;
                     self.read_trackers.contact.reset();
                     self.current_values.contact = 1;
                     self.draw_inverted_value(ValueType::Contact);
@@ -378,13 +402,13 @@ impl<'a> Ui<'a> {
                     // if data was read
                     Ok((temperature, humidity)) => {
                         // send values over mqtt
-                        self.send(MqttMessage { topic: ValueType::Temperature, value: temperature }).await;
-                        self.send(MqttMessage { topic: ValueType::Humidity, value: humidity }).await;
+                        self.send(MqttMessage::new(ValueType::Temperature, temperature)).await;
+                        self.send(MqttMessage::new(ValueType::Humidity, humidity)).await;
                         // reset read trackers as data appeared
                         self.read_trackers.dht.reset();
                         // update current values
-                        self.current_values.temperature = temperature;
-                        self.current_values.humidity = humidity;
+                        self.current_values.temperature = Some(temperature);
+                        self.current_values.humidity = Some(humidity);
                         // draw them inverted for blinking
                         self.draw_inverted_value(ValueType::Temperature);
                         self.draw_inverted_value(ValueType::Humidity);
@@ -412,7 +436,7 @@ impl<'a> Ui<'a> {
             if read_motion_elapsed >= read_delay + 1 {
                 // assume that there is no motion
                 // send no motion to mqtt
-                self.send(MqttMessage { topic: ValueType::Motion, value: 0 }).await;
+                self.send(MqttMessage::new(ValueType::Motion, Tenths::from_int(0))).await;
                 // reset read trackers
                 self.read_trackers.motion.reset();
                 // update current values
@@ -437,7 +461,7 @@ impl<'a> Ui<'a> {
             if read_contact_elapsed >= read_delay + 1 {
                 // assume that there is no contact
                 // send no contact to mqtt
-                self.send(MqttMessage { topic: ValueType::Contact, value: 0 }).await;
+                self.send(MqttMessage::new(ValueType::Contact, Tenths::from_int(0))).await;
                 // reset read trackers
                 self.read_trackers.contact.reset();
                 // update current values
@@ -509,17 +533,16 @@ impl<'a> Ui<'a> {
             UiState::Displaying => {
                 match self.dht_enabled {
                     true => {     
-                        if self.current_values.temperature == 255 {
-                            set_buffer!(self, "Temp: ???",);
-                        } else {
-                            set_buffer!(self, "Temp: {:>2}C", self.current_values.temperature);
+                        match self.current_values.temperature {
+                            Some(temperature) => set_buffer!(self, "Temp: {:>5}C", temperature),
+                            None => set_buffer!(self, "Temp: ???",),
                         }
                         self.draw_at(Point { x: DISPLAY_INDENT, y: DisplayLine::LINE1});
    
-                        if self.current_values.humidity == 255 {
-                            set_buffer!(self, "Humidity: ???",);
-                        } else {
-                            set_buffer!(self, "Humidity: {:>2}%", self.current_values.humidity);
+                        // humidity is shown without decimals, as there is no room left before the mqtt prompt
+                        match self.current_values.humidity {
+                            Some(humidity) => set_buffer!(self, "Humidity: {:>2}%", humidity.round()),
+                            None => set_buffer!(self, "Humidity: ???",),
                         }
                         self.draw_at(Point { x: DISPLAY_INDENT, y: DisplayLine::LINE2});
                     },
@@ -690,8 +713,14 @@ impl<'a> Ui<'a> {
             return;
         }
         match value_type {
-            ValueType::Temperature => set_buffer!(self, "{:>2}C", self.current_values.temperature),
-            ValueType::Humidity => set_buffer!(self, "{:>2}%", self.current_values.humidity),
+            ValueType::Temperature => match self.current_values.temperature {
+                Some(temperature) => set_buffer!(self, "{:>5}C", temperature),
+                None => set_buffer!(self, "???",),
+            },
+            ValueType::Humidity => match self.current_values.humidity {
+                Some(humidity) => set_buffer!(self, "{:>2}%", humidity.round()),
+                None => set_buffer!(self, "???",),
+            },
             ValueType::Motion => set_buffer!(self, "{}", if self.current_values.motion != 0 { "YES" } else { " NO" }),
             ValueType::Contact => set_buffer!(self, "{}", if self.current_values.contact != 0 { "YES" } else { " NO" }),
         }
@@ -704,8 +733,14 @@ impl<'a> Ui<'a> {
             return;
         }
         match value_type {
-            ValueType::Temperature => set_buffer!(self, "{:>2}C", self.current_values.temperature),
-            ValueType::Humidity => set_buffer!(self, "{:>2}%", self.current_values.humidity),
+            ValueType::Temperature => match self.current_values.temperature {
+                Some(temperature) => set_buffer!(self, "{:>5}C", temperature),
+                None => set_buffer!(self, "???",),
+            },
+            ValueType::Humidity => match self.current_values.humidity {
+                Some(humidity) => set_buffer!(self, "{:>2}%", humidity.round()),
+                None => set_buffer!(self, "???",),
+            },
             ValueType::Motion => set_buffer!(self, "{}", if self.current_values.motion != 0 { "YES" } else { " NO" }),
             ValueType::Contact => set_buffer!(self, "{}", if self.current_values.contact != 0  { "YES" } else { " NO" }),
         };
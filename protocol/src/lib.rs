@@ -0,0 +1,155 @@
+// messages exchanged between the esp32 and the server over mqtt
+// both sides depend on this crate, so the topic layout and payload format cannot drift apart
+#![no_std]
+
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+pub use serde_json_core::de::Error as DecodeError;
+pub use serde_json_core::ser::Error as EncodeError;
+
+// version of the json reading payload, bumped on incompatible changes
+pub const PAYLOAD_VERSION: u8 = 1;
+// longest encoded reading is around 120 bytes, with every field at its maximum
+pub const PAYLOAD_LEN: usize = 160;
+// longest topic, a 16 character device name with the longest suffix
+pub const TOPIC_LEN: usize = 32;
+// device name used by the esp32 board
+pub const DEFAULT_DEVICE: &str = "esp32";
+
+// sensors a device can report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SensorKind {
+    Temperature,
+    Humidity,
+    Motion,
+    Contact,
+}
+
+impl SensorKind {
+    pub const ALL: [SensorKind; 4] = [Self::Temperature, Self::Humidity, Self::Motion, Self::Contact];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Temperature => "temperature",
+            Self::Humidity => "humidity",
+            Self::Motion => "motion",
+            Self::Contact => "contact",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.as_str() == name)
+    }
+
+    // unit sent and stored next to each reading, binary sensors have none
+    pub fn unit(&self) -> &'static str {
+        match self {
+            Self::Temperature => "°C",
+            Self::Humidity => "%",
+            Self::Motion | Self::Contact => "",
+        }
+    }
+
+    // motion and contact only report 0 or 1
+    pub fn is_binary(&self) -> bool {
+        matches!(self, Self::Motion | Self::Contact)
+    }
+}
+
+// topic layout, readings go to {device}/{sensor} and commands to {device}/command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Topic<'a> {
+    Reading { device: &'a str, sensor: SensorKind },
+    Command { device: &'a str },
+}
+
+const COMMAND_SUFFIX: &str = "command";
+
+impl<'a> Topic<'a> {
+    pub fn parse(topic: &'a str) -> Option<Self> {
+        let (device, suffix) = topic.split_once('/')?;
+        if device.is_empty() {
+            return None;
+        }
+        if suffix == COMMAND_SUFFIX {
+            return Some(Self::Command { device });
+        }
+        SensorKind::parse(suffix).map(|sensor| Self::Reading { device, sensor })
+    }
+
+    pub fn device(&self) -> &'a str {
+        match self {
+            Self::Reading { device, .. } | Self::Command { device } => device,
+        }
+    }
+}
+
+impl fmt::Display for Topic<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Reading { device, sensor } => write!(f, "{}/{}", device, sensor.as_str()),
+            Self::Command { device } => write!(f, "{}/{}", device, COMMAND_SUFFIX),
+        }
+    }
+}
+
+// reading sent by a device, for example {"v":1,"value":-5.3,"unit":"°C","ts":1200,"up":1250,"seq":7,"fw":"0.1.0"}
+// ts and up are the device uptimes in ms when the value was read and when it was sent,
+// the board has no clock so the server derives the time of the reading from the difference
+// unknown fields are ignored, so devices can add fields without bumping the version
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Reading<'a> {
+    pub v: u8,
+    pub value: f64,
+    #[serde(borrow, default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<&'a str>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ts: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub up: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u32>,
+    #[serde(borrow, default, skip_serializing_if = "Option::is_none")]
+    pub fw: Option<&'a str>,
+}
+
+impl<'a> Reading<'a> {
+    // reading of the current version with only the value and the unit of the sensor set
+    pub fn new(sensor: SensorKind, value: f64) -> Self {
+        Self { v: PAYLOAD_VERSION, value, unit: Some(sensor.unit()), ts: None, up: None, seq: None, fw: None }
+    }
+
+    // returns the number of bytes written
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, EncodeError> {
+        serde_json_core::to_slice(self, buf)
+    }
+
+    // strings are borrowed from the payload, so escaped strings are not supported
+    pub fn decode(payload: &'a [u8]) -> Result<Self, DecodeError> {
+        serde_json_core::from_slice(payload).map(|(reading, _)| reading)
+    }
+}
+
+// commands sent to a device on its command topic, for example {"set_delay":{"sensor":"motion","secs":10}}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Command {
+    // delay between reads of a sensor, or the minimum delay between alerts of binary sensors
+    SetDelay { sensor: SensorKind, secs: u8 },
+    // enables or disables reading a sensor
+    SetEnabled { sensor: SensorKind, enabled: bool },
+    // enables or disables sending readings over mqtt
+    SetMqtt { enabled: bool },
+}
+
+impl Command {
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, EncodeError> {
+        serde_json_core::to_slice(self, buf)
+    }
+
+    pub fn decode(payload: &[u8]) -> Result<Self, DecodeError> {
+        serde_json_core::from_slice(payload).map(|(command, _)| command)
+    }
+}
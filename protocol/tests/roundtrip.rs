@@ -0,0 +1,110 @@
+use iiot_protocol::{Command, Reading, SensorKind, Topic, DEFAULT_DEVICE, PAYLOAD_LEN, PAYLOAD_VERSION, TOPIC_LEN};
+
+fn encode_reading(reading: &Reading) -> Vec<u8> {
+    let mut buf = [0u8; PAYLOAD_LEN];
+    let len = reading.encode(&mut buf).unwrap();
+    buf[..len].to_vec()
+}
+
+fn encode_command(command: &Command) -> Vec<u8> {
+    let mut buf = [0u8; PAYLOAD_LEN];
+    let len = command.encode(&mut buf).unwrap();
+    buf[..len].to_vec()
+}
+
+#[test]
+fn sensor_kinds_round_trip() {
+    for kind in SensorKind::ALL {
+        assert_eq!(SensorKind::parse(kind.as_str()), Some(kind));
+    }
+    assert_eq!(SensorKind::parse("pressure"), None);
+}
+
+#[test]
+fn topics_round_trip() {
+    for sensor in SensorKind::ALL {
+        let topic = Topic::Reading { device: DEFAULT_DEVICE, sensor };
+        assert_eq!(topic.to_string(), format!("esp32/{}", sensor.as_str()));
+        assert_eq!(Topic::parse(&topic.to_string()), Some(topic));
+    }
+    let topic = Topic::Command { device: "kitchen" };
+    assert_eq!(topic.to_string(), "kitchen/command");
+    assert_eq!(Topic::parse("kitchen/command"), Some(topic));
+    assert_eq!(Topic::parse("esp32/temperature").unwrap().device(), "esp32");
+}
+
+#[test]
+fn rejects_unknown_topics() {
+    assert_eq!(Topic::parse("esp32"), None);
+    assert_eq!(Topic::parse("/temperature"), None);
+    assert_eq!(Topic::parse("esp32/pressure"), None);
+    assert_eq!(Topic::parse("esp32/temperature/extra"), None);
+}
+
+#[test]
+fn longest_topic_fits() {
+    let topic = Topic::Reading { device: "sixteen-chars-ab", sensor: SensorKind::Temperature };
+    assert!(topic.to_string().len() <= TOPIC_LEN);
+}
+
+#[test]
+fn readings_round_trip() {
+    for sensor in SensorKind::ALL {
+        let reading = Reading { ts: Some(1200), up: Some(1250), seq: Some(7), fw: Some("0.1.0"), ..Reading::new(sensor, -5.3) };
+        let encoded = encode_reading(&reading);
+        assert_eq!(Reading::decode(&encoded).unwrap(), reading);
+    }
+    // optional fields are left out
+    let reading = Reading::new(SensorKind::Motion, 1.0);
+    let encoded = encode_reading(&reading);
+    assert_eq!(std::str::from_utf8(&encoded).unwrap(), r#"{"v":1,"value":1.0,"unit":""}"#);
+    assert_eq!(Reading::decode(&encoded).unwrap(), reading);
+}
+
+#[test]
+fn encodes_the_documented_format() {
+    let reading = Reading { ts: Some(1200), up: Some(1250), seq: Some(7), fw: Some("0.1.0"), ..Reading::new(SensorKind::Temperature, -5.3) };
+    assert_eq!(
+        std::str::from_utf8(&encode_reading(&reading)).unwrap(),
+        r#"{"v":1,"value":-5.3,"unit":"°C","ts":1200,"up":1250,"seq":7,"fw":"0.1.0"}"#
+    );
+}
+
+#[test]
+fn longest_reading_fits() {
+    let reading = Reading {
+        v: PAYLOAD_VERSION,
+        value: -3276.8,
+        unit: Some("°C"),
+        ts: Some(u64::MAX),
+        up: Some(u64::MAX),
+        seq: Some(u32::MAX),
+        fw: Some("10.10.10"),
+    };
+    assert!(reading.encode(&mut [0u8; PAYLOAD_LEN]).is_ok());
+}
+
+#[test]
+fn decodes_readings_from_other_senders() {
+    let reading = Reading::decode(br#"{ "v": 1, "value": 21, "rssi": -60 }"#).unwrap();
+    assert_eq!(reading.value, 21.0);
+    assert_eq!(reading.unit, None);
+    assert!(Reading::decode(br#"{"value":1}"#).is_err());
+    assert!(Reading::decode(br#"{"v":1,"value":"1"}"#).is_err());
+}
+
+#[test]
+fn commands_round_trip() {
+    let mut commands = vec![Command::SetMqtt { enabled: true }, Command::SetMqtt { enabled: false }];
+    for sensor in SensorKind::ALL {
+        commands.push(Command::SetDelay { sensor, secs: u8::MAX });
+        commands.push(Command::SetEnabled { sensor, enabled: false });
+    }
+    for command in commands {
+        assert_eq!(Command::decode(&encode_command(&command)).unwrap(), command);
+    }
+    assert_eq!(
+        encode_command(&Command::SetDelay { sensor: SensorKind::Motion, secs: 10 }),
+        br#"{"set_delay":{"sensor":"motion","secs":10}}"#
+    );
+}
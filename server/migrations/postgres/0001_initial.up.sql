@@ -0,0 +1,31 @@
+-- postgresql schema, kept at the same versions as the sqlite migrations
+-- times are stored as UTC timestamps without fractions, like sqlite's CURRENT_TIMESTAMP
+
+-- sqlite's datetime(text), the server stores and compares times through it so its queries run on both backends
+CREATE OR REPLACE FUNCTION datetime(text) RETURNS timestamp AS $$
+    SELECT $1::timestamp(0)
+$$ LANGUAGE sql STABLE;
+
+CREATE TABLE IF NOT EXISTS temperature (
+    id BIGSERIAL PRIMARY KEY,
+    value INTEGER NOT NULL,
+    created_at TIMESTAMP(0) DEFAULT (now() AT TIME ZONE 'utc')
+);
+
+CREATE TABLE IF NOT EXISTS humidity (
+    id BIGSERIAL PRIMARY KEY,
+    value INTEGER NOT NULL,
+    created_at TIMESTAMP(0) DEFAULT (now() AT TIME ZONE 'utc')
+);
+
+CREATE TABLE IF NOT EXISTS motion (
+    id BIGSERIAL PRIMARY KEY,
+    value INTEGER NOT NULL,
+    created_at TIMESTAMP(0) DEFAULT (now() AT TIME ZONE 'utc')
+);
+
+CREATE TABLE IF NOT EXISTS contact (
+    id BIGSERIAL PRIMARY KEY,
+    value INTEGER NOT NULL,
+    created_at TIMESTAMP(0) DEFAULT (now() AT TIME ZONE 'utc')
+);
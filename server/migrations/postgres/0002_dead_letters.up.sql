@@ -0,0 +1,8 @@
+-- messages that could not be stored, kept for inspection instead of being dropped
+CREATE TABLE IF NOT EXISTS dead_letters (
+    id BIGSERIAL PRIMARY KEY,
+    topic TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    error TEXT NOT NULL,
+    created_at TIMESTAMP(0) DEFAULT (now() AT TIME ZONE 'utc')
+);
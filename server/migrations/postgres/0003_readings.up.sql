@@ -0,0 +1,48 @@
+-- single table for every sensor, keyed by device, sensor kind and time
+-- new sensor kinds only need new rows, not new tables
+-- the time is part of the primary key, as timescaledb requires it in every unique index of a hypertable
+CREATE TABLE IF NOT EXISTS readings (
+    id BIGSERIAL,
+    device TEXT NOT NULL,
+    sensor TEXT NOT NULL,
+    value DOUBLE PRECISION NOT NULL,
+    unit TEXT NOT NULL DEFAULT '',
+    created_at TIMESTAMP(0) NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+    PRIMARY KEY (id, created_at)
+);
+
+CREATE INDEX IF NOT EXISTS readings_device_sensor_created_at ON readings (device, sensor, created_at);
+
+-- moving rows from the per-sensor tables, all of them came from the single esp32 board
+INSERT INTO readings (device, sensor, value, unit, created_at)
+SELECT 'esp32', 'temperature', value, '°C', created_at FROM temperature WHERE created_at IS NOT NULL ORDER BY id;
+INSERT INTO readings (device, sensor, value, unit, created_at)
+SELECT 'esp32', 'humidity', value, '%', created_at FROM humidity WHERE created_at IS NOT NULL ORDER BY id;
+INSERT INTO readings (device, sensor, value, unit, created_at)
+SELECT 'esp32', 'motion', value, '', created_at FROM motion WHERE created_at IS NOT NULL ORDER BY id;
+INSERT INTO readings (device, sensor, value, unit, created_at)
+SELECT 'esp32', 'contact', value, '', created_at FROM contact WHERE created_at IS NOT NULL ORDER BY id;
+
+DROP TABLE temperature;
+DROP TABLE humidity;
+DROP TABLE motion;
+DROP TABLE contact;
+
+-- compatibility views with the old table names and columns
+CREATE VIEW temperature AS SELECT id, value, created_at FROM readings WHERE sensor = 'temperature';
+CREATE VIEW humidity AS SELECT id, value, created_at FROM readings WHERE sensor = 'humidity';
+CREATE VIEW motion AS SELECT id, value, created_at FROM readings WHERE sensor = 'motion';
+CREATE VIEW contact AS SELECT id, value, created_at FROM readings WHERE sensor = 'contact';
+
+-- readings become a hypertable partitioned by time where timescaledb is installed and preloaded,
+-- plain postgresql keeps the table as it is
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM pg_available_extensions WHERE name = 'timescaledb')
+        AND current_setting('shared_preload_libraries') LIKE '%timescaledb%'
+    THEN
+        CREATE EXTENSION IF NOT EXISTS timescaledb;
+        PERFORM create_hypertable('readings', 'created_at', migrate_data => true);
+    END IF;
+END
+$$;
@@ -0,0 +1,15 @@
+-- last sequence number seen for each device and sensor, with counters to measure data loss between device and database
+-- missing / (received + missing) is the share of readings lost on the way
+CREATE TABLE IF NOT EXISTS sequence_stats (
+    device TEXT NOT NULL,
+    sensor TEXT NOT NULL,
+    last_seq BIGINT NOT NULL,
+    last_uptime_ms BIGINT,
+    received BIGINT NOT NULL DEFAULT 0,
+    missing BIGINT NOT NULL DEFAULT 0,
+    duplicates BIGINT NOT NULL DEFAULT 0,
+    replays BIGINT NOT NULL DEFAULT 0,
+    restarts BIGINT NOT NULL DEFAULT 0,
+    updated_at TIMESTAMP(0) DEFAULT (now() AT TIME ZONE 'utc'),
+    PRIMARY KEY (device, sensor)
+);
@@ -0,0 +1,4 @@
+-- time range scans across devices, as done by the fault checks, backtests and replays, and dead letters looked up by when they arrived
+CREATE INDEX IF NOT EXISTS readings_created_at ON readings (created_at);
+CREATE INDEX IF NOT EXISTS readings_sensor_created_at ON readings (sensor, created_at);
+CREATE INDEX IF NOT EXISTS dead_letters_created_at ON dead_letters (created_at);
@@ -0,0 +1,33 @@
+-- single table for every sensor, keyed by device, sensor kind and time
+-- new sensor kinds only need new rows, not new tables
+CREATE TABLE IF NOT EXISTS readings (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    device TEXT NOT NULL,
+    sensor TEXT NOT NULL,
+    value REAL NOT NULL,
+    unit TEXT NOT NULL DEFAULT '',
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS readings_device_sensor_created_at ON readings (device, sensor, created_at);
+
+-- moving rows from the per-sensor tables, all of them came from the single esp32 board
+INSERT INTO readings (device, sensor, value, unit, created_at)
+SELECT 'esp32', 'temperature', value, '°C', created_at FROM temperature ORDER BY id;
+INSERT INTO readings (device, sensor, value, unit, created_at)
+SELECT 'esp32', 'humidity', value, '%', created_at FROM humidity ORDER BY id;
+INSERT INTO readings (device, sensor, value, unit, created_at)
+SELECT 'esp32', 'motion', value, '', created_at FROM motion ORDER BY id;
+INSERT INTO readings (device, sensor, value, unit, created_at)
+SELECT 'esp32', 'contact', value, '', created_at FROM contact ORDER BY id;
+
+DROP TABLE temperature;
+DROP TABLE humidity;
+DROP TABLE motion;
+DROP TABLE contact;
+
+-- compatibility views with the old table names and columns
+CREATE VIEW temperature AS SELECT id, value, created_at FROM readings WHERE sensor = 'temperature';
+CREATE VIEW humidity AS SELECT id, value, created_at FROM readings WHERE sensor = 'humidity';
+CREATE VIEW motion AS SELECT id, value, created_at FROM readings WHERE sensor = 'motion';
+CREATE VIEW contact AS SELECT id, value, created_at FROM readings WHERE sensor = 'contact';
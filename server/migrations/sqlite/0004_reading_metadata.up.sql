@@ -0,0 +1,3 @@
+-- sequence number and firmware version sent by devices using the json payload, empty for plain values
+ALTER TABLE readings ADD COLUMN seq INTEGER;
+ALTER TABLE readings ADD COLUMN firmware TEXT;
@@ -0,0 +1,15 @@
+-- last sequence number seen for each device and sensor, with counters to measure data loss between device and database
+-- missing / (received + missing) is the share of readings lost on the way
+CREATE TABLE IF NOT EXISTS sequence_stats (
+    device TEXT NOT NULL,
+    sensor TEXT NOT NULL,
+    last_seq INTEGER NOT NULL,
+    last_uptime_ms INTEGER,
+    received INTEGER NOT NULL DEFAULT 0,
+    missing INTEGER NOT NULL DEFAULT 0,
+    duplicates INTEGER NOT NULL DEFAULT 0,
+    replays INTEGER NOT NULL DEFAULT 0,
+    restarts INTEGER NOT NULL DEFAULT 0,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (device, sensor)
+);
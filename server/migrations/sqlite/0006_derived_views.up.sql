@@ -0,0 +1,4 @@
+-- metrics derived from temperature and humidity pairs are stored in readings like the sensors, with views by name
+CREATE VIEW dew_point AS SELECT id, device, value, created_at FROM readings WHERE sensor = 'dew_point';
+CREATE VIEW heat_index AS SELECT id, device, value, created_at FROM readings WHERE sensor = 'heat_index';
+CREATE VIEW absolute_humidity AS SELECT id, device, value, created_at FROM readings WHERE sensor = 'absolute_humidity';
@@ -0,0 +1,11 @@
+-- notes written by rule scripts, kept next to the readings they are about
+CREATE TABLE IF NOT EXISTS annotations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    device TEXT NOT NULL,
+    sensor TEXT NOT NULL,
+    script TEXT NOT NULL,
+    text TEXT NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS annotations_device_created_at ON annotations (device, created_at);
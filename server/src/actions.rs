@@ -0,0 +1,144 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use chrono::NaiveTime;
+use serde::Deserialize;
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::Instant;
+
+use crate::alerts::{Alert, AlertKind};
+
+// messages waiting for the subscriber, which owns the mqtt client
+pub const OUTBOX_SIZE: usize = 100;
+
+// mqtt message published by an action
+#[derive(Debug, Clone, PartialEq)]
+pub struct Publish {
+    pub topic: String,
+    pub payload: String,
+    pub retain: bool,
+}
+
+// local time of day as ["HH:MM", "HH:MM"], wrapping around midnight when the start is after the end
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "[String; 2]")]
+pub struct TimeWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl TryFrom<[String; 2]> for TimeWindow {
+    type Error = String;
+
+    fn try_from([start, end]: [String; 2]) -> Result<Self, Self::Error> {
+        let parse = |time: &str| {
+            NaiveTime::parse_from_str(time, "%H:%M").map_err(|_| format!("'{}' is not a time of day (HH:MM)", time))
+        };
+        Ok(Self { start: parse(&start)?, end: parse(&end)? })
+    }
+}
+
+impl TimeWindow {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+pub(crate) fn default_min_interval_secs() -> u64 {
+    60
+}
+
+// publishes a message when a matching alert is raised, e.g. switching on a dehumidifier plug
+// empty `kinds`, `rules` or `devices` match everything, validation makes sure `kinds` or `rules` is set
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Action {
+    #[serde(default)]
+    pub kinds: Vec<AlertKind>,
+    // names of rules, or file names of rule scripts without the extension
+    #[serde(default)]
+    pub rules: Vec<String>,
+    #[serde(default)]
+    pub devices: Vec<String>,
+    // "{device}" in the topic and payload is replaced with the device of the alert
+    pub topic: String,
+    pub payload: String,
+    #[serde(default)]
+    pub retain: bool,
+    // the action publishes at most once within this time for each topic, alerts in between are left out
+    #[serde(default = "default_min_interval_secs")]
+    pub min_interval_secs: u64,
+    // only acts within this local time of day, e.g. ["18:00", "07:00"] for after dark
+    pub between: Option<TimeWindow>,
+}
+
+impl Action {
+    pub fn matches(&self, alert: &Alert, time: NaiveTime) -> bool {
+        (self.kinds.is_empty() || self.kinds.contains(&alert.kind))
+            && (self.rules.is_empty() || alert.rule.as_ref().is_some_and(|rule| self.rules.contains(rule)))
+            && (self.devices.is_empty() || self.devices.iter().any(|d| d == &alert.device))
+            && self.between.is_none_or(|between| between.contains(time))
+    }
+
+    pub fn publish(&self, alert: &Alert) -> Publish {
+        Publish {
+            topic: self.topic.replace("{device}", &alert.device),
+            payload: self.payload.replace("{device}", &alert.device),
+            retain: self.retain,
+        }
+    }
+}
+
+// topics actions and rule scripts may publish on, never one the server subscribes to,
+// as every message it gets back would be handled again and could trigger the same publish
+pub fn check_topic(topic: &str, subscriptions: &HashSet<String>) -> Result<(), String> {
+    if topic.is_empty() || topic.contains(['+', '#']) {
+        return Err(format!("topic '{}' must be a non-empty topic without wildcards", topic));
+    }
+    if subscriptions.contains(topic) {
+        return Err(format!("topic '{}' is subscribed to by the server", topic));
+    }
+    Ok(())
+}
+
+// hands the messages of matching actions and rule scripts over to the subscriber, at most once per interval for each
+// of them and topic; the times survive config reloads like the alert cooldowns, actions are told apart by their position
+pub struct Actions {
+    last_run: Mutex<HashMap<String, Instant>>,
+    outbox: mpsc::Sender<Publish>,
+}
+
+impl Actions {
+    pub fn new(outbox: mpsc::Sender<Publish>) -> Self {
+        Self { last_run: Mutex::new(HashMap::new()), outbox }
+    }
+
+    pub async fn run(&self, actions: &[Action], alert: &Alert, time: NaiveTime) {
+        for (i, action) in actions.iter().enumerate().filter(|(_, action)| action.matches(alert, time)) {
+            self.publish(&format!("Action #{}", i + 1), action.publish(alert), Duration::from_secs(action.min_interval_secs)).await;
+        }
+    }
+
+    // `source` names the action or script in the log and keeps its rate limit apart from the others
+    pub async fn publish(&self, source: &str, publish: Publish, min_interval: Duration) {
+        let mut times = self.last_run.lock().await;
+        let now = Instant::now();
+        let key = format!("{}/{}", source, publish.topic);
+        if let Some(last_run) = times.get(&key)
+            && now.duration_since(*last_run) < min_interval
+        {
+            println!("{} on {} is rate limited", source, publish.topic);
+            return;
+        }
+        println!("{} publishes {} on {}", source, publish.payload, publish.topic);
+        match self.outbox.try_send(publish) {
+            Ok(()) => {
+                times.insert(key, now);
+            }
+            Err(e) => eprintln!("{} was dropped: {}", source, e),
+        }
+    }
+}
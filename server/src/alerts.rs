@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use chrono::Local;
+use serde::Deserialize;
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::Instant;
+
+use crate::AppError;
+use crate::actions::{Action, Actions, Publish};
+use crate::config::{Config, CooldownConfig};
+use crate::notify::{Mailer, Notification};
+
+// kinds of alerts the server can raise, used to pick who gets notified
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertKind {
+    Motion,
+    Contact,
+    Threshold,
+    Offline,
+    Mold,
+    Condensation,
+    Anomaly,
+    Fault,
+    Rate,
+    Trend,
+    Intrusion,
+    Script,
+}
+
+impl AlertKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Motion => "motion",
+            Self::Contact => "contact",
+            Self::Threshold => "threshold",
+            Self::Offline => "offline",
+            Self::Mold => "mold",
+            Self::Condensation => "condensation",
+            Self::Anomaly => "anomaly",
+            Self::Fault => "fault",
+            Self::Rate => "rate",
+            Self::Trend => "trend",
+            Self::Intrusion => "intrusion",
+            Self::Script => "script",
+        }
+    }
+}
+
+// how urgent an alert is, routes can leave out the less urgent ones
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    #[default]
+    Normal,
+    High,
+}
+
+// single alert raised for a device, delivered according to the routing rules
+#[derive(Debug, Clone)]
+pub struct Alert {
+    pub kind: AlertKind,
+    pub severity: Severity,
+    // name of the rule or rule script that raised it, if it has one
+    pub rule: Option<String>,
+    pub device: String,
+    pub subject: String,
+    pub body: String,
+}
+
+impl Alert {
+    pub fn new(kind: AlertKind, device: &str, subject: &str, body: &str) -> Self {
+        Self {
+            kind,
+            severity: Severity::Normal,
+            rule: None,
+            device: device.to_string(),
+            subject: subject.to_string(),
+            body: body.to_string(),
+        }
+    }
+
+    pub fn with_severity(mut self, severity: Severity) -> Self {
+        self.severity = severity;
+        self
+    }
+
+    pub fn with_rule(mut self, rule: Option<&str>) -> Self {
+        self.rule = rule.map(str::to_string);
+        self
+    }
+
+    // cooldowns are tracked per device and alert kind, so a door alert does not mute motion alerts
+    pub(crate) fn cooldown_key(&self) -> String {
+        format!("{}/{}", self.device, self.kind.as_str())
+    }
+}
+
+// ways of delivering an alert
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    Email,
+    Log,
+}
+
+fn default_channels() -> Vec<Channel> {
+    vec![Channel::Email]
+}
+
+// routing rule, empty `kinds` or `devices` match everything
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Route {
+    #[serde(default)]
+    pub kinds: Vec<AlertKind>,
+    #[serde(default)]
+    pub devices: Vec<String>,
+    // alerts less urgent than this are left out
+    #[serde(default)]
+    pub min_severity: Severity,
+    #[serde(default)]
+    pub recipients: Vec<String>,
+    #[serde(default = "default_channels")]
+    pub channels: Vec<Channel>,
+}
+
+impl Route {
+    pub fn matches(&self, alert: &Alert) -> bool {
+        (self.kinds.is_empty() || self.kinds.contains(&alert.kind))
+            && (self.devices.is_empty() || self.devices.iter().any(|d| d == &alert.device))
+            && alert.severity >= self.min_severity
+    }
+}
+
+// recipients collected from all matching routes for one channel
+#[derive(Debug, PartialEq)]
+pub struct Delivery {
+    pub channel: Channel,
+    pub recipients: Vec<String>,
+}
+
+// routing and notifier settings, swapped as a whole when the config gets reloaded
+pub struct AlertSettings {
+    routes: Vec<Route>,
+    cooldowns: CooldownConfig,
+    mailer: Option<Arc<Mailer>>,
+    actions: Vec<Action>,
+}
+
+impl AlertSettings {
+    pub fn new(config: &Config) -> Result<Self, AppError> {
+        let mailer = match &config.notifiers.email {
+            Some(email) => Some(Arc::new(Mailer::new(email)?)),
+            None => None,
+        };
+        Ok(Self {
+            routes: config.routes.clone(),
+            cooldowns: config.cooldowns.clone(),
+            mailer,
+            actions: config.actions.clone(),
+        })
+    }
+
+    // merges all routes matching the alert into one delivery per channel without duplicate recipients
+    pub fn deliveries(&self, alert: &Alert) -> Vec<Delivery> {
+        let mut deliveries: Vec<Delivery> = Vec::new();
+        for route in self.routes.iter().filter(|r| r.matches(alert)) {
+            for channel in &route.channels {
+                let index = match deliveries.iter().position(|d| d.channel == *channel) {
+                    Some(index) => index,
+                    None => {
+                        deliveries.push(Delivery { channel: *channel, recipients: Vec::new() });
+                        deliveries.len() - 1
+                    }
+                };
+                let recipients = &mut deliveries[index].recipients;
+                for recipient in &route.recipients {
+                    if !recipients.contains(recipient) {
+                        recipients.push(recipient.clone());
+                    }
+                }
+            }
+        }
+        deliveries
+    }
+}
+
+// delivers alerts according to the configured routes, at most once per cooldown for each device and kind
+// cooldowns survive config reloads, only the settings get swapped
+// emails are handed over to the notification queue, so slow smtp servers do not hold up the subscriber
+// actions are not affected by cooldowns or routes, they have their own rate limits
+pub struct Alerter {
+    settings: RwLock<Arc<AlertSettings>>,
+    last_sent: Mutex<HashMap<String, Instant>>,
+    queue: mpsc::Sender<Notification>,
+    actions: Actions,
+}
+
+impl Alerter {
+    pub fn new(config: &Config, queue: mpsc::Sender<Notification>, outbox: mpsc::Sender<Publish>) -> Result<Self, AppError> {
+        Ok(Self {
+            settings: RwLock::new(Arc::new(AlertSettings::new(config)?)),
+            last_sent: Mutex::new(HashMap::new()),
+            queue,
+            actions: Actions::new(outbox),
+        })
+    }
+
+    pub fn reload(&self, settings: AlertSettings) {
+        *self.settings.write().unwrap() = Arc::new(settings);
+    }
+
+    fn settings(&self) -> Arc<AlertSettings> {
+        self.settings.read().unwrap().clone()
+    }
+
+    // messages of rule scripts, rate limited and sent like those of the actions
+    pub async fn publish(&self, source: &str, publish: Publish, min_interval: Duration) {
+        self.actions.publish(source, publish, min_interval).await;
+    }
+
+    pub async fn raise(&self, alert: &Alert) {
+        let settings = self.settings();
+        self.actions.run(&settings.actions, alert, Local::now().time()).await;
+
+        let mut times = self.last_sent.lock().await;
+        let now = Instant::now();
+        let key = alert.cooldown_key();
+        if let Some(last_sent) = times.get(&key)
+            && now.duration_since(*last_sent) < settings.cooldowns.for_kind(alert.kind)
+        {
+            println!("Cooldown active for: {}", key);
+            return;
+        }
+
+        let deliveries = settings.deliveries(alert);
+        if deliveries.is_empty() {
+            println!("No route matches alert: {}", key);
+            return;
+        }
+
+        let mut delivered = false;
+        for delivery in deliveries {
+            match delivery.channel {
+                Channel::Email => {
+                    // validation guarantees a mailer for routes using email
+                    let Some(mailer) = &settings.mailer else { continue };
+                    let notification = Notification {
+                        mailer: mailer.clone(),
+                        alert: alert.clone(),
+                        recipients: delivery.recipients,
+                    };
+                    match self.queue.send(notification).await {
+                        Ok(_) => delivered = true,
+                        Err(_) => eprintln!("Notification queue is closed, dropping email for {}", key),
+                    }
+                },
+                Channel::Log => {
+                    let marker = if alert.severity == Severity::High { " HIGH" } else { "" };
+                    println!("ALERT{} [{}] {}: {}", marker, key, alert.subject, alert.body);
+                    delivered = true;
+                },
+            }
+        }
+        if delivered {
+            times.insert(key, now);
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use std::collections::VecDeque;
+
+// rolling baseline of the last values of a series, readings are compared to its mean and standard deviation
+#[derive(Debug, Clone)]
+pub struct Baseline {
+    values: VecDeque<f64>,
+    window: usize,
+}
+
+impl Baseline {
+    pub fn new(window: usize) -> Self {
+        Self { values: VecDeque::with_capacity(window), window }
+    }
+
+    pub fn push(&mut self, value: f64) {
+        if self.values.len() == self.window {
+            self.values.pop_front();
+        }
+        self.values.push_back(value);
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.values.iter().sum::<f64>() / self.values.len() as f64
+    }
+
+    pub fn std_dev(&self) -> f64 {
+        let mean = self.mean();
+        let variance = self.values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / self.values.len() as f64;
+        variance.sqrt()
+    }
+
+    // how many standard deviations the value is from the mean, none until `min_samples` values were seen
+    // `min_std` keeps a flat series (the DHT11 reports whole numbers) from turning every change into an anomaly
+    pub fn z_score(&self, value: f64, min_samples: usize, min_std: f64) -> Option<f64> {
+        if self.values.len() < min_samples.max(1) {
+            return None;
+        }
+        Some((value - self.mean()) / self.std_dev().max(min_std))
+    }
+}
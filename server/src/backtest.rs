@@ -0,0 +1,243 @@
+// runs the alert rules of a config over the readings stored in the database, as if they were arriving again
+// the rules get the time of each reading, so every check sees the history it would have seen back then
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeDelta, Utc};
+use sqlx::AnyPool;
+
+use crate::{db, timestamp, AppError};
+use crate::alerts::{Alert, AlertKind};
+use crate::config::{Config, CooldownConfig, Metric};
+use crate::rules::{Rules, SUSTAINED_INTERVAL};
+use crate::scripts::{ScriptAction, ScriptReading, Scripts};
+
+// accepts "2025-06-01 12:00:00", "2025-06-01T12:00:00" and "2025-06-01" (midnight), in UTC like the stored readings
+pub fn parse_time(time: &str) -> Result<DateTime<Utc>, String> {
+    ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(time, format).ok())
+        .or_else(|| NaiveDate::parse_from_str(time, "%Y-%m-%d").ok()?.and_hms_opt(0, 0, 0))
+        .map(|time| time.and_utc())
+        .ok_or_else(|| format!("'{}' is not a time (YYYY-MM-DD HH:MM:SS or YYYY-MM-DD)", time))
+}
+
+// what would have happened to an alert
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Sent,
+    // suppressed by the cooldown of the device and kind, with the cooldown time that was left
+    Cooldown(TimeDelta),
+    // no route matches, so nobody would have been notified
+    Unrouted,
+}
+
+// alert the rules would have raised
+#[derive(Debug, Clone)]
+pub struct Fired {
+    pub at: DateTime<Utc>,
+    pub alert: Alert,
+    pub outcome: Outcome,
+}
+
+impl fmt::Display for Fired {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let outcome = match self.outcome {
+            Outcome::Sent => "sent".to_string(),
+            Outcome::Cooldown(left) => format!("cooldown, {}s left", left.num_seconds()),
+            Outcome::Unrouted => "no route".to_string(),
+        };
+        write!(
+            f,
+            "{}  {:<12} {:<12} {:<20} {}: {}",
+            timestamp(self.at), self.alert.kind.as_str(), self.alert.device, outcome, self.alert.subject, self.alert.body
+        )
+    }
+}
+
+// counts of a backtest by alert kind, in the order the kinds first fired
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    pub kinds: Vec<(AlertKind, Counts)>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Counts {
+    pub sent: usize,
+    pub cooldown: usize,
+    pub unrouted: usize,
+}
+
+impl Summary {
+    pub fn new(fired: &[Fired]) -> Self {
+        let mut summary = Self::default();
+        for fired in fired {
+            let index = match summary.kinds.iter().position(|(kind, _)| *kind == fired.alert.kind) {
+                Some(index) => index,
+                None => {
+                    summary.kinds.push((fired.alert.kind, Counts::default()));
+                    summary.kinds.len() - 1
+                }
+            };
+            let counts = &mut summary.kinds[index].1;
+            match fired.outcome {
+                Outcome::Sent => counts.sent += 1,
+                Outcome::Cooldown(_) => counts.cooldown += 1,
+                Outcome::Unrouted => counts.unrouted += 1,
+            }
+        }
+        summary
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.kinds.is_empty() {
+            return writeln!(f, "No alerts would have fired");
+        }
+        for (kind, counts) in &self.kinds {
+            writeln!(
+                f,
+                "{:<12} {} fired, {} sent, {} suppressed by cooldowns, {} without a route",
+                kind.as_str(), counts.sent + counts.cooldown + counts.unrouted, counts.sent, counts.cooldown, counts.unrouted
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// rule state of one backtest, fresh rules and cooldowns that run on the time of the readings instead of the clock
+// offline alerts are derived from gaps between readings, as the live check only knows the current time
+// the armed state comes from the config, messages on the armed topic are not stored
+struct Backtest<'a> {
+    config: &'a Config,
+    db_pool: &'a AnyPool,
+    device: Option<&'a str>,
+    rules: Rules,
+    scripts: Scripts,
+    cooldowns: CooldownConfig,
+    last_sent: HashMap<String, DateTime<Utc>>,
+    last_seen: BTreeMap<String, DateTime<Utc>>,
+    offline: HashSet<String>,
+    next_tick: DateTime<Utc>,
+    fired: Vec<Fired>,
+}
+
+// alerts the rules of the config would have raised for the readings stored between `from` and `to`, optionally of one device
+// readings before `from` are only used as history, e.g. for anomaly baselines and rate windows
+pub async fn run(
+    config: &Config,
+    db_pool: &AnyPool,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    device: Option<&str>,
+) -> Result<Vec<Fired>, AppError> {
+    let readings = db::readings(db_pool, from, to, device).await?;
+    // the periodic checks start with the first reading, an open range does not tick through the years before it
+    let start = readings.first().map_or(from, |reading| reading.at);
+
+    let mut backtest = Backtest {
+        config,
+        db_pool,
+        device,
+        rules: Rules::new(&config.rules),
+        scripts: Scripts::new(config)?,
+        cooldowns: config.cooldowns.clone(),
+        last_sent: HashMap::new(),
+        last_seen: BTreeMap::new(),
+        offline: HashSet::new(),
+        next_tick: start + SUSTAINED_INTERVAL,
+        fired: Vec::new(),
+    };
+    for reading in readings {
+        let Ok(metric) = Metric::try_from(reading.sensor) else { continue };
+        backtest.reading(&reading.device, metric, reading.value, reading.at).await?;
+    }
+    backtest.advance(to).await?;
+    Ok(backtest.fired)
+}
+
+impl Backtest<'_> {
+    // same checks as a stored reading goes through in handle_publish, derived metrics are stored and replayed like sensors
+    async fn reading(&mut self, device: &str, metric: Metric, value: f64, at: DateTime<Utc>) -> Result<(), AppError> {
+        self.advance(at).await?;
+        self.last_seen.insert(device.to_string(), at);
+        self.offline.remove(device);
+
+        let rules = &self.rules;
+        let mut alerts = Vec::new();
+        if let Metric::Sensor(sensor) = metric {
+            alerts.extend(rules.check_range(device, sensor, value).await);
+            if sensor.is_binary() && value == 1.0 {
+                alerts.extend(rules.check_event(device, sensor, at).await);
+            }
+        }
+        alerts.extend(rules.check_reading(device, metric, value));
+        alerts.extend(rules.check_anomaly(self.db_pool, device, metric, value, at).await?);
+        alerts.extend(rules.check_changes(self.db_pool, device, metric, at).await?);
+        let reading = ScriptReading { device, metric, value, at };
+        for (script, action) in self.scripts.run(self.db_pool, &reading).await? {
+            if let ScriptAction::Alert { subject, body, severity } = action {
+                alerts.push(Alert::new(AlertKind::Script, device, &subject, &body).with_severity(severity).with_rule(Some(&script)));
+            }
+        }
+        for alert in alerts {
+            self.raise(alert, at);
+        }
+        Ok(())
+    }
+
+    // runs the periodic checks the live server would have run until `now`
+    async fn advance(&mut self, now: DateTime<Utc>) -> Result<(), AppError> {
+        while self.next_tick <= now {
+            let tick = self.next_tick;
+            let mut alerts = self.rules.expire_events(tick).await;
+            alerts.extend(self.rules.check_sustained(self.db_pool, tick).await?);
+            alerts.extend(self.rules.check_faults(self.db_pool, tick).await?);
+            alerts.extend(self.check_offline(tick));
+            for alert in alerts {
+                self.raise(alert, tick);
+            }
+            self.next_tick += SUSTAINED_INTERVAL;
+        }
+        // held back doors expire within a second on the live server, not only on the next tick
+        for alert in self.rules.expire_events(now).await {
+            self.raise(alert, now);
+        }
+        Ok(())
+    }
+
+    fn check_offline(&mut self, now: DateTime<Utc>) -> Vec<Alert> {
+        let Some(after) = self.config.rules.offline.as_ref().map(|offline| offline.after_secs) else { return Vec::new() };
+        let mut alerts = Vec::new();
+        for (device, last_seen) in &self.last_seen {
+            if now - *last_seen >= TimeDelta::seconds(after as i64) && self.offline.insert(device.clone()) {
+                alerts.push(Alert::new(
+                    AlertKind::Offline,
+                    device,
+                    "Device offline",
+                    &format!("{} has not sent any data for {} seconds", device, after),
+                ));
+            }
+        }
+        alerts
+    }
+
+    // applies the cooldowns the way the alerter does, an alert only starts a cooldown when it is delivered
+    fn raise(&mut self, alert: Alert, at: DateTime<Utc>) {
+        // the periodic checks cover every device
+        if self.device.is_some_and(|device| device != alert.device) {
+            return;
+        }
+        let key = alert.cooldown_key();
+        let cooldown = TimeDelta::from_std(self.cooldowns.for_kind(alert.kind)).unwrap_or(TimeDelta::MAX);
+        let outcome = match self.last_sent.get(&key) {
+            Some(last_sent) if at - *last_sent < cooldown => Outcome::Cooldown(cooldown - (at - *last_sent)),
+            _ if !self.config.routes.iter().any(|route| route.matches(&alert)) => Outcome::Unrouted,
+            _ => {
+                self.last_sent.insert(key, at);
+                Outcome::Sent
+            }
+        };
+        self.fired.push(Fired { at, alert, outcome });
+    }
+}
@@ -0,0 +1,101 @@
+// publishes the traffic of a simulated esp32, so the server can be exercised without a board
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use clap::Parser;
+use rumqttc::{AsyncClient, Event, Incoming, MqttOptions, QoS};
+use tokio::sync::mpsc;
+
+use iiot_protocol::Command;
+use iiot_webserver::simulator::{tick_period, Device, Scenario};
+
+#[derive(Debug, Parser)]
+#[command(about = "Publishes simulated esp32 sensor traffic to an MQTT broker")]
+struct Args {
+    #[arg(long, default_value = "127.0.0.1")]
+    host: String,
+    #[arg(long, default_value_t = 1883)]
+    port: u16,
+    /// Scenario file with device settings and scripted steps, see scenarios/example.toml
+    #[arg(long)]
+    scenario: Option<PathBuf>,
+    /// Random motion, contact and drift on top of the scenario
+    #[arg(long)]
+    random: bool,
+    /// Seed of the random traffic, unless the scenario sets one
+    #[arg(long)]
+    seed: Option<u64>,
+    /// How many times faster than real time the simulated uptime advances
+    #[arg(long, default_value_t = 1.0)]
+    speed: f64,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    let mut scenario = match &args.scenario {
+        Some(path) => Scenario::load(path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }),
+        None => Scenario::default(),
+    };
+    if args.random && scenario.random.is_none() {
+        scenario.random = Some(Default::default());
+    }
+    let Some(period) = tick_period(args.speed) else {
+        eprintln!("--speed has to be a positive number, small enough to leave each tick some time");
+        std::process::exit(1);
+    };
+    let seed = args.seed.unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64);
+    let mut device = Device::new(&scenario, seed);
+
+    let client_id = format!("iiot-simulator-{}", scenario.device.name);
+    let mut options = MqttOptions::new(client_id, &args.host, args.port);
+    options.set_keep_alive(Duration::from_secs(5));
+    let (client, mut event_loop) = AsyncClient::new(options, 100);
+    // commands are applied between ticks, subscribing again after every reconnect like the firmware
+    let (commands, mut received) = mpsc::unbounded_channel();
+    let command_topic = device.command_topic();
+    let subscriber = client.clone();
+    tokio::spawn(async move {
+        loop {
+            match event_loop.poll().await {
+                Ok(Event::Incoming(Incoming::ConnAck(_))) => {
+                    if let Err(e) = subscriber.subscribe(&command_topic, QoS::AtLeastOnce).await {
+                        eprintln!("Failed to subscribe to {}: {}", command_topic, e);
+                    }
+                }
+                Ok(Event::Incoming(Incoming::Publish(publish))) => match Command::decode(&publish.payload) {
+                    Ok(command) => {
+                        let _ = commands.send(command);
+                    }
+                    Err(e) => eprintln!("Invalid command on {}: {}", publish.topic, e),
+                },
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("MQTT error: {}", e);
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    });
+
+    println!("Simulating {} against {}:{} at {}x speed", scenario.device.name, args.host, args.port, args.speed);
+    let mut ticks = tokio::time::interval(period);
+    while !device.finished() {
+        ticks.tick().await;
+        while let Ok(command) = received.try_recv() {
+            println!("[{:>8.1}s] command {:?}", device.uptime_ms() as f64 / 1000.0, command);
+            device.command(command);
+        }
+        for message in device.tick() {
+            println!("[{:>8.1}s] {} {}", device.uptime_ms() as f64 / 1000.0, message.topic, message.payload);
+            if let Err(e) = client.publish(message.topic, QoS::AtLeastOnce, false, message.payload).await {
+                eprintln!("Failed to publish: {}", e);
+            }
+        }
+    }
+    // giving the event loop time to send what is still queued
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let _ = client.disconnect().await;
+}
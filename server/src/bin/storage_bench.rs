@@ -0,0 +1,105 @@
+// measures how many readings per second sqlite takes, the way the server stored them before and after the wal and write queue changes
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use chrono::Utc;
+use clap::Parser;
+use sqlx::AnyPool;
+use sqlx::any::AnyPoolOptions;
+
+use iiot_webserver::{db, timestamp};
+use iiot_webserver::config::DatabaseConfig;
+use iiot_webserver::writer::{Write, Writer};
+
+#[derive(Debug, Parser)]
+#[command(about = "Compares sqlite write throughput with and without wal and the batched write queue")]
+struct Args {
+    /// Readings stored in every mode
+    #[arg(long, default_value_t = 5000)]
+    readings: usize,
+    /// Directory of the scratch databases, on the disk the server would use
+    #[arg(long, default_value_os_t = std::env::temp_dir())]
+    dir: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Mode {
+    // one autocommit insert per reading with a rollback journal, as the server stored them before
+    Journal,
+    Wal,
+    WalQueue,
+}
+
+impl Mode {
+    fn describe(self) -> &'static str {
+        match self {
+            Self::Journal => "autocommit, rollback journal",
+            Self::Wal => "autocommit, wal",
+            Self::WalQueue => "write queue, wal",
+        }
+    }
+}
+
+fn reading(i: usize) -> Write {
+    Write::Reading {
+        device: format!("esp32-{}", i % 10),
+        sensor: "temperature".to_string(),
+        value: 20.0 + (i % 50) as f64 / 10.0,
+        unit: "°C".to_string(),
+        seq: Some(i as i64),
+        firmware: None,
+        created_at: timestamp(Utc::now()),
+    }
+}
+
+async fn store(pool: &AnyPool, mode: Mode, readings: usize) -> Duration {
+    let started = Instant::now();
+    match mode {
+        Mode::Journal | Mode::Wal => {
+            for i in 0..readings {
+                reading(i).execute(pool).await.expect("Insert failed");
+            }
+        }
+        Mode::WalQueue => {
+            let (writer, task) = Writer::start(pool.clone(), &DatabaseConfig::default());
+            for i in 0..readings {
+                writer.write(reading(i)).await;
+            }
+            drop(writer);
+            task.await.expect("Writer task failed");
+        }
+    }
+    started.elapsed()
+}
+
+fn remove(path: &Path) {
+    for suffix in ["", "-wal", "-shm", "-journal"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    println!("Storing {} readings in {}", args.readings, args.dir.display());
+    let mut baseline = None;
+    for mode in [Mode::Journal, Mode::Wal, Mode::WalQueue] {
+        let path = args.dir.join(format!("iiot-bench-{}.db", std::process::id()));
+        remove(&path);
+        let options = AnyPoolOptions::new().max_connections(1);
+        let pool = db::connect_with(options, &format!("sqlite://{}?mode=rwc", path.display())).await.expect("Database connection failed");
+        if let Mode::Journal = mode {
+            // the single connection is the one every insert goes through
+            sqlx::raw_sql("PRAGMA journal_mode = DELETE; PRAGMA synchronous = FULL;").execute(&pool).await.expect("Failed to set pragmas");
+        }
+
+        let elapsed = store(&pool, mode, args.readings).await;
+        let stored: i64 = sqlx::query_scalar("select count(*) from readings").fetch_one(&pool).await.expect("Count failed");
+        pool.close().await;
+        remove(&path);
+
+        let rate = stored as f64 / elapsed.as_secs_f64();
+        let speedup = baseline.map(|base| format!(" ({:.1}x)", rate / base)).unwrap_or_default();
+        baseline.get_or_insert(rate);
+        println!("{:<30} {:>10.0} readings/s{}", mode.describe(), rate, speedup);
+    }
+}
@@ -0,0 +1,908 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use lettre::message::Mailbox;
+use serde::Deserialize;
+use thiserror::Error;
+
+use iiot_protocol::{Topic, DEFAULT_DEVICE};
+
+use crate::actions::{check_topic, default_min_interval_secs, Action};
+use crate::alerts::{AlertKind, Channel, Route, Severity};
+use crate::db::Backend;
+use crate::derived::DerivedKind;
+use crate::sinks::Buffering;
+use crate::writer::Batching;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Cannot read config file {0}: {1}")]
+    Read(PathBuf, std::io::Error),
+    #[error("Cannot parse config file {0}: {1}")]
+    Parse(PathBuf, toml::de::Error),
+    #[error("Invalid configuration:\n  - {}", .0.join("\n  - "))]
+    Invalid(Vec<String>),
+}
+
+// sensors the server knows how to store, each subscribed topic maps to one of them
+// shared with the firmware through the protocol crate
+pub use iiot_protocol::SensorKind;
+
+// what a rule looks at, either a sensor or a metric derived from temperature and humidity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Metric {
+    Sensor(SensorKind),
+    Derived(DerivedKind),
+}
+
+impl Metric {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Sensor(sensor) => sensor.as_str(),
+            Self::Derived(kind) => kind.as_str(),
+        }
+    }
+
+    pub fn unit(&self) -> &'static str {
+        match self {
+            Self::Sensor(sensor) => sensor.unit(),
+            Self::Derived(kind) => kind.unit(),
+        }
+    }
+
+    pub fn all() -> impl Iterator<Item = Metric> {
+        SensorKind::ALL.into_iter().map(Self::Sensor).chain(DerivedKind::ALL.into_iter().map(Self::Derived))
+    }
+}
+
+impl TryFrom<String> for Metric {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        Self::all().find(|metric| metric.as_str() == name).ok_or_else(|| {
+            let names: Vec<&str> = Self::all().map(|metric| metric.as_str()).collect();
+            format!("unknown sensor `{}`, expected one of {}", name, names.join(", "))
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub keep_alive_secs: u64,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            host: String::new(),
+            port: 1883,
+            client_id: "rust-mqtt-subscriber".to_string(),
+            keep_alive_secs: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DatabaseConfig {
+    #[serde(default)]
+    pub url: String,
+    // rows stored per transaction by the write queue
+    #[serde(default = "default_write_batch_size")]
+    pub batch_size: usize,
+    // how long a row may wait for the batch to fill up, readings reach the periodic checks that much later
+    #[serde(default = "default_flush_ms")]
+    pub flush_ms: u64,
+}
+
+fn default_write_batch_size() -> usize {
+    200
+}
+
+fn default_flush_ms() -> u64 {
+    100
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self { url: String::new(), batch_size: default_write_batch_size(), flush_ms: default_flush_ms() }
+    }
+}
+
+impl DatabaseConfig {
+    pub fn batching(&self) -> Batching {
+        Batching { batch_size: self.batch_size, flush: Duration::from_millis(self.flush_ms) }
+    }
+}
+
+// how the connection to the smtp server is secured
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    #[default]
+    Tls,
+    StartTls,
+    None,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmailConfig {
+    pub smtp_host: String,
+    // defaults to the standard port of the chosen security mode
+    pub smtp_port: Option<u16>,
+    pub security: SmtpSecurity,
+    pub username: String,
+    pub password: String,
+    // sender address, defaults to the username
+    pub from: Option<String>,
+}
+
+impl Default for EmailConfig {
+    fn default() -> Self {
+        Self {
+            smtp_host: "smtp.gmail.com".to_string(),
+            smtp_port: None,
+            security: SmtpSecurity::Tls,
+            username: String::new(),
+            password: String::new(),
+            from: None,
+        }
+    }
+}
+
+impl EmailConfig {
+    pub fn sender(&self) -> &str {
+        self.from.as_deref().unwrap_or(&self.username)
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotifiersConfig {
+    pub email: Option<EmailConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TopicConfig {
+    pub topic: String,
+    pub sensor: SensorKind,
+    // defaults to the first segment of the topic
+    pub device: Option<String>,
+}
+
+impl TopicConfig {
+    pub fn device(&self) -> &str {
+        match &self.device {
+            Some(device) => device,
+            None => self.topic.split('/').next().unwrap_or_default(),
+        }
+    }
+}
+
+fn default_topics() -> Vec<TopicConfig> {
+    SensorKind::ALL
+        .into_iter()
+        .map(|sensor| TopicConfig { topic: Topic::Reading { device: DEFAULT_DEVICE, sensor }.to_string(), sensor, device: None })
+        .collect()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CooldownConfig {
+    pub default_secs: u64,
+    // per alert kind overrides of the default
+    pub kinds: HashMap<AlertKind, u64>,
+}
+
+impl Default for CooldownConfig {
+    fn default() -> Self {
+        Self { default_secs: 600, kinds: HashMap::new() }
+    }
+}
+
+impl CooldownConfig {
+    pub fn for_kind(&self, kind: AlertKind) -> Duration {
+        Duration::from_secs(*self.kinds.get(&kind).unwrap_or(&self.default_secs))
+    }
+}
+
+// raises a threshold alert when a reading goes above or below the limits
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ThresholdRule {
+    // lets actions refer to the alerts of the rule, see [[action]]
+    pub name: Option<String>,
+    // a sensor, or one of dew_point, heat_index and absolute_humidity
+    pub sensor: Metric,
+    // limits the rule to a single device
+    pub device: Option<String>,
+    pub above: Option<f64>,
+    pub below: Option<f64>,
+}
+
+fn default_sustained_alert() -> AlertKind {
+    AlertKind::Mold
+}
+
+// raises a mold or condensation alert when readings stayed outside the limits for long enough within a rolling window,
+// e.g. humidity above 70% for more than 6 hours in the last 24
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SustainedRule {
+    pub name: Option<String>,
+    #[serde(default = "default_sustained_alert")]
+    pub alert: AlertKind,
+    pub sensor: Metric,
+    pub device: Option<String>,
+    pub above: Option<f64>,
+    pub below: Option<f64>,
+    // time outside the limits needed within the window
+    pub min_secs: u64,
+    pub window_secs: u64,
+}
+
+// raises a rate alert when a reading rose or fell by more than the limit within the window,
+// e.g. temperature up by more than 3°C in 10 minutes
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateRule {
+    pub name: Option<String>,
+    pub sensor: Metric,
+    pub device: Option<String>,
+    // change from the lowest (rise) or highest (fall) reading in the window
+    pub rise: Option<f64>,
+    pub fall: Option<f64>,
+    pub window_secs: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Rising,
+    Falling,
+}
+
+impl Direction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Rising => "rising",
+            Self::Falling => "falling",
+        }
+    }
+}
+
+fn default_min_fit() -> f64 {
+    0.8
+}
+
+// raises a trend alert when the readings of the whole window lie close to a rising or falling line,
+// e.g. humidity rising steadily for 2 hours
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TrendRule {
+    pub name: Option<String>,
+    pub sensor: Metric,
+    pub device: Option<String>,
+    pub direction: Direction,
+    pub window_secs: u64,
+    // smallest change over the window along the line, in the unit of the sensor
+    #[serde(default)]
+    pub min_change: f64,
+    // how closely the readings have to follow the line (r², 0 to 1)
+    #[serde(default = "default_min_fit")]
+    pub min_fit: f64,
+}
+
+fn default_z_score() -> f64 {
+    4.0
+}
+
+fn default_anomaly_window() -> usize {
+    120
+}
+
+fn default_min_samples() -> usize {
+    30
+}
+
+fn default_min_std() -> f64 {
+    0.5
+}
+
+// raises an anomaly alert when a reading is further from the rolling mean of its series than `z_score` standard deviations
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AnomalyRule {
+    pub name: Option<String>,
+    pub sensor: Metric,
+    pub device: Option<String>,
+    // sensitivity, lower values flag smaller deviations
+    #[serde(default = "default_z_score")]
+    pub z_score: f64,
+    // number of previous readings the baseline is learned from
+    #[serde(default = "default_anomaly_window")]
+    pub window: usize,
+    // readings needed before anything is flagged
+    #[serde(default = "default_min_samples")]
+    pub min_samples: usize,
+    // lower bound of the standard deviation, in the unit of the sensor
+    #[serde(default = "default_min_std")]
+    pub min_std: f64,
+}
+
+// raises an offline alert when a device has not sent anything for a while
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OfflineRule {
+    pub after_secs: u64,
+}
+
+fn default_history_secs() -> u64 {
+    3600
+}
+
+fn default_max_operations() -> u64 {
+    100_000
+}
+
+// user rule script, run for every stored reading it applies to, see scripts.rs for what a script can do
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptRule {
+    // relative to the working directory, like the config file
+    pub path: PathBuf,
+    pub device: Option<String>,
+    // metrics the script runs for, empty for all of them
+    #[serde(default)]
+    pub sensors: Vec<Metric>,
+    // how far back the readings in `history` go
+    #[serde(default = "default_history_secs")]
+    pub history_secs: u64,
+    // a script running longer than this is stopped, which also ends endless loops
+    #[serde(default = "default_max_operations")]
+    pub max_operations: u64,
+    // publishes at most once within this time for each topic, like actions
+    #[serde(default = "default_min_interval_secs")]
+    pub min_interval_secs: u64,
+}
+
+fn default_entry_secs() -> u64 {
+    30
+}
+
+fn default_presence_secs() -> u64 {
+    600
+}
+
+// correlates door and motion events while armed, raising one intrusion alert instead of separate contact and motion alerts:
+// - a door opened, then motion within `entry_secs`, is a probable intrusion
+// - motion without any door opened in the last `presence_secs` means someone was already inside
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IntrusionRule {
+    #[serde(default)]
+    pub armed: bool,
+    // retained "1"/"0" (or on/off, true/false) on this topic arms and disarms, overriding `armed`
+    pub armed_topic: Option<String>,
+    // devices watching the same place, events of all of them are correlated, empty for every device
+    #[serde(default)]
+    pub devices: Vec<String>,
+    #[serde(default = "default_entry_secs")]
+    pub entry_secs: u64,
+    #[serde(default = "default_presence_secs")]
+    pub presence_secs: u64,
+}
+
+// measuring range of the DHT11
+fn default_temperature_range() -> [f64; 2] {
+    [0.0, 50.0]
+}
+
+fn default_humidity_range() -> [f64; 2] {
+    [20.0, 90.0]
+}
+
+// raises sensor fault alerts for readings that cannot be trusted, the checks without a duration are off by default
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FaultRule {
+    pub device: Option<String>,
+    // values outside the measuring range of the sensor
+    #[serde(default = "default_temperature_range")]
+    pub temperature_range: [f64; 2],
+    #[serde(default = "default_humidity_range")]
+    pub humidity_range: [f64; 2],
+    // temperature or humidity that did not change at all for this long
+    pub flatline_secs: Option<u64>,
+    // no temperature or humidity for this long while other sensors of the device keep sending,
+    // which is what repeated dht read errors look like
+    pub silent_secs: Option<u64>,
+    // no motion detected for this long on a device that sends motion readings
+    pub no_motion_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RulesConfig {
+    pub threshold: Vec<ThresholdRule>,
+    pub sustained: Vec<SustainedRule>,
+    pub anomaly: Vec<AnomalyRule>,
+    pub rate: Vec<RateRule>,
+    pub trend: Vec<TrendRule>,
+    pub offline: Option<OfflineRule>,
+    pub fault: Option<FaultRule>,
+    pub intrusion: Option<IntrusionRule>,
+    pub script: Vec<ScriptRule>,
+}
+
+fn default_measurement() -> String {
+    "readings".to_string()
+}
+
+fn default_batch_size() -> usize {
+    500
+}
+
+fn default_flush_secs() -> u64 {
+    5
+}
+
+fn default_buffer_size() -> usize {
+    100_000
+}
+
+fn default_retry_secs() -> u64 {
+    10
+}
+
+fn default_timeout_secs() -> u64 {
+    10
+}
+
+// forwards every stored reading to influxdb (or any backend accepting its line protocol over http)
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InfluxConfig {
+    // write endpoint, e.g. http://localhost:8086/api/v2/write?org=home&bucket=iiot or http://localhost:8086/write?db=iiot
+    pub url: String,
+    // sent as "Authorization: Token ...", for influxdb 1.x "user:password"
+    pub token: Option<String>,
+    #[serde(default = "default_measurement")]
+    pub measurement: String,
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    // how long readings may wait for a batch to fill up
+    #[serde(default = "default_flush_secs")]
+    pub flush_secs: u64,
+    // readings kept while the backend is down, the oldest are dropped beyond this
+    #[serde(default = "default_buffer_size")]
+    pub buffer_size: usize,
+    #[serde(default = "default_retry_secs")]
+    pub retry_secs: u64,
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+impl InfluxConfig {
+    pub fn buffering(&self) -> Buffering {
+        Buffering {
+            batch_size: self.batch_size,
+            flush: Duration::from_secs(self.flush_secs),
+            capacity: self.buffer_size,
+            retry: Duration::from_secs(self.retry_secs),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SinksConfig {
+    pub influxdb: Option<InfluxConfig>,
+}
+
+fn default_discovery_prefix() -> String {
+    "homeassistant".to_string()
+}
+
+fn default_state_prefix() -> String {
+    "iiot".to_string()
+}
+
+// publishes home assistant discovery configs for the configured sensors, and their states in the format it expects
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HomeAssistantConfig {
+    // has to match the discovery prefix set in home assistant
+    #[serde(default = "default_discovery_prefix")]
+    pub discovery_prefix: String,
+    // states are published on {state_prefix}/{device}/{sensor}
+    #[serde(default = "default_state_prefix")]
+    pub state_prefix: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownConfig {
+    // how long pending database writes, emails and the broker disconnect may take after SIGINT/SIGTERM
+    pub timeout_secs: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self { timeout_secs: 10 }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub mqtt: MqttConfig,
+    pub database: DatabaseConfig,
+    pub notifiers: NotifiersConfig,
+    pub topics: Vec<TopicConfig>,
+    pub cooldowns: CooldownConfig,
+    #[serde(rename = "route")]
+    pub routes: Vec<Route>,
+    pub rules: RulesConfig,
+    #[serde(rename = "action")]
+    pub actions: Vec<Action>,
+    pub home_assistant: Option<HomeAssistantConfig>,
+    pub sinks: SinksConfig,
+    pub shutdown: ShutdownConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            mqtt: MqttConfig::default(),
+            database: DatabaseConfig::default(),
+            notifiers: NotifiersConfig::default(),
+            topics: default_topics(),
+            cooldowns: CooldownConfig::default(),
+            routes: Vec::new(),
+            rules: RulesConfig::default(),
+            actions: Vec::new(),
+            home_assistant: None,
+            sinks: SinksConfig::default(),
+            shutdown: ShutdownConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    // loads the config file from IIOT_CONFIG (config.toml by default), applies env overrides and validates it
+    // a missing file is allowed, so a deployment can still be configured from .env alone
+    pub fn load() -> Result<Self, ConfigError> {
+        Self::load_from(&Self::path(), |name| std::env::var(name).ok())
+    }
+
+    pub fn path() -> PathBuf {
+        std::env::var("IIOT_CONFIG").unwrap_or_else(|_| "config.toml".to_string()).into()
+    }
+
+    pub fn load_from(path: &Path, env: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        if !path.exists() {
+            println!("No config file at {}, using defaults and environment", path.display());
+            return Self::default().finish(env);
+        }
+        Self::load_existing(path, env)
+    }
+
+    // like `load_from`, but the file has to be there, so a reload never falls back to the defaults
+    // when the file was deleted or is caught in the middle of being replaced
+    pub fn load_existing(path: &Path, env: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let content = std::fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+        Self::parse(&content).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))?.finish(env)
+    }
+
+    fn finish(mut self, env: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let mut errors = self.apply_env(env);
+        errors.extend(self.validate());
+        if errors.is_empty() {
+            Ok(self)
+        } else {
+            Err(ConfigError::Invalid(errors))
+        }
+    }
+
+    pub fn parse(content: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(content)
+    }
+
+    // environment variables take precedence over the file, returns errors for malformed values
+    fn apply_env(&mut self, env: impl Fn(&str) -> Option<String>) -> Vec<String> {
+        let mut errors = Vec::new();
+        if let Some(url) = env("DATABASE_URL") {
+            self.database.url = url;
+        }
+        if let Some(host) = env("MQTT_HOST") {
+            self.mqtt.host = host;
+        }
+        if let Some(port) = env("MQTT_PORT") {
+            match port.parse() {
+                Ok(port) => self.mqtt.port = port,
+                Err(_) => errors.push(format!("MQTT_PORT: '{}' is not a valid port", port)),
+            }
+        }
+        let username = env("EMAIL_USERNAME");
+        let password = env("EMAIL_PASSWORD");
+        if username.is_some() || password.is_some() {
+            let email = self.notifiers.email.get_or_insert_with(EmailConfig::default);
+            if let Some(username) = username {
+                email.username = username;
+            }
+            if let Some(password) = password {
+                email.password = password;
+            }
+        }
+        // keeps the old single recipient setup working when no routes are configured
+        if let Some(recipient) = env("EMAIL_RECIPIENT")
+            && self.routes.is_empty()
+        {
+            self.routes.push(Route {
+                kinds: Vec::new(),
+                devices: Vec::new(),
+                min_severity: Severity::Normal,
+                recipients: vec![recipient],
+                channels: vec![Channel::Email],
+            });
+        }
+        errors
+    }
+
+    // checks everything that would otherwise fail later, collecting all problems at once
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if self.mqtt.host.is_empty() {
+            errors.push("mqtt.host is not set (config file or MQTT_HOST)".to_string());
+        }
+        if self.mqtt.port == 0 {
+            errors.push("mqtt.port must not be 0".to_string());
+        }
+        if self.mqtt.client_id.is_empty() {
+            errors.push("mqtt.client_id must not be empty".to_string());
+        }
+        if self.mqtt.keep_alive_secs < 5 {
+            errors.push("mqtt.keep_alive_secs must be at least 5".to_string());
+        }
+        if self.database.url.is_empty() {
+            errors.push("database.url is not set (config file or DATABASE_URL)".to_string());
+        } else if Backend::from_url(&self.database.url).is_none() {
+            errors.push(format!("database.url '{}' is neither an sqlite nor a postgres url", self.database.url));
+        }
+        if self.database.batch_size == 0 {
+            errors.push("database.batch_size must be at least 1".to_string());
+        }
+        if self.database.flush_ms == 0 {
+            errors.push("database.flush_ms must be greater than 0".to_string());
+        }
+
+        if let Some(email) = &self.notifiers.email {
+            if email.smtp_host.is_empty() {
+                errors.push("notifiers.email.smtp_host must not be empty".to_string());
+            }
+            if email.sender().parse::<Mailbox>().is_err() {
+                errors.push(format!("notifiers.email: sender '{}' is not a valid address", email.sender()));
+            }
+            if email.username.is_empty() != email.password.is_empty() {
+                errors.push("notifiers.email: username and password must be set together".to_string());
+            }
+        }
+
+        if self.topics.is_empty() {
+            errors.push("no topics configured".to_string());
+        }
+        let mut topics = HashSet::new();
+        for topic in &self.topics {
+            if topic.topic.is_empty() || topic.topic.contains(['+', '#']) {
+                errors.push(format!("topic '{}' must be a non-empty topic without wildcards", topic.topic));
+            }
+            if !topics.insert(&topic.topic) {
+                errors.push(format!("topic '{}' is configured more than once", topic.topic));
+            }
+        }
+
+        if self.routes.is_empty() {
+            errors.push("no alert routes configured ([[route]] or EMAIL_RECIPIENT)".to_string());
+        }
+        for (i, route) in self.routes.iter().enumerate() {
+            let name = format!("route #{}", i + 1);
+            if route.channels.is_empty() {
+                errors.push(format!("{}: no channels", name));
+            }
+            if route.channels.contains(&Channel::Email) {
+                if self.notifiers.email.is_none() {
+                    errors.push(format!("{}: uses email but notifiers.email is not configured", name));
+                }
+                if route.recipients.is_empty() {
+                    errors.push(format!("{}: uses email but has no recipients", name));
+                }
+            }
+            for recipient in &route.recipients {
+                if recipient.parse::<Mailbox>().is_err() {
+                    errors.push(format!("{}: recipient '{}' is not a valid address", name, recipient));
+                }
+            }
+        }
+
+        for (i, rule) in self.rules.threshold.iter().enumerate() {
+            let name = format!("rules.threshold #{}", i + 1);
+            if let Metric::Sensor(sensor) = rule.sensor && sensor.is_binary() {
+                errors.push(format!("{}: thresholds do not apply to motion and contact", name));
+            }
+            match (rule.above, rule.below) {
+                (None, None) => errors.push(format!("{}: needs `above`, `below` or both", name)),
+                (Some(above), Some(below)) if below >= above => {
+                    errors.push(format!("{}: `below` ({}) must be lower than `above` ({})", name, below, above))
+                },
+                _ => {}
+            }
+        }
+        for (i, rule) in self.rules.sustained.iter().enumerate() {
+            let name = format!("rules.sustained #{}", i + 1);
+            if !matches!(rule.alert, AlertKind::Mold | AlertKind::Condensation) {
+                errors.push(format!("{}: `alert` must be mold or condensation", name));
+            }
+            if let Metric::Sensor(sensor) = rule.sensor && sensor.is_binary() {
+                errors.push(format!("{}: sustained rules do not apply to motion and contact", name));
+            }
+            match (rule.above, rule.below) {
+                (None, None) => errors.push(format!("{}: needs `above`, `below` or both", name)),
+                (Some(above), Some(below)) if below >= above => {
+                    errors.push(format!("{}: `below` ({}) must be lower than `above` ({})", name, below, above))
+                },
+                _ => {}
+            }
+            if rule.min_secs == 0 || rule.min_secs > rule.window_secs {
+                errors.push(format!("{}: `min_secs` must be greater than 0 and at most `window_secs`", name));
+            }
+        }
+        for (i, rule) in self.rules.rate.iter().enumerate() {
+            let name = format!("rules.rate #{}", i + 1);
+            if let Metric::Sensor(sensor) = rule.sensor && sensor.is_binary() {
+                errors.push(format!("{}: rate rules do not apply to motion and contact", name));
+            }
+            if rule.rise.is_none() && rule.fall.is_none() {
+                errors.push(format!("{}: needs `rise`, `fall` or both", name));
+            }
+            if rule.rise.is_some_and(|rise| rise <= 0.0) || rule.fall.is_some_and(|fall| fall <= 0.0) {
+                errors.push(format!("{}: `rise` and `fall` must be greater than 0", name));
+            }
+            if rule.window_secs == 0 {
+                errors.push(format!("{}: `window_secs` must be greater than 0", name));
+            }
+        }
+        for (i, rule) in self.rules.trend.iter().enumerate() {
+            let name = format!("rules.trend #{}", i + 1);
+            if let Metric::Sensor(sensor) = rule.sensor && sensor.is_binary() {
+                errors.push(format!("{}: trend rules do not apply to motion and contact", name));
+            }
+            if rule.window_secs == 0 {
+                errors.push(format!("{}: `window_secs` must be greater than 0", name));
+            }
+            if rule.min_change < 0.0 {
+                errors.push(format!("{}: `min_change` cannot be negative", name));
+            }
+            if !(0.0..=1.0).contains(&rule.min_fit) {
+                errors.push(format!("{}: `min_fit` must be between 0 and 1", name));
+            }
+        }
+        for (i, rule) in self.rules.anomaly.iter().enumerate() {
+            let name = format!("rules.anomaly #{}", i + 1);
+            if let Metric::Sensor(sensor) = rule.sensor && sensor.is_binary() {
+                errors.push(format!("{}: anomaly rules do not apply to motion and contact", name));
+            }
+            if rule.z_score <= 0.0 {
+                errors.push(format!("{}: `z_score` must be greater than 0", name));
+            }
+            if rule.min_samples < 2 || rule.min_samples > rule.window {
+                errors.push(format!("{}: `min_samples` must be at least 2 and at most `window`", name));
+            }
+            if rule.min_std < 0.0 {
+                errors.push(format!("{}: `min_std` cannot be negative", name));
+            }
+        }
+        for (i, rule) in self.rules.script.iter().enumerate() {
+            let name = format!("rules.script #{}", i + 1);
+            if rule.history_secs == 0 || rule.max_operations == 0 {
+                errors.push(format!("{}: `history_secs` and `max_operations` must be greater than 0", name));
+            }
+        }
+        if let Some(intrusion) = &self.rules.intrusion {
+            if intrusion.entry_secs == 0 || intrusion.presence_secs < intrusion.entry_secs {
+                errors.push("rules.intrusion: `entry_secs` must be greater than 0 and at most `presence_secs`".to_string());
+            }
+            if let Some(topic) = &intrusion.armed_topic && self.topic(topic).is_some() {
+                errors.push(format!("rules.intrusion: armed_topic '{}' is also a sensor topic", topic));
+            }
+        }
+        if let Some(fault) = &self.rules.fault {
+            for (name, [low, high]) in [("temperature_range", fault.temperature_range), ("humidity_range", fault.humidity_range)] {
+                if low >= high {
+                    errors.push(format!("rules.fault.{}: the lower bound must be below the upper bound", name));
+                }
+            }
+            for (name, secs) in [("flatline_secs", fault.flatline_secs), ("silent_secs", fault.silent_secs), ("no_motion_secs", fault.no_motion_secs)] {
+                if secs == Some(0) {
+                    errors.push(format!("rules.fault.{} must be greater than 0", name));
+                }
+            }
+        }
+        if let Some(offline) = &self.rules.offline
+            && offline.after_secs == 0
+        {
+            errors.push("rules.offline.after_secs must be greater than 0".to_string());
+        }
+        let rule_names: HashSet<&str> = self.rule_names().collect();
+        let subscriptions = self.subscriptions();
+        for (i, action) in self.actions.iter().enumerate() {
+            let name = format!("action #{}", i + 1);
+            if action.kinds.is_empty() && action.rules.is_empty() {
+                errors.push(format!("{}: needs `kinds`, `rules` or both", name));
+            }
+            for rule in action.rules.iter().filter(|rule| !rule_names.contains(rule.as_str())) {
+                errors.push(format!("{}: no rule or rule script named '{}'", name, rule));
+            }
+            if let Err(e) = check_topic(&action.topic, &subscriptions) {
+                errors.push(format!("{}: {}", name, e));
+            }
+        }
+        if let Some(home_assistant) = &self.home_assistant {
+            for (name, prefix) in [("discovery_prefix", &home_assistant.discovery_prefix), ("state_prefix", &home_assistant.state_prefix)] {
+                if prefix.is_empty() || prefix.contains(['+', '#']) {
+                    errors.push(format!("home_assistant.{} '{}' must be a non-empty topic without wildcards", name, prefix));
+                }
+            }
+        }
+        if let Some(influx) = &self.sinks.influxdb {
+            if !influx.url.starts_with("http://") && !influx.url.starts_with("https://") {
+                errors.push(format!("sinks.influxdb.url '{}' is not an http(s) url", influx.url));
+            }
+            if influx.batch_size == 0 || influx.buffer_size < influx.batch_size {
+                errors.push("sinks.influxdb: `batch_size` must be greater than 0 and at most `buffer_size`".to_string());
+            }
+            if influx.flush_secs == 0 || influx.retry_secs == 0 || influx.timeout_secs == 0 {
+                errors.push("sinks.influxdb: `flush_secs`, `retry_secs` and `timeout_secs` must be greater than 0".to_string());
+            }
+        }
+        if self.shutdown.timeout_secs == 0 {
+            errors.push("shutdown.timeout_secs must be greater than 0".to_string());
+        }
+
+        errors
+    }
+
+    pub fn topic(&self, topic: &str) -> Option<&TopicConfig> {
+        self.topics.iter().find(|t| t.topic == topic)
+    }
+
+    // topics the subscriber listens to, the sensor topics and the armed state
+    pub fn subscriptions(&self) -> HashSet<String> {
+        let armed_topic = self.rules.intrusion.as_ref().and_then(|i| i.armed_topic.clone());
+        self.topics.iter().map(|t| t.topic.clone()).chain(armed_topic).collect()
+    }
+
+    // names actions can refer to, of the named rules and of the rule scripts
+    fn rule_names(&self) -> impl Iterator<Item = &str> {
+        let rules = &self.rules;
+        rules.threshold.iter().map(|r| &r.name)
+            .chain(rules.sustained.iter().map(|r| &r.name))
+            .chain(rules.anomaly.iter().map(|r| &r.name))
+            .chain(rules.rate.iter().map(|r| &r.name))
+            .chain(rules.trend.iter().map(|r| &r.name))
+            .filter_map(|name| name.as_deref())
+            .chain(rules.script.iter().filter_map(|r| r.path.file_stem()?.to_str()))
+    }
+
+    pub fn is_armed_topic(&self, topic: &str) -> bool {
+        self.rules.intrusion.as_ref().and_then(|i| i.armed_topic.as_deref()) == Some(topic)
+    }
+}
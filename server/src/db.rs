@@ -0,0 +1,107 @@
+// storage backends, the same queries run on sqlite or postgresql through sqlx's any driver
+// queries use $1, $2... placeholders, which both accept, and go through datetime() for times:
+// sqlite stores them as text, postgresql as timestamps with a datetime(text) function of its own (see migrations/postgres)
+use std::future::Future;
+use std::pin::Pin;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sqlx::{AnyConnection, AnyPool, Executor};
+use sqlx::any::AnyPoolOptions;
+use sqlx::pool::PoolConnectionMetadata;
+use sqlx::migrate::Migrator;
+
+use crate::timestamp;
+
+pub static SQLITE_MIGRATOR: Migrator = sqlx::migrate!("./migrations/sqlite");
+pub static POSTGRES_MIGRATOR: Migrator = sqlx::migrate!("./migrations/postgres");
+
+// set on every sqlite connection, in-memory databases keep their own journal mode
+pub const SQLITE_PRAGMAS: &str = "PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL; PRAGMA busy_timeout = 5000;";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Sqlite,
+    Postgres,
+}
+
+impl Backend {
+    // picked by the scheme of the database url
+    pub fn from_url(url: &str) -> Option<Self> {
+        if url.starts_with("sqlite:") {
+            Some(Self::Sqlite)
+        } else if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+            Some(Self::Postgres)
+        } else {
+            None
+        }
+    }
+
+    pub fn migrator(self) -> &'static Migrator {
+        match self {
+            Self::Sqlite => &SQLITE_MIGRATOR,
+            Self::Postgres => &POSTGRES_MIGRATOR,
+        }
+    }
+}
+
+// connects to the database of the url and brings its schema up to date
+pub async fn connect(url: &str) -> Result<AnyPool, sqlx::Error> {
+    connect_with(AnyPoolOptions::new(), url).await
+}
+
+pub async fn connect_with(options: AnyPoolOptions, url: &str) -> Result<AnyPool, sqlx::Error> {
+    sqlx::any::install_default_drivers();
+    let backend = Backend::from_url(url)
+        .ok_or_else(|| sqlx::Error::Configuration(format!("'{}' is neither an sqlite nor a postgres url", url).into()))?;
+    // wal lets readers go on while the writer commits and only syncs at checkpoints, which together with
+    // synchronous = normal is what makes small transactions cheap, a crash loses no more than the last commits
+    let options = match backend {
+        Backend::Sqlite => options.after_connect(set_sqlite_pragmas),
+        Backend::Postgres => options,
+    };
+    let pool = options.connect(url).await?;
+    backend.migrator().run(&pool).await?;
+    Ok(pool)
+}
+
+fn set_sqlite_pragmas(connection: &mut AnyConnection, _: PoolConnectionMetadata) -> Pin<Box<dyn Future<Output = Result<(), sqlx::Error>> + Send + '_>> {
+    Box::pin(async move {
+        connection.execute(SQLITE_PRAGMAS).await?;
+        Ok(())
+    })
+}
+
+// times are read back with `cast(created_at as text)`, which gives the same format on both backends
+pub fn parse_timestamp(text: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S%.f").ok()
+}
+
+// reading as stored, for the tools working on recorded data
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredReading {
+    pub device: String,
+    // sensor or derived metric
+    pub sensor: String,
+    pub value: f64,
+    pub firmware: Option<String>,
+    pub at: DateTime<Utc>,
+}
+
+// readings stored between `from` and `to`, optionally of one device, in the order they were read
+pub async fn readings(db_pool: &AnyPool, from: DateTime<Utc>, to: DateTime<Utc>, device: Option<&str>) -> Result<Vec<StoredReading>, sqlx::Error> {
+    let rows: Vec<(String, String, f64, Option<String>, String)> = sqlx::query_as(
+        "select device, sensor, value, firmware, cast(created_at as text) from readings
+        where created_at >= datetime($1) and created_at <= datetime($2) and ($3 is null or device = $3) order by created_at, id",
+    )
+    .bind(timestamp(from))
+    .bind(timestamp(to))
+    .bind(device)
+    .fetch_all(db_pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|(device, sensor, value, firmware, created_at)| {
+            let at = parse_timestamp(&created_at)?.and_utc();
+            Some(StoredReading { device, sensor, value, firmware, at })
+        })
+        .collect())
+}
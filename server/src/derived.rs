@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use chrono::{DateTime, TimeDelta, Utc};
+
+use crate::config::SensorKind;
+
+// temperature and humidity read further apart than this are not paired
+pub const PAIR_WINDOW: TimeDelta = TimeDelta::seconds(5);
+
+// metrics computed by the server from a temperature and humidity pair of the same device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DerivedKind {
+    DewPoint,
+    HeatIndex,
+    AbsoluteHumidity,
+}
+
+impl DerivedKind {
+    pub const ALL: [DerivedKind; 3] = [Self::DewPoint, Self::HeatIndex, Self::AbsoluteHumidity];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::DewPoint => "dew_point",
+            Self::HeatIndex => "heat_index",
+            Self::AbsoluteHumidity => "absolute_humidity",
+        }
+    }
+
+    pub fn unit(&self) -> &'static str {
+        match self {
+            Self::DewPoint | Self::HeatIndex => "°C",
+            Self::AbsoluteHumidity => "g/m³",
+        }
+    }
+
+    // temperature in °C and relative humidity in %, none when the result is undefined (e.g. dew point at 0%)
+    pub fn compute(&self, temperature: f64, humidity: f64) -> Option<f64> {
+        let value = match self {
+            Self::DewPoint => dew_point(temperature, humidity),
+            Self::HeatIndex => heat_index(temperature, humidity),
+            Self::AbsoluteHumidity => absolute_humidity(temperature, humidity),
+        };
+        value.is_finite().then_some(value)
+    }
+}
+
+// Magnus formula with the Sonntag constants, within 0.35°C between -45°C and 60°C
+pub fn dew_point(temperature: f64, humidity: f64) -> f64 {
+    const A: f64 = 17.62;
+    const B: f64 = 243.12;
+    let gamma = (humidity / 100.0).ln() + A * temperature / (B + temperature);
+    B * gamma / (A - gamma)
+}
+
+// NWS heat index, the simple formula below 80°F and the Rothfusz regression with its adjustments above
+pub fn heat_index(temperature: f64, humidity: f64) -> f64 {
+    let t = temperature * 9.0 / 5.0 + 32.0;
+    let rh = humidity;
+    let simple = 0.5 * (t + 61.0 + (t - 68.0) * 1.2 + rh * 0.094);
+    let fahrenheit = if (simple + t) / 2.0 < 80.0 {
+        simple
+    } else {
+        let mut hi = -42.379 + 2.04901523 * t + 10.14333127 * rh - 0.22475541 * t * rh - 0.00683783 * t * t
+            - 0.05481717 * rh * rh + 0.00122874 * t * t * rh + 0.00085282 * t * rh * rh - 0.00000199 * t * t * rh * rh;
+        if rh < 13.0 && (80.0..=112.0).contains(&t) {
+            hi -= (13.0 - rh) / 4.0 * ((17.0 - (t - 95.0).abs()) / 17.0).sqrt();
+        } else if rh > 85.0 && (80.0..=87.0).contains(&t) {
+            hi += (rh - 85.0) / 10.0 * (87.0 - t) / 5.0;
+        }
+        hi
+    };
+    (fahrenheit - 32.0) * 5.0 / 9.0
+}
+
+// grams of water vapour per cubic metre of air
+pub fn absolute_humidity(temperature: f64, humidity: f64) -> f64 {
+    let saturation_pressure = 6.112 * (17.67 * temperature / (temperature + 243.5)).exp();
+    saturation_pressure * humidity * 2.1674 / (273.15 + temperature)
+}
+
+// temperature and humidity of a device read close enough together to be combined
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pair {
+    pub temperature: f64,
+    pub humidity: f64,
+    // the later of both reads
+    pub read_at: DateTime<Utc>,
+}
+
+impl Pair {
+    pub fn derived(&self) -> Vec<(DerivedKind, f64)> {
+        DerivedKind::ALL
+            .into_iter()
+            .filter_map(|kind| kind.compute(self.temperature, self.humidity).map(|value| (kind, value)))
+            .collect()
+    }
+}
+
+#[derive(Default)]
+struct Pending {
+    temperature: Option<(f64, DateTime<Utc>)>,
+    humidity: Option<(f64, DateTime<Utc>)>,
+}
+
+// pairs the separately sent temperature and humidity values by the time they were read
+#[derive(Default)]
+pub struct Correlator {
+    pending: Mutex<HashMap<String, Pending>>,
+}
+
+impl Correlator {
+    // returns a pair once both values of a device were read within PAIR_WINDOW, each value is used at most once
+    pub fn add(&self, device: &str, sensor: SensorKind, value: f64, read_at: DateTime<Utc>) -> Option<Pair> {
+        let mut pending = self.pending.lock().unwrap();
+        let entry = pending.entry(device.to_string()).or_default();
+        match sensor {
+            SensorKind::Temperature => entry.temperature = Some((value, read_at)),
+            SensorKind::Humidity => entry.humidity = Some((value, read_at)),
+            _ => return None,
+        }
+        let (Some((temperature, temperature_at)), Some((humidity, humidity_at))) = (entry.temperature, entry.humidity) else {
+            return None;
+        };
+        if (temperature_at - humidity_at).abs() > PAIR_WINDOW {
+            return None;
+        }
+        *entry = Pending::default();
+        Some(Pair { temperature, humidity, read_at: temperature_at.max(humidity_at) })
+    }
+}
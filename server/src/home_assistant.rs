@@ -0,0 +1,84 @@
+// home assistant mqtt discovery, so the sensors of every configured device show up without any yaml
+// https://www.home-assistant.io/integrations/mqtt/#mqtt-discovery
+use serde_json::json;
+
+use crate::actions::Publish;
+use crate::config::{HomeAssistantConfig, SensorKind, TopicConfig};
+
+const MANUFACTURER: &str = "iiot";
+
+// node and object ids may only contain letters, digits, underscores and dashes
+fn id(name: &str) -> String {
+    name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' }).collect()
+}
+
+fn component(sensor: SensorKind) -> &'static str {
+    if sensor.is_binary() { "binary_sensor" } else { "sensor" }
+}
+
+pub fn state_topic(config: &HomeAssistantConfig, device: &str, sensor: SensorKind) -> String {
+    format!("{}/{}/{}", config.state_prefix, device, sensor.as_str())
+}
+
+fn discovery_topic(config: &HomeAssistantConfig, device: &str, sensor: SensorKind) -> String {
+    format!("{}/{}/{}/{}/config", config.discovery_prefix, component(sensor), id(device), sensor.as_str())
+}
+
+// retained discovery config of every configured device and sensor
+pub fn discovery(config: &HomeAssistantConfig, topics: &[TopicConfig]) -> Vec<Publish> {
+    let mut sensors: Vec<(&str, SensorKind)> = Vec::new();
+    for topic in topics {
+        if !sensors.contains(&(topic.device(), topic.sensor)) {
+            sensors.push((topic.device(), topic.sensor));
+        }
+    }
+    sensors
+        .into_iter()
+        .map(|(device, sensor)| {
+            let mut payload = json!({
+                "name": sensor.as_str(),
+                "unique_id": id(&format!("{}_{}_{}", config.state_prefix, device, sensor.as_str())),
+                "state_topic": state_topic(config, device, sensor),
+                "device": {
+                    "identifiers": [id(&format!("{}_{}", config.state_prefix, device))],
+                    "name": device,
+                    "manufacturer": MANUFACTURER,
+                },
+            });
+            match sensor {
+                SensorKind::Temperature | SensorKind::Humidity => {
+                    payload["device_class"] = json!(sensor.as_str());
+                    payload["unit_of_measurement"] = json!(sensor.unit());
+                    payload["state_class"] = json!("measurement");
+                }
+                SensorKind::Motion | SensorKind::Contact => {
+                    payload["device_class"] = json!(if sensor == SensorKind::Motion { "occupancy" } else { "door" });
+                    payload["payload_on"] = json!("ON");
+                    payload["payload_off"] = json!("OFF");
+                }
+            }
+            Publish { topic: discovery_topic(config, device, sensor), payload: payload.to_string(), retain: true }
+        })
+        .collect()
+}
+
+// empty retained configs removing the sensors of `previous` that are not in `current` from home assistant
+pub fn removed(previous: &[Publish], current: &[Publish]) -> Vec<Publish> {
+    previous
+        .iter()
+        .filter(|old| current.iter().all(|new| new.topic != old.topic))
+        .map(|old| Publish { topic: old.topic.clone(), payload: String::new(), retain: true })
+        .collect()
+}
+
+// retained state of a stored reading, the plain value or ON/OFF for motion and contact
+pub fn state(config: &HomeAssistantConfig, device: &str, sensor: SensorKind, value: f64) -> Publish {
+    let payload = if !sensor.is_binary() {
+        value.to_string()
+    } else if value == 1.0 {
+        "ON".to_string()
+    } else {
+        "OFF".to_string()
+    };
+    Publish { topic: state_topic(config, device, sensor), payload, retain: true }
+}
@@ -0,0 +1,382 @@
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use rumqttc::{AsyncClient, Event, Incoming, MqttOptions, Outgoing, QoS};
+use sqlx::AnyPool;
+use thiserror::Error;
+use tokio::sync::{mpsc, watch};
+
+pub mod actions;
+use actions::Publish;
+
+pub mod alerts;
+use alerts::{Alert, AlertKind, Alerter};
+
+pub mod config;
+use config::{Config, ConfigError, Metric};
+
+pub mod anomaly;
+
+pub mod backtest;
+
+pub mod db;
+
+pub mod derived;
+use derived::Pair;
+
+pub mod home_assistant;
+
+pub mod notify;
+
+pub mod payload;
+use payload::{check_value, Payload};
+
+pub mod reload;
+
+pub mod replay;
+
+pub mod rules;
+use rules::Rules;
+
+pub mod scripts;
+use scripts::{ScriptAction, ScriptReading, Scripts};
+
+pub mod sequence;
+use sequence::Sequences;
+
+pub mod simulator;
+
+pub mod sinks;
+use sinks::{Point, Sinks};
+
+pub mod trend;
+
+pub mod writer;
+use writer::{Write, Writer};
+
+#[derive(Debug, Error)]
+pub enum AppError {
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("MQTT Error: {0}")]
+    Mqtt(#[from] rumqttc::ClientError),
+    #[error("Config Error: {0}")]
+    Config(#[from] ConfigError),
+    #[error("Email Error: {0}")]
+    Email(String),
+    #[error("Script Error: {0}")]
+    Script(String),
+    #[error("Sink Error: {0}")]
+    Sink(String),
+}
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+// room for the subscriptions and home assistant discovery queued before the event loop runs
+const REQUEST_QUEUE_SIZE: usize = 100;
+
+// what the subscriber stores, checks and forwards readings with, kept across reconnects
+#[derive(Clone)]
+pub struct Services {
+    pub db_pool: AnyPool,
+    pub alerter: Arc<Alerter>,
+    pub rules: Arc<Rules>,
+    pub scripts: Arc<Scripts>,
+    pub sinks: Sinks,
+    pub writer: Writer,
+    pub sequences: Arc<Sequences>,
+}
+
+// keeps the subscriber connected until shutdown
+pub async fn run_subscriber(
+    config: watch::Receiver<Arc<Config>>,
+    services: Services,
+    mut outbox: mpsc::Receiver<Publish>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    while !*shutdown.borrow() {
+        if let Err(e) = start_mqtt_subscriber(config.clone(), &services, &mut outbox, shutdown.clone()).await {
+            eprintln!("MQTT subscriber error: {}", e);
+        }
+        // waiting a bit before reconnecting, unless the server is shutting down
+        tokio::select! {
+            _ = tokio::time::sleep(RECONNECT_DELAY) => {}
+            _ = shutdown.changed() => {}
+        }
+    }
+}
+
+pub async fn start_mqtt_subscriber(
+    mut config: watch::Receiver<Arc<Config>>,
+    services: &Services,
+    outbox: &mut mpsc::Receiver<Publish>,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<(), AppError> {
+    let current = config.borrow_and_update().clone();
+    let mut mqtt_options = MqttOptions::new(&current.mqtt.client_id, &current.mqtt.host, current.mqtt.port);
+    mqtt_options.set_keep_alive(Duration::from_secs(current.mqtt.keep_alive_secs));
+
+    let (client, mut event_loop) = AsyncClient::new(mqtt_options, REQUEST_QUEUE_SIZE);
+    let mut subscribed = current.subscriptions();
+    for topic in &subscribed {
+        client.subscribe(topic, QoS::AtMostOnce).await?;
+    }
+    println!("MQTT connected and subscribed to topics");
+    // discovery configs are retained, sending them on every connect also restores them after a broker lost its state
+    let mut discovered = discovery(&current);
+    for publish in discovered.clone() {
+        try_publish(&client, publish);
+    }
+    let ctx = Context {
+        db_pool: &services.db_pool,
+        client: &client,
+        alerter: &services.alerter,
+        rules: &services.rules,
+        scripts: &services.scripts,
+        sinks: &services.sinks,
+        writer: &services.writer,
+        sequences: &services.sequences,
+    };
+
+    // set once a disconnect was requested, messages received until the broker acknowledges it are still stored
+    let mut disconnecting = false;
+    loop {
+        tokio::select! {
+            event = event_loop.poll() => {
+                let Ok(event) = event else { break };
+                match event {
+                    Event::Incoming(Incoming::Publish(publish)) => {
+                        let current = config.borrow().clone();
+                        handle_publish(&current, &publish.topic, &publish.payload, &ctx).await?;
+                    }
+                    Event::Outgoing(Outgoing::Disconnect) => {
+                        println!("MQTT disconnected");
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            Ok(()) = config.changed() => {
+                // topic mappings are updated on the live connection
+                let current = config.borrow_and_update().clone();
+                let topics = current.subscriptions();
+                for topic in subscribed.difference(&topics) {
+                    client.unsubscribe(topic).await?;
+                    println!("Unsubscribed from {}", topic);
+                }
+                for topic in topics.difference(&subscribed) {
+                    client.subscribe(topic, QoS::AtMostOnce).await?;
+                    println!("Subscribed to {}", topic);
+                }
+                subscribed = topics;
+                let current = discovery(&current);
+                if current != discovered {
+                    for publish in home_assistant::removed(&discovered, &current).into_iter().chain(current.clone()) {
+                        try_publish(&client, publish);
+                    }
+                    discovered = current;
+                }
+            }
+            // messages of actions raised while disconnected are sent once connected again
+            Some(publish) = outbox.recv(), if !disconnecting => try_publish(&client, publish),
+            Ok(()) = shutdown.changed(), if !disconnecting => {
+                println!("Disconnecting from MQTT broker");
+                disconnecting = true;
+                client.disconnect().await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// what received messages are handled with
+pub struct Context<'a> {
+    pub db_pool: &'a AnyPool,
+    pub client: &'a AsyncClient,
+    pub alerter: &'a Alerter,
+    pub rules: &'a Rules,
+    pub scripts: &'a Scripts,
+    pub sinks: &'a Sinks,
+    pub writer: &'a Writer,
+    pub sequences: &'a Sequences,
+}
+
+pub async fn handle_publish(config: &Config, topic: &str, payload: &[u8], ctx: &Context<'_>) -> Result<(), AppError> {
+    let Context { db_pool, alerter, rules, writer, .. } = *ctx;
+    let payload = String::from_utf8_lossy(payload).to_string();
+
+    println!("Received on {}: {}", topic, payload);
+
+    if config.is_armed_topic(topic) {
+        return match parse_armed(&payload) {
+            Some(armed) => {
+                rules.set_armed(armed).await;
+                Ok(())
+            }
+            None => dead_letter(writer, topic, &payload, "invalid armed state").await,
+        };
+    }
+
+    let Some(topic_config) = config.topic(topic) else {
+        return dead_letter(writer, topic, &payload, "unknown topic").await;
+    };
+    let device = topic_config.device();
+    rules.seen(device).await;
+
+    // values are signed and may carry decimals, e.g. "-5.3"
+    let parsed = match Payload::parse(&payload) {
+        Ok(parsed) => parsed,
+        Err(e) => return dead_letter(writer, topic, &payload, &e).await,
+    };
+    let sensor = topic_config.sensor.as_str();
+    let unit = topic_config.sensor.unit();
+    if let Some(sent_unit) = &parsed.unit && sent_unit != unit {
+        let error = format!("unit mismatch: expected \"{}\", got \"{}\"", unit, sent_unit);
+        return dead_letter(writer, topic, &payload, &error).await;
+    }
+
+    // fault rules see impossible values as well, as they are outside any measuring range
+    for alert in rules.check_range(device, topic_config.sensor, parsed.value).await {
+        alerter.raise(&alert).await;
+    }
+    if let Err(e) = check_value(topic_config.sensor, parsed.value) {
+        return dead_letter(writer, topic, &payload, &e).await;
+    }
+
+    if let Some(seq) = parsed.seq {
+        let check = ctx.sequences.check(db_pool, writer, device, sensor, seq, parsed.uptime).await?;
+        if !check.is_accepted() {
+            println!("Dropping {:?} message {} on {}", check, seq, topic);
+            return Ok(());
+        }
+    }
+
+    // readings cached on the device during an outage are stored with the time they were read
+    let value = parsed.value;
+    let read_at = Utc::now() - parsed.age;
+    let created_at = timestamp(read_at);
+    writer.write(Write::Reading {
+        device: device.to_string(),
+        sensor: sensor.to_string(),
+        value,
+        unit: unit.to_string(),
+        seq: parsed.seq.map(i64::from),
+        firmware: parsed.firmware,
+        created_at,
+    }).await;
+    ctx.sinks.send(&Point { device: device.to_string(), sensor: sensor.to_string(), unit: unit.to_string(), value, at: read_at });
+    if let Some(home_assistant) = &config.home_assistant {
+        try_publish(ctx.client, home_assistant::state(home_assistant, device, topic_config.sensor, value));
+    }
+
+    // motion and contact go through the intrusion rule, which may hold them back or merge them
+    if topic_config.sensor.is_binary() && value == 1.0 {
+        for alert in rules.check_event(device, topic_config.sensor, read_at).await {
+            alerter.raise(&alert).await;
+        }
+    }
+
+    check_rules(ctx, device, Metric::Sensor(topic_config.sensor), value, read_at).await?;
+
+    if let Some(pair) = rules.pair(device, topic_config.sensor, value, read_at) {
+        store_derived(ctx, device, &pair).await?;
+    }
+    Ok(())
+}
+
+// stores the metrics derived from a temperature and humidity pair, and checks them against the rules
+async fn store_derived(ctx: &Context<'_>, device: &str, pair: &Pair) -> Result<(), AppError> {
+    let created_at = timestamp(pair.read_at);
+    for (kind, value) in pair.derived() {
+        // the inputs only have one decimal place
+        let value = (value * 10.0).round() / 10.0;
+        let (sensor, unit) = (kind.as_str(), kind.unit());
+        ctx.writer.write(Write::Reading {
+            device: device.to_string(),
+            sensor: sensor.to_string(),
+            value,
+            unit: unit.to_string(),
+            seq: None,
+            firmware: None,
+            created_at: created_at.clone(),
+        }).await;
+        ctx.sinks.send(&Point { device: device.to_string(), sensor: sensor.to_string(), unit: unit.to_string(), value, at: pair.read_at });
+        check_rules(ctx, device, Metric::Derived(kind), value, pair.read_at).await?;
+    }
+    Ok(())
+}
+
+// armed state sent on the armed topic
+fn parse_armed(payload: &str) -> Option<bool> {
+    match payload.trim().to_ascii_lowercase().as_str() {
+        "1" | "on" | "true" | "armed" => Some(true),
+        "0" | "off" | "false" | "disarmed" => Some(false),
+        _ => None,
+    }
+}
+
+// threshold, anomaly, rate and trend rules and the rule scripts for a stored reading
+async fn check_rules(ctx: &Context<'_>, device: &str, metric: Metric, value: f64, read_at: DateTime<Utc>) -> Result<(), AppError> {
+    let Context { db_pool, alerter, rules, writer, .. } = *ctx;
+    let reading = ScriptReading { device, metric, value, at: read_at };
+    // the rate and trend rules and the scripts read the stored series, which has to include this reading
+    if rules.reads_history(device, metric) || ctx.scripts.applies(&reading) {
+        writer.flush().await;
+    }
+    let mut alerts = rules.check_reading(device, metric, value);
+    alerts.extend(rules.check_anomaly(db_pool, device, metric, value, read_at).await?);
+    alerts.extend(rules.check_changes(db_pool, device, metric, read_at).await?);
+    for alert in alerts {
+        alerter.raise(&alert).await;
+    }
+
+    for (script, action) in ctx.scripts.run(db_pool, &reading).await? {
+        match action {
+            ScriptAction::Alert { subject, body, severity } => {
+                let alert = Alert::new(AlertKind::Script, device, &subject, &body)
+                    .with_severity(severity)
+                    .with_rule(Some(&script));
+                alerter.raise(&alert).await;
+            }
+            ScriptAction::Publish { script, topic, payload, retain } => {
+                let source = format!("Script {}", script.display());
+                alerter.publish(&source, Publish { topic, payload, retain }, ctx.scripts.min_interval(&script)).await;
+            }
+            ScriptAction::Annotate { text } => {
+                writer.write(Write::Annotation {
+                    device: device.to_string(),
+                    sensor: metric.as_str().to_string(),
+                    script,
+                    text,
+                    created_at: timestamp(read_at),
+                }).await;
+            }
+        }
+    }
+    Ok(())
+}
+
+// the request queue is drained by the subscriber loop, which also handles the messages publishing, so waiting for room would never end
+fn try_publish(client: &AsyncClient, publish: Publish) {
+    if let Err(e) = client.try_publish(&publish.topic, QoS::AtLeastOnce, publish.retain, publish.payload) {
+        eprintln!("Could not publish on {}: {}", publish.topic, e);
+    }
+}
+
+fn discovery(config: &Config) -> Vec<Publish> {
+    match &config.home_assistant {
+        Some(home_assistant) => home_assistant::discovery(home_assistant, &config.topics),
+        None => Vec::new(),
+    }
+}
+
+// same format as CURRENT_TIMESTAMP, so rows written by the database and by the server compare correctly
+pub fn timestamp(at: DateTime<Utc>) -> String {
+    at.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+// stores a message that could not be handled, so one bad payload does not drop the connection
+async fn dead_letter(writer: &Writer, topic: &str, payload: &str, error: &str) -> Result<(), AppError> {
+    eprintln!("Dead-lettering message on {}: {}", topic, error);
+    writer.write(Write::DeadLetter { topic: topic.to_string(), payload: payload.to_string(), error: error.to_string() }).await;
+    Ok(())
+}
@@ -1,146 +1,197 @@
-use std::collections::HashMap;
+use std::sync::Arc;
 use std::time::Duration;
-use rumqttc::{AsyncClient, Event, Incoming, MqttOptions, QoS};
-use sqlx::SqlitePool;
-use thiserror::Error;
-use lettre::{Message, SmtpTransport, Transport};
-use lettre::transport::smtp::authentication::Credentials;
-use tokio::sync::Mutex;
-use once_cell::sync::Lazy;
-use tokio::time::Instant;
-
-#[derive(Debug, Error)]
-enum AppError {
-    #[error("Database error: {0}")]
-    Database(#[from] sqlx::Error),
-    #[error("MQTT Error: {0}")]
-    Mqtt(#[from] rumqttc::ClientError),
-    #[error("Environment Error: {0}")]
-    Env(#[from] std::env::VarError),
-    #[error("Parse Error: {0}")]
-    Parse(#[from] std::num::ParseIntError),
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
+use rumqttc::{AsyncClient, Event, MqttOptions, Outgoing};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, oneshot, watch};
+
+use iiot_webserver::{db, run_subscriber, Services};
+use iiot_webserver::actions::OUTBOX_SIZE;
+use iiot_webserver::alerts::Alerter;
+use iiot_webserver::backtest::{self, parse_time, Summary};
+use iiot_webserver::config::Config;
+use iiot_webserver::notify::{run_notifier, QUEUE_SIZE};
+use iiot_webserver::reload::watch_config;
+use iiot_webserver::replay::{self, Pacing};
+use iiot_webserver::rules::{watch_events, watch_faults, watch_offline, watch_sustained, Rules};
+use iiot_webserver::scripts::Scripts;
+use iiot_webserver::sequence::Sequences;
+use iiot_webserver::sinks::Sinks;
+use iiot_webserver::writer::Writer;
+
+#[derive(Debug, Parser)]
+#[command(about = "Stores esp32 sensor readings received over MQTT and raises alerts")]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-
-
-
-static LAST_SENT_TIMES: Lazy<Mutex<HashMap<String, Instant>>> = Lazy::new(|| Mutex::new(HashMap::new()));
-const COOLDOWN_DURATION: Duration = Duration::from_secs(600);
-
-async fn maybe_send_email(subject: &str, body: &str, topic: &str) {
-    let username = std::env::var("EMAIL_USERNAME").expect("email username not set");
-    let password = std::env::var("EMAIL_PASSWORD").expect("email password not set");
-    let recipient = std::env::var("EMAIL_RECIPIENT").expect("email recipient not set");
-
-    let mut times = LAST_SENT_TIMES.lock().await;
-    let now = Instant::now();
-    if let Some(last_sent) = times.get(topic) {
-        if now.duration_since(*last_sent) < COOLDOWN_DURATION {
-            println!("Cooldown active f or topic: {}", topic);
-            return;
-        }
-    }
-
-    let email = Message::builder()
-        .from(username.parse().unwrap())
-        .to(recipient.parse().unwrap())
-        .subject(subject)
-        .body(body.to_string())
-        .unwrap();
-
-    let credentials = Credentials::new(username.clone(), password);
-    let mailer = SmtpTransport::relay("smtp.gmail.com")
-        .unwrap()
-        .credentials(credentials)
-        .build();
-
-    match mailer.send(&email) {
-        Ok(_) => {
-            println!("Email sent for topic: {}", topic);
-            times.insert(topic.to_string(), now);
-        },
-        Err(e) => eprintln!("Failed to send email: {:?}", e),
-    }
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Runs the alert rules of the config over the stored readings and prints the alerts they would have raised
+    Backtest {
+        /// Start of the readings to check, in UTC (YYYY-MM-DD HH:MM:SS or YYYY-MM-DD), earlier readings are only history
+        #[arg(long, value_parser = parse_time)]
+        from: Option<DateTime<Utc>>,
+        /// End of the readings to check, in UTC, now by default
+        #[arg(long, value_parser = parse_time)]
+        to: Option<DateTime<Utc>>,
+        /// Only checks the readings of this device
+        #[arg(long)]
+        device: Option<String>,
+    },
+    /// Republishes the stored readings of a time range to a broker, on the topics they arrived on
+    Replay {
+        /// Start of the readings to publish, in UTC (YYYY-MM-DD HH:MM:SS or YYYY-MM-DD)
+        #[arg(long, value_parser = parse_time)]
+        from: Option<DateTime<Utc>>,
+        /// End of the readings to publish, in UTC, now by default
+        #[arg(long, value_parser = parse_time)]
+        to: Option<DateTime<Utc>>,
+        /// Only publishes the readings of this device
+        #[arg(long)]
+        device: Option<String>,
+        /// Broker to publish to, the one of the config by default
+        #[arg(long)]
+        host: Option<String>,
+        #[arg(long)]
+        port: Option<u16>,
+        /// How many times faster than recorded the readings are published
+        #[arg(long, default_value_t = 1.0)]
+        speed: f64,
+        /// Publishes the readings without waiting between them
+        #[arg(long, conflicts_with = "speed")]
+        fast: bool,
+    },
 }
+
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
     dotenv::dotenv().ok();
-
-    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL is not set in .env file");
-    let mqtt_host = std::env::var("MQTT_HOST").expect("MQTT_HOST is not set in .env file");
-    let mqtt_port = std::env::var("MQTT_PORT").expect("MQTT_PORT is not set in .env file");
-
-    let db_pool = SqlitePool::connect(&database_url).await.expect("Database connection failed");
-
-    sqlx::migrate!().run(&db_pool).await.expect("Failed to run migrations");
-
-    loop {
-        if let Err(e) = start_mqtt_subscriber(&mqtt_host, &mqtt_port, db_pool.clone()).await {
-            eprintln!("MQTT subscriber error: {}", e);
+    let args = Args::parse();
+
+    // everything is validated up front, so a bad config fails here instead of at the first alert
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
         }
+    };
+
+    // sqlite or postgresql depending on the url, with the migrations of that backend applied
+    let db_pool = db::connect(&config.database.url).await.expect("Database connection failed");
+
+    if let Some(Command::Backtest { from, to, device }) = args.command {
+        let from = from.unwrap_or(DateTime::UNIX_EPOCH);
+        let fired = backtest::run(&config, &db_pool, from, to.unwrap_or_else(Utc::now), device.as_deref())
+            .await
+            .unwrap_or_else(|e| {
+                eprintln!("Backtest failed: {}", e);
+                std::process::exit(1);
+            });
+        for fired in &fired {
+            println!("{}", fired);
+        }
+        print!("{}", Summary::new(&fired));
+        return Ok(());
     }
-}
-
-async fn start_mqtt_subscriber(
-    host: &str,
-    port: &str,
-    db_pool: SqlitePool,
-) -> Result<(), AppError> {
-    let mut mqtt_options = MqttOptions::new("rust-mqtt-subscriber", host, port.parse()?);
-    mqtt_options.set_keep_alive(Duration::from_secs(5));
-
-    let (client, mut event_loop) = AsyncClient::new(mqtt_options, 10);
-    client.subscribe("esp32/temperature", QoS::AtMostOnce).await?;
-    client.subscribe("esp32/humidity", QoS::AtMostOnce).await?;
-    client.subscribe("esp32/motion", QoS::AtMostOnce).await?;
-    client.subscribe("esp32/contact", QoS::AtMostOnce).await?;
-    println!("MQTT connected and subscribed to topics");
-
-    while let Ok(event) = event_loop.poll().await {
-        match event {
-            Event::Incoming(Incoming::Publish(publish)) => {
-                let topic = publish.topic;
-                let payload = String::from_utf8_lossy(&publish.payload).to_string();
-
-                println!("Received on {}: {}", topic, payload);
-
-                match topic.as_str() {
-                    "esp32/temperature" => {
-                        let temp_value: i32 = payload.parse()?;
-                        sqlx::query!(
-                            "insert into temperature (value) values (?)", temp_value
-                        ).execute(&db_pool).await?;
-                    }
-                    "esp32/humidity" => {
-                        let humid_value: i32 = payload.parse()?;
-                        sqlx::query!(
-                            "insert into humidity (value) values (?)", humid_value
-                        ).execute(&db_pool).await?;
-                    }
-                    "esp32/motion" => {
-                        let motion_value: i32 = payload.parse()?;
-                        sqlx::query!(
-                            "insert into motion (value) values (?)", motion_value
-                        ).execute(&db_pool).await?;
-                        if motion_value == 1 {
-                            maybe_send_email("Motion alert", "Motion was detected!", "esp32/motion").await;
-                        }
-
-                    }
-                    "esp32/contact" => {
-                        let contact_value: i32 = payload.parse()?;
-                        sqlx::query!(
-                            "insert into contact (value) values (?)", contact_value
-                        ).execute(&db_pool).await?;
-                        if contact_value == 1 {
-                            maybe_send_email("Contact alert", "Contact sensor was detected!", "esp32/contact").await;
-                        }
+    if let Some(Command::Replay { from, to, device, host, port, speed, fast }) = args.command {
+        let Some(pacing) = (if fast { Some(Pacing::Fast) } else { Pacing::speed(speed) }) else {
+            eprintln!("--speed has to be a positive number");
+            std::process::exit(1);
+        };
+        let (from, to) = (from.unwrap_or(DateTime::UNIX_EPOCH), to.unwrap_or_else(Utc::now));
+        let recorded = replay::load(&config, &db_pool, from, to, device.as_deref()).await.expect("Failed to load readings");
+        let host = host.unwrap_or(config.mqtt.host.clone());
+        let port = port.unwrap_or(config.mqtt.port);
+        println!("Replaying {} readings to {}:{}", recorded.len(), host, port);
+
+        // a client id of its own, so a server running against the same broker keeps its connection
+        let mut options = MqttOptions::new(format!("{}-replay", config.mqtt.client_id), host, port);
+        options.set_keep_alive(Duration::from_secs(config.mqtt.keep_alive_secs));
+        let (client, mut event_loop) = AsyncClient::new(options, replay::QUEUE_SIZE);
+        let connection = tokio::spawn(async move {
+            loop {
+                match event_loop.poll().await {
+                    Ok(Event::Outgoing(Outgoing::Disconnect)) => break,
+                    Ok(_) => {}
+                    Err(e) => {
+                        eprintln!("MQTT error: {}", e);
+                        tokio::time::sleep(Duration::from_secs(1)).await;
                     }
-                    _ => println!("Unknown topic: {}", topic),
                 }
             }
-            _ => {}
+        });
+        if let Err(e) = replay::publish(&client, &recorded, pacing).await {
+            eprintln!("Replay failed: {}", e);
         }
+        // the disconnect is queued after the readings, so they are all sent once it goes out
+        let _ = client.disconnect().await;
+        let _ = connection.await;
+        return Ok(());
+    }
+
+    let (queue_tx, queue_rx) = mpsc::channel(QUEUE_SIZE);
+    let (close_tx, close_rx) = oneshot::channel();
+    let notifier = tokio::spawn(run_notifier(queue_rx, close_rx));
+
+    // messages published by actions go out on the subscriber's connection
+    let (outbox_tx, outbox_rx) = mpsc::channel(OUTBOX_SIZE);
+    let alerter = Arc::new(Alerter::new(&config, queue_tx, outbox_tx).expect("Failed to set up notifiers"));
+    let rules = Arc::new(Rules::new(&config.rules));
+    let scripts = Arc::new(Scripts::new(&config).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }));
+    let (sinks, sink_tasks) = Sinks::start(&config).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let (writer, writer_task) = Writer::start(db_pool.clone(), &config.database);
+    tokio::spawn(watch_offline(rules.clone(), alerter.clone()));
+    tokio::spawn(watch_events(rules.clone(), alerter.clone()));
+    tokio::spawn(watch_sustained(db_pool.clone(), rules.clone(), alerter.clone()));
+    tokio::spawn(watch_faults(db_pool.clone(), rules.clone(), alerter.clone()));
+
+    // the running config is shared over a watch channel, so reloads reach the subscriber without reconnecting
+    let (config_tx, config_rx) = watch::channel(Arc::new(config));
+    tokio::spawn(watch_config(Config::path(), alerter.clone(), rules.clone(), scripts.clone(), config_tx));
+
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let services = Services { db_pool: db_pool.clone(), alerter, rules, scripts, sinks, writer, sequences: Arc::new(Sequences::default()) };
+    let subscriber = tokio::spawn(run_subscriber(config_rx.clone(), services, outbox_rx, shutdown_rx));
+
+    wait_for_shutdown_signal().await;
+    let timeout = Duration::from_secs(config_rx.borrow().shutdown.timeout_secs);
+    println!("Shutting down, waiting up to {} seconds for pending work", timeout.as_secs());
+    let _ = shutdown_tx.send(true);
+
+    // the subscriber has to finish first, as messages it drains can still raise alerts
+    // and forward readings, the sinks and the write queue store what they have left once it dropped them
+    let drain = async {
+        let _ = subscriber.await;
+        let _ = close_tx.send(());
+        let _ = notifier.await;
+        for task in sink_tasks {
+            let _ = task.await;
+        }
+        let _ = writer_task.await;
+        db_pool.close().await;
+    };
+    match tokio::time::timeout(timeout, drain).await {
+        Ok(_) => println!("Shutdown complete"),
+        Err(_) => eprintln!("Shutdown timed out after {} seconds, pending work was dropped", timeout.as_secs()),
     }
     Ok(())
 }
+
+async fn wait_for_shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => println!("SIGINT received"),
+        _ = terminate.recv() => println!("SIGTERM received"),
+    }
+}
+
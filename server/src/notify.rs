@@ -0,0 +1,99 @@
+use std::sync::Arc;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::AppError;
+use crate::alerts::{Alert, Severity};
+use crate::config::{EmailConfig, SmtpSecurity};
+
+// number of emails that can wait for delivery before raising alerts starts to wait
+pub const QUEUE_SIZE: usize = 100;
+
+// email waiting in the notification queue
+// the mailer is captured when the alert is raised, so a config reload does not affect queued emails
+pub struct Notification {
+    pub mailer: Arc<Mailer>,
+    pub alert: Alert,
+    pub recipients: Vec<String>,
+}
+
+// smtp transport built once from the email notifier config
+pub struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl Mailer {
+    pub fn new(config: &EmailConfig) -> Result<Self, AppError> {
+        let mut builder = match config.security {
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.smtp_host),
+            SmtpSecurity::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.smtp_host),
+            SmtpSecurity::None => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.smtp_host)),
+        }
+        .map_err(|e| AppError::Email(e.to_string()))?;
+        if let Some(port) = config.smtp_port {
+            builder = builder.port(port);
+        }
+        if !config.username.is_empty() {
+            builder = builder.credentials(Credentials::new(config.username.clone(), config.password.clone()));
+        }
+        Ok(Self {
+            transport: builder.build(),
+            from: parse_mailbox(config.sender())?,
+        })
+    }
+
+    pub async fn send(&self, alert: &Alert, recipients: &[String]) -> Result<(), AppError> {
+        // urgent alerts stand out in the inbox
+        let subject = match alert.severity {
+            Severity::High => format!("[URGENT] {}", alert.subject),
+            Severity::Normal => alert.subject.clone(),
+        };
+        let mut builder = Message::builder()
+            .from(self.from.clone())
+            .subject(subject);
+        for recipient in recipients {
+            builder = builder.to(parse_mailbox(recipient)?);
+        }
+        let email = builder
+            .body(alert.body.clone())
+            .map_err(|e| AppError::Email(e.to_string()))?;
+        self.transport.send(email).await.map_err(|e| AppError::Email(e.to_string()))?;
+        Ok(())
+    }
+}
+
+fn parse_mailbox(address: &str) -> Result<Mailbox, AppError> {
+    address.parse().map_err(|e| AppError::Email(format!("invalid address {}: {}", address, e)))
+}
+
+async fn deliver(notification: Notification) {
+    let Notification { mailer, alert, recipients } = notification;
+    match mailer.send(&alert, &recipients).await {
+        Ok(_) => println!("Email sent for {}/{} to {:?}", alert.device, alert.kind.as_str(), recipients),
+        Err(e) => eprintln!("Failed to send email: {}", e),
+    }
+}
+
+// sends queued emails one by one
+// after `close` fires, no new emails are accepted and the ones already queued are sent before returning
+pub async fn run_notifier(mut queue: mpsc::Receiver<Notification>, mut close: oneshot::Receiver<()>) {
+    loop {
+        tokio::select! {
+            notification = queue.recv() => match notification {
+                Some(notification) => deliver(notification).await,
+                None => return,
+            },
+            _ = &mut close => break,
+        }
+    }
+    queue.close();
+    if !queue.is_empty() {
+        println!("Sending {} queued notifications", queue.len());
+    }
+    while let Some(notification) = queue.recv().await {
+        deliver(notification).await;
+    }
+}
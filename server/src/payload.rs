@@ -0,0 +1,63 @@
+use std::time::Duration;
+use iiot_protocol::{Reading, SensorKind, PAYLOAD_VERSION};
+
+// value received on a sensor topic, either as a plain number ("21", "-5.3") or as a json payload
+#[derive(Debug, Clone, PartialEq)]
+pub struct Payload {
+    pub value: f64,
+    pub unit: Option<String>,
+    // how long before arriving the value was read, zero for plain values
+    pub age: Duration,
+    // device uptime when the value was sent, used to recognize reboots
+    pub uptime: Option<Duration>,
+    pub seq: Option<u32>,
+    pub firmware: Option<String>,
+}
+
+impl Payload {
+    // errors are stored in the dead letter table as they are
+    pub fn parse(payload: &str) -> Result<Self, String> {
+        let payload = payload.trim();
+        let parsed = if payload.starts_with('{') {
+            Self::parse_json(payload)?
+        } else {
+            let value = payload.parse().map_err(|e| format!("invalid value: {}", e))?;
+            Self { value, unit: None, age: Duration::ZERO, uptime: None, seq: None, firmware: None }
+        };
+        if !parsed.value.is_finite() {
+            return Err("invalid value: not a finite number".to_string());
+        }
+        Ok(parsed)
+    }
+
+    // json format is defined by the protocol crate
+    fn parse_json(payload: &str) -> Result<Self, String> {
+        let reading = Reading::decode(payload.as_bytes()).map_err(|e| format!("invalid payload: {}", e))?;
+        if reading.v != PAYLOAD_VERSION {
+            return Err(format!("unsupported payload version {}", reading.v));
+        }
+        let age = match (reading.ts, reading.up) {
+            (Some(ts), Some(up)) if ts <= up => Duration::from_millis(up - ts),
+            (Some(_), Some(_)) => return Err("invalid payload: ts is later than up".to_string()),
+            _ => Duration::ZERO,
+        };
+        Ok(Self {
+            value: reading.value,
+            unit: reading.unit.map(str::to_string),
+            age,
+            uptime: reading.up.map(Duration::from_millis),
+            seq: reading.seq,
+            firmware: reading.fw.map(str::to_string),
+        })
+    }
+}
+
+// rejects values no working sensor can report, whatever its measuring range
+pub fn check_value(sensor: SensorKind, value: f64) -> Result<(), String> {
+    let possible = match sensor {
+        SensorKind::Temperature => value >= -273.15,
+        SensorKind::Humidity => (0.0..=100.0).contains(&value),
+        SensorKind::Motion | SensorKind::Contact => value == 0.0 || value == 1.0,
+    };
+    if possible { Ok(()) } else { Err(format!("impossible value: {} {}", sensor.as_str(), value)) }
+}
@@ -0,0 +1,85 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+
+use crate::alerts::{AlertSettings, Alerter};
+use crate::config::Config;
+use crate::rules::Rules;
+use crate::scripts::{ScriptSettings, Scripts};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// reloads the config on SIGHUP or when the config file changes
+// the new config is only applied when it is valid, otherwise the running one is kept
+pub async fn watch_config(
+    path: PathBuf,
+    alerter: Arc<Alerter>,
+    rules: Arc<Rules>,
+    scripts: Arc<Scripts>,
+    config_tx: watch::Sender<Arc<Config>>,
+) {
+    let mut hangup = signal(SignalKind::hangup()).expect("Failed to listen for SIGHUP");
+    let mut last_modified = modified(&path);
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = hangup.recv() => println!("SIGHUP received, reloading {}", path.display()),
+            _ = interval.tick() => {
+                let current = modified(&path);
+                if current == last_modified {
+                    continue;
+                }
+                last_modified = current;
+                println!("{} changed, reloading", path.display());
+            }
+        }
+
+        match reload(&path, |name| std::env::var(name).ok(), &alerter, &rules, &scripts, &config_tx) {
+            Ok(()) => println!("Config reloaded"),
+            Err(e) => eprintln!("Config reload rejected, keeping the running config: {}", e),
+        }
+    }
+}
+
+// the file has to exist, a deleted file or one caught while an editor replaces it is rejected like an invalid one
+pub fn reload(
+    path: &Path,
+    env: impl Fn(&str) -> Option<String>,
+    alerter: &Alerter,
+    rules: &Rules,
+    scripts: &Scripts,
+    config_tx: &watch::Sender<Arc<Config>>,
+) -> Result<(), String> {
+    let mut config = Config::load_existing(path, env).map_err(|e| e.to_string())?;
+    // everything that can fail is built before anything gets swapped
+    let alert_settings = AlertSettings::new(&config).map_err(|e| e.to_string())?;
+    // scripts are read again as well, so editing a script only needs the config file touched or a SIGHUP
+    let script_settings = ScriptSettings::new(&config).map_err(|e| e.to_string())?;
+
+    // broker and database connections are kept, changing them needs a restart
+    let running = config_tx.borrow().clone();
+    if config.mqtt != running.mqtt || config.database != running.database {
+        println!("Changes to [mqtt] or [database] need a restart, keeping the running values");
+        config.mqtt = running.mqtt.clone();
+        config.database = running.database.clone();
+    }
+    // sinks are only started once, and like them the shutdown deadline only changes with a restart
+    if config.sinks != running.sinks || config.shutdown != running.shutdown {
+        println!("Changes to [sinks] or [shutdown] are ignored until the next restart");
+        config.sinks = running.sinks.clone();
+        config.shutdown = running.shutdown.clone();
+    }
+
+    alerter.reload(alert_settings);
+    rules.reload(&config.rules);
+    scripts.reload(script_settings);
+    config_tx.send_replace(Arc::new(config));
+    Ok(())
+}
@@ -0,0 +1,89 @@
+// republishes stored readings to a broker, so dashboards, other subscribers or a fresh server can be run on recorded data
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use iiot_protocol::{Reading, SensorKind, Topic};
+use rumqttc::{AsyncClient, QoS};
+use sqlx::AnyPool;
+
+use crate::config::Config;
+use crate::db;
+use crate::simulator::Message;
+use crate::timestamp;
+
+// publishes the client can queue before publishing waits for the connection
+pub const QUEUE_SIZE: usize = 100;
+
+// how the time between the readings is kept
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pacing {
+    // the recorded gaps divided by the speed, 1.0 is real time
+    Speed(f64),
+    // no waiting at all
+    Fast,
+}
+
+impl Pacing {
+    // None unless the speed is a finite number above zero, anything else would not give a delay
+    pub fn speed(speed: f64) -> Option<Self> {
+        (speed.is_finite() && speed > 0.0).then_some(Self::Speed(speed))
+    }
+
+    // gaps too long to represent after dividing by a tiny speed wait as long as possible instead of panicking
+    pub fn delay(&self, gap: Duration) -> Duration {
+        match self {
+            Self::Speed(speed) => Duration::try_from_secs_f64(gap.as_secs_f64() / speed).unwrap_or(Duration::MAX),
+            Self::Fast => Duration::ZERO,
+        }
+    }
+}
+
+// stored reading with the message it is republished as
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recorded {
+    pub at: DateTime<Utc>,
+    pub message: Message,
+}
+
+// readings of the sensors stored between `from` and `to`, optionally of one device, in the order they were read
+// derived metrics are left out, as a receiving server derives them again
+// topics follow the configured topic of the device and sensor, or the {device}/{sensor} layout of devices not in the config
+// sequence numbers are left out, without the uptimes they came with a receiver could not tell a reboot from a replay
+pub async fn load(
+    config: &Config,
+    db_pool: &AnyPool,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    device: Option<&str>,
+) -> Result<Vec<Recorded>, sqlx::Error> {
+    let rows = db::readings(db_pool, from, to, device).await?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| {
+            let sensor = SensorKind::parse(&row.sensor)?;
+            let topic = match config.topics.iter().find(|t| t.sensor == sensor && t.device() == row.device) {
+                Some(topic) => topic.topic.clone(),
+                None => Topic::Reading { device: &row.device, sensor }.to_string(),
+            };
+            let reading = Reading { fw: row.firmware.as_deref(), ..Reading::new(sensor, row.value) };
+            let mut buf = [0u8; iiot_protocol::PAYLOAD_LEN];
+            let len = reading.encode(&mut buf).ok()?;
+            let payload = String::from_utf8_lossy(&buf[..len]).to_string();
+            Some(Recorded { at: row.at, message: Message { topic, payload } })
+        })
+        .collect())
+}
+
+// publishes the readings with the gaps between them kept according to the pacing
+pub async fn publish(client: &AsyncClient, recorded: &[Recorded], pacing: Pacing) -> Result<(), rumqttc::ClientError> {
+    let mut previous: Option<DateTime<Utc>> = None;
+    for Recorded { at, message } in recorded {
+        if let Some(previous) = previous {
+            let gap = (*at - previous).to_std().unwrap_or_default();
+            tokio::time::sleep(pacing.delay(gap)).await;
+        }
+        previous = Some(*at);
+        println!("[{}] {} {}", timestamp(*at), message.topic, message.payload);
+        client.publish(&message.topic, QoS::AtLeastOnce, false, message.payload.clone()).await?;
+    }
+    Ok(())
+}
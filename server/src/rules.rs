@@ -0,0 +1,712 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use chrono::{DateTime, TimeDelta, Utc};
+use sqlx::{AnyPool, FromRow};
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+use crate::alerts::{Alert, AlertKind, Alerter, Severity};
+use crate::anomaly::Baseline;
+use crate::config::{AnomalyRule, Direction, FaultRule, IntrusionRule, Metric, RateRule, RulesConfig, SensorKind, SustainedRule, ThresholdRule, TrendRule};
+use crate::derived::{Correlator, Pair};
+use crate::db::parse_timestamp;
+use crate::timestamp;
+use crate::trend::fit;
+
+// how long a reading counts for when no newer one follows, so gaps in the data are not counted as time outside the limits
+pub const MAX_HOLD: TimeDelta = TimeDelta::minutes(10);
+// how often sustained and fault rules are evaluated against the stored history
+pub const SUSTAINED_INTERVAL: Duration = Duration::from_secs(60);
+
+// when a device was last heard from, and whether it was already reported as offline
+struct DeviceActivity {
+    last_seen: Instant,
+    offline: bool,
+}
+
+// door and motion events seen by the intrusion rule
+#[derive(Default)]
+struct Events {
+    // doors opened while armed, their contact alert is held back until `entry_secs` passed without motion
+    pending_doors: Vec<(String, DateTime<Utc>)>,
+    last_door: Option<DateTime<Utc>>,
+}
+
+// rule settings, swapped as a whole when the config gets reloaded
+struct RuleSettings {
+    thresholds: Vec<ThresholdRule>,
+    sustained: Vec<SustainedRule>,
+    anomaly: Vec<AnomalyRule>,
+    rates: Vec<RateRule>,
+    trends: Vec<TrendRule>,
+    offline_after: Option<Duration>,
+    fault: Option<FaultRule>,
+    intrusion: Option<IntrusionRule>,
+}
+
+impl RuleSettings {
+    fn new(config: &RulesConfig) -> Self {
+        Self {
+            thresholds: config.threshold.clone(),
+            sustained: config.sustained.clone(),
+            anomaly: config.anomaly.clone(),
+            rates: config.rate.clone(),
+            trends: config.trend.clone(),
+            offline_after: config.offline.as_ref().map(|o| Duration::from_secs(o.after_secs)),
+            fault: config.fault.clone(),
+            intrusion: config.intrusion.clone(),
+        }
+    }
+}
+
+// evaluates the configured alert rules against incoming readings and device activity
+pub struct Rules {
+    settings: RwLock<Arc<RuleSettings>>,
+    activity: Mutex<HashMap<String, DeviceActivity>>,
+    pairs: Correlator,
+    // sustained rules currently over their limit for a device, reported once until they recover
+    sustained_active: Mutex<HashSet<String>>,
+    // baselines of the series watched by anomaly rules, by device, metric and window
+    baselines: Mutex<HashMap<String, Baseline>>,
+    // sensor faults currently reported, by check, device and sensor, reported once until they recover
+    faults_active: Mutex<HashSet<String>>,
+    // rate and trend rules currently matching for a device, reported once until they stop
+    changes_active: Mutex<HashSet<String>>,
+    // armed state received on the armed topic, takes precedence over the config
+    armed: Mutex<Option<bool>>,
+    events: Mutex<Events>,
+}
+
+impl Rules {
+    pub fn new(config: &RulesConfig) -> Self {
+        Self {
+            settings: RwLock::new(Arc::new(RuleSettings::new(config))),
+            activity: Mutex::new(HashMap::new()),
+            pairs: Correlator::default(),
+            sustained_active: Mutex::new(HashSet::new()),
+            baselines: Mutex::new(HashMap::new()),
+            faults_active: Mutex::new(HashSet::new()),
+            changes_active: Mutex::new(HashSet::new()),
+            armed: Mutex::new(None),
+            events: Mutex::new(Events::default()),
+        }
+    }
+
+    // device activity is kept, so a reload does not reset offline tracking
+    pub fn reload(&self, config: &RulesConfig) {
+        *self.settings.write().unwrap() = Arc::new(RuleSettings::new(config));
+    }
+
+    fn settings(&self) -> Arc<RuleSettings> {
+        self.settings.read().unwrap().clone()
+    }
+
+    // returns threshold alerts for a reading
+    pub fn check_reading(&self, device: &str, sensor: Metric, value: f64) -> Vec<Alert> {
+        self.settings()
+            .thresholds
+            .iter()
+            .filter(|rule| rule.sensor == sensor && rule.device.as_ref().is_none_or(|d| d == device))
+            .filter_map(|rule| {
+                let limit = match (rule.above, rule.below) {
+                    (Some(above), _) if value > above => format!("above {}", above),
+                    (_, Some(below)) if value < below => format!("below {}", below),
+                    _ => return None,
+                };
+                Some(Alert::new(
+                    AlertKind::Threshold,
+                    device,
+                    &format!("{} alert", sensor.as_str()),
+                    &format!("{} on {} is {}, {}", sensor.as_str(), device, value, limit),
+                ).with_rule(rule.name.as_deref()))
+            })
+            .collect()
+    }
+
+    // compares a reading read at `at` to the baseline of its series, which is then updated with it
+    // a series seen for the first time learns its baseline from the readings stored before `at`
+    pub async fn check_anomaly(
+        &self,
+        db_pool: &AnyPool,
+        device: &str,
+        metric: Metric,
+        value: f64,
+        at: DateTime<Utc>,
+    ) -> Result<Vec<Alert>, sqlx::Error> {
+        let settings = self.settings();
+        let mut alerts = Vec::new();
+        let mut baselines = self.baselines.lock().await;
+        for rule in settings.anomaly.iter().filter(|rule| rule.sensor == metric && rule.device.as_ref().is_none_or(|d| d == device)) {
+            let key = format!("{}/{}/{}", device, metric.as_str(), rule.window);
+            if !baselines.contains_key(&key) {
+                let sensor = metric.as_str();
+                let before = timestamp(at);
+                let limit = rule.window as i64;
+                let mut history: Vec<f64> = sqlx::query_scalar(
+                    "select value from readings where device = $1 and sensor = $2 and created_at < datetime($3) order by created_at desc, id desc limit $4",
+                )
+                .bind(device)
+                .bind(sensor)
+                .bind(before)
+                .bind(limit)
+                .fetch_all(db_pool)
+                .await?;
+                history.reverse();
+                let mut baseline = Baseline::new(rule.window);
+                history.into_iter().for_each(|value| baseline.push(value));
+                baselines.insert(key.clone(), baseline);
+            }
+            let baseline = baselines.get_mut(&key).unwrap();
+            if let Some(z_score) = baseline.z_score(value, rule.min_samples, rule.min_std)
+                && z_score.abs() > rule.z_score
+            {
+                alerts.push(Alert::new(
+                    AlertKind::Anomaly,
+                    device,
+                    &format!("{} anomaly", metric.as_str()),
+                    &format!(
+                        "{} on {} is {}, {:.1} standard deviations from the recent mean of {:.1}",
+                        metric.as_str(), device, value, z_score, baseline.mean()
+                    ),
+                ).with_rule(rule.name.as_deref()));
+            }
+            baseline.push(value);
+        }
+        Ok(alerts)
+    }
+
+    // whether a rate or trend rule reads the stored series of the metric, which then has to include the latest reading
+    pub fn reads_history(&self, device: &str, metric: Metric) -> bool {
+        let settings = self.settings();
+        let applies = |rule_metric: Metric, rule_device: &Option<String>| rule_metric == metric && rule_device.as_ref().is_none_or(|d| d == device);
+        settings.rates.iter().any(|rule| applies(rule.sensor, &rule.device))
+            || settings.trends.iter().any(|rule| applies(rule.sensor, &rule.device))
+    }
+
+    // evaluates the rate and trend rules over the readings stored up to `at`, including the one just stored
+    pub async fn check_changes(
+        &self,
+        db_pool: &AnyPool,
+        device: &str,
+        metric: Metric,
+        at: DateTime<Utc>,
+    ) -> Result<Vec<Alert>, sqlx::Error> {
+        let settings = self.settings();
+        let applies = |rule_metric: Metric, rule_device: &Option<String>| rule_metric == metric && rule_device.as_ref().is_none_or(|d| d == device);
+        let mut alerts = Vec::new();
+        let mut active = self.changes_active.lock().await;
+        for rule in settings.rates.iter().filter(|rule| applies(rule.sensor, &rule.device)) {
+            let readings = history(db_pool, device, metric, at, rule.window_secs).await?;
+            let Some(&(_, value)) = readings.last() else { continue };
+            let lowest = readings.iter().map(|(_, v)| *v).fold(f64::INFINITY, f64::min);
+            let highest = readings.iter().map(|(_, v)| *v).fold(f64::NEG_INFINITY, f64::max);
+            let changes = [(Direction::Rising, rule.rise, value - lowest), (Direction::Falling, rule.fall, highest - value)];
+            for (direction, limit, change) in changes {
+                let Some(limit) = limit else { continue };
+                if !update_active(&mut active, rule.key(device, direction), change > limit) {
+                    continue;
+                }
+                let verb = if direction == Direction::Rising { "rose" } else { "fell" };
+                alerts.push(Alert::new(
+                    AlertKind::Rate,
+                    device,
+                    &format!("{} {} fast", metric.as_str(), direction.as_str()),
+                    &format!(
+                        "{} on {} {} by {:.1} to {} within {:.0} minutes",
+                        metric.as_str(), device, verb, change, value, rule.window_secs as f64 / 60.0
+                    ),
+                ).with_rule(rule.name.as_deref()));
+            }
+        }
+        for rule in settings.trends.iter().filter(|rule| applies(rule.sensor, &rule.device)) {
+            let readings = history(db_pool, device, metric, at, rule.window_secs).await?;
+            let window = TimeDelta::seconds(rule.window_secs as i64);
+            // the readings have to cover the whole window, a series that just started has no trend yet
+            let covered = readings.first().is_some_and(|(first, _)| *first <= at - window + MAX_HOLD);
+            let hours = rule.window_secs as f64 / 3600.0;
+            let trend = fit(&readings).filter(|fit| {
+                let change = match rule.direction {
+                    Direction::Rising => fit.slope * hours,
+                    Direction::Falling => -fit.slope * hours,
+                };
+                covered && change > 0.0 && change >= rule.min_change && fit.r_squared >= rule.min_fit
+            });
+            if !update_active(&mut active, rule.key(device), trend.is_some()) {
+                continue;
+            }
+            let trend = trend.unwrap();
+            alerts.push(Alert::new(
+                AlertKind::Trend,
+                device,
+                &format!("{} {} steadily", metric.as_str(), rule.direction.as_str()),
+                &format!(
+                    "{} on {} has been {} steadily for {:.1} hours, by {:.1} per hour (fit {:.2})",
+                    metric.as_str(), device, rule.direction.as_str(), hours, trend.slope.abs(), trend.r_squared
+                ),
+            ).with_rule(rule.name.as_deref()));
+        }
+        Ok(alerts)
+    }
+
+    // returns a sensor fault alert when a reading leaves the measuring range of the sensor
+    pub async fn check_range(&self, device: &str, sensor: SensorKind, value: f64) -> Vec<Alert> {
+        let settings = self.settings();
+        let Some(fault) = settings.fault.as_ref().filter(|fault| fault.device.as_ref().is_none_or(|d| d == device)) else {
+            return Vec::new();
+        };
+        let [low, high] = match sensor {
+            SensorKind::Temperature => fault.temperature_range,
+            SensorKind::Humidity => fault.humidity_range,
+            _ => return Vec::new(),
+        };
+        let key = format!("range/{}/{}", device, sensor.as_str());
+        let mut active = self.faults_active.lock().await;
+        if !update_fault(&mut active, key, !(low..=high).contains(&value)) {
+            return Vec::new();
+        }
+        vec![fault_alert(
+            device,
+            &format!("{} on {} is {}, outside the sensor range of {} to {}", sensor.as_str(), device, value, low, high),
+        )]
+    }
+
+    // evaluates the fault checks over the history stored up to `now`, returning alerts for faults that started
+    pub async fn check_faults(&self, db_pool: &AnyPool, now: DateTime<Utc>) -> Result<Vec<Alert>, sqlx::Error> {
+        let settings = self.settings();
+        let Some(fault) = &settings.fault else { return Ok(Vec::new()) };
+        let applies = |device: &str| fault.device.as_ref().is_none_or(|d| d == device);
+        let mut alerts = Vec::new();
+        let mut active = self.faults_active.lock().await;
+
+        if let Some(flatline_secs) = fault.flatline_secs {
+            let since = now - TimeDelta::seconds(flatline_secs as i64);
+            // the series has to cover the whole period, a sensor that just started is not stuck
+            let (since, covered, until) = (timestamp(since), timestamp(since + MAX_HOLD), timestamp(now));
+            for sensor in [SensorKind::Temperature, SensorKind::Humidity] {
+                let name = sensor.as_str();
+                let rows: Vec<SeriesRange> = sqlx::query_as(
+                    "select device, count(*) as readings, min(value) as low, max(value) as high, cast(min(created_at) as text) as first
+                    from readings where sensor = $1 and created_at >= datetime($2) and created_at <= datetime($3) group by device",
+                )
+                .bind(name)
+                .bind(&since)
+                .bind(&until)
+                .fetch_all(db_pool)
+                .await?;
+                let prefix = format!("flatline/{}/", name);
+                let mut devices: HashSet<String> = active.iter().filter_map(|key| key.strip_prefix(&prefix).map(str::to_string)).collect();
+                for row in rows.iter().filter(|row| applies(&row.device)) {
+                    devices.remove(&row.device);
+                    let stuck = row.readings >= 2 && row.low == row.high && row.first <= covered;
+                    if update_fault(&mut active, format!("{}{}", prefix, row.device), stuck) {
+                        alerts.push(fault_alert(&row.device, &format!(
+                            "{} on {} has not changed from {} for {:.1} hours, the sensor may be stuck",
+                            name, row.device, row.low, flatline_secs as f64 / 3600.0,
+                        )));
+                    }
+                }
+                // devices without data in the period are not stuck, the silent check covers them
+                for device in devices {
+                    update_fault(&mut active, format!("{}{}", prefix, device), false);
+                }
+            }
+        }
+
+        if fault.silent_secs.is_none() && fault.no_motion_secs.is_none() {
+            return Ok(alerts);
+        }
+        let until = timestamp(now);
+        let devices: Vec<SensorTimes> = sqlx::query_as(
+            "select device, cast(min(created_at) as text) as first, cast(max(created_at) as text) as last,
+            cast(max(case when sensor in ('temperature', 'humidity') then created_at end) as text) as last_dht,
+            cast(min(case when sensor = 'motion' then created_at end) as text) as first_motion,
+            cast(max(case when sensor = 'motion' then created_at end) as text) as last_motion,
+            cast(max(case when sensor = 'motion' and value = 1 then created_at end) as text) as last_detected
+            from readings where sensor in ('temperature', 'humidity', 'motion', 'contact') and created_at <= datetime($1) group by device",
+        )
+        .bind(until)
+        .fetch_all(db_pool)
+        .await?;
+        for row in devices.iter().filter(|row| applies(&row.device)) {
+            if let Some(silent_secs) = fault.silent_secs {
+                let since = timestamp(now - TimeDelta::seconds(silent_secs as i64));
+                // only devices that were known for the whole period, are still sending, and have a dht at all
+                let silent = row.first <= since && row.last >= since && row.last_dht.as_ref().is_some_and(|last| *last < since);
+                if update_fault(&mut active, format!("silent/{}", row.device), silent) {
+                    alerts.push(fault_alert(&row.device, &format!(
+                        "{} has not sent temperature or humidity for {:.0} minutes while its other sensors kept sending, \
+                        the DHT sensor may be failing to read or disconnected",
+                        row.device, silent_secs as f64 / 60.0,
+                    )));
+                }
+            }
+            if let Some(no_motion_secs) = fault.no_motion_secs {
+                let since = timestamp(now - TimeDelta::seconds(no_motion_secs as i64));
+                let quiet = row.first_motion.as_ref().is_some_and(|first| *first <= since)
+                    && row.last_motion.as_ref().is_some_and(|last| *last >= since)
+                    && row.last_detected.as_ref().is_none_or(|last| *last < since);
+                if update_fault(&mut active, format!("no_motion/{}", row.device), quiet) {
+                    alerts.push(fault_alert(&row.device, &format!(
+                        "no motion was detected on {} for {:.1} hours, the motion sensor may be blocked or broken",
+                        row.device, no_motion_secs as f64 / 3600.0,
+                    )));
+                }
+            }
+        }
+        Ok(alerts)
+    }
+
+    // arms or disarms the intrusion rule, kept across config reloads
+    pub async fn set_armed(&self, armed: bool) {
+        println!("Intrusion rule {}", if armed { "armed" } else { "disarmed" });
+        *self.armed.lock().await = Some(armed);
+    }
+
+    pub async fn is_armed(&self) -> bool {
+        let settings = self.settings();
+        let Some(intrusion) = &settings.intrusion else { return false };
+        self.armed.lock().await.unwrap_or(intrusion.armed)
+    }
+
+    // returns the alerts for motion or a door opening at `at`
+    // while armed, door openings are held back until `entry_secs` passed, see `expire_events`
+    pub async fn check_event(&self, device: &str, sensor: SensorKind, at: DateTime<Utc>) -> Vec<Alert> {
+        let settings = self.settings();
+        let Some(intrusion) = settings.intrusion.as_ref().filter(|i| i.devices.is_empty() || i.devices.iter().any(|d| d == device)) else {
+            return vec![event_alert(device, sensor)];
+        };
+        if !self.is_armed().await {
+            return vec![event_alert(device, sensor)];
+        }
+        let mut events = self.events.lock().await;
+        if sensor == SensorKind::Contact {
+            events.pending_doors.push((device.to_string(), at));
+            events.last_door = events.last_door.max(Some(at));
+            return Vec::new();
+        }
+
+        // every door opened shortly before is part of the same intrusion
+        let entry = TimeDelta::seconds(intrusion.entry_secs as i64);
+        let (entered, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut events.pending_doors)
+            .into_iter()
+            .partition(|(_, opened)| *opened <= at && at - *opened <= entry);
+        events.pending_doors = waiting;
+        if let Some((door, opened)) = entered.last() {
+            let alert = Alert::new(
+                AlertKind::Intrusion,
+                device,
+                "Probable intrusion",
+                &format!(
+                    "contact on {} opened, then motion was detected on {} {} seconds later while armed",
+                    door, device, (at - *opened).num_seconds()
+                ),
+            );
+            return vec![alert.with_severity(Severity::High)];
+        }
+
+        let presence = TimeDelta::seconds(intrusion.presence_secs as i64);
+        if events.last_door.is_some_and(|door| door <= at && at - door <= presence) {
+            return vec![event_alert(device, sensor)];
+        }
+        let alert = Alert::new(
+            AlertKind::Intrusion,
+            device,
+            "Motion without entry",
+            &format!(
+                "motion was detected on {} while armed, with no door opened in the last {:.0} minutes, someone may already be inside",
+                device, presence.num_seconds() as f64 / 60.0
+            ),
+        );
+        vec![alert.with_severity(Severity::High)]
+    }
+
+    // returns the held back contact alerts of doors opened more than `entry_secs` before `now` without motion following
+    pub async fn expire_events(&self, now: DateTime<Utc>) -> Vec<Alert> {
+        let entry = self.settings().intrusion.as_ref().map_or(0, |i| i.entry_secs);
+        let entry = TimeDelta::seconds(entry as i64);
+        let mut events = self.events.lock().await;
+        let (expired, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut events.pending_doors)
+            .into_iter()
+            .partition(|(_, opened)| now - *opened > entry);
+        events.pending_doors = waiting;
+        expired.into_iter().map(|(device, _)| event_alert(&device, SensorKind::Contact)).collect()
+    }
+
+    // pairs temperature and humidity of a device, so metrics can be derived from both
+    pub fn pair(&self, device: &str, sensor: SensorKind, value: f64, read_at: DateTime<Utc>) -> Option<Pair> {
+        self.pairs.add(device, sensor, value, read_at)
+    }
+
+    // records that a device sent something, so it is not reported as offline
+    pub async fn seen(&self, device: &str) {
+        let mut activity = self.activity.lock().await;
+        let entry = activity.entry(device.to_string()).or_insert(DeviceActivity {
+            last_seen: Instant::now(),
+            offline: false,
+        });
+        if entry.offline {
+            println!("Device {} is back online", device);
+        }
+        entry.last_seen = Instant::now();
+        entry.offline = false;
+    }
+
+    // returns an offline alert for each device that went silent since the last check
+    pub async fn check_offline(&self) -> Vec<Alert> {
+        let Some(after) = self.settings().offline_after else { return Vec::new() };
+        let mut activity = self.activity.lock().await;
+        let mut alerts = Vec::new();
+        for (device, state) in activity.iter_mut() {
+            if !state.offline && state.last_seen.elapsed() >= after {
+                state.offline = true;
+                alerts.push(Alert::new(
+                    AlertKind::Offline,
+                    device,
+                    "Device offline",
+                    &format!("{} has not sent any data for {} seconds", device, after.as_secs()),
+                ));
+            }
+        }
+        alerts
+    }
+
+    // evaluates the sustained rules over the history stored up to `now`, returning alerts for rules that started matching
+    pub async fn check_sustained(&self, db_pool: &AnyPool, now: DateTime<Utc>) -> Result<Vec<Alert>, sqlx::Error> {
+        let settings = self.settings();
+        let mut alerts = Vec::new();
+        let mut active = self.sustained_active.lock().await;
+        for rule in &settings.sustained {
+            let window = TimeDelta::seconds(rule.window_secs as i64);
+            let (since, until) = (timestamp(now - window), timestamp(now));
+            let sensor = rule.sensor.as_str();
+            let rows: Vec<(String, f64, Option<String>)> = sqlx::query_as(
+                "select device, value, cast(created_at as text) from readings where sensor = $1 and created_at >= datetime($2) and created_at <= datetime($3)
+                order by device, created_at",
+            )
+            .bind(sensor)
+            .bind(since)
+            .bind(until)
+            .fetch_all(db_pool)
+            .await?;
+
+            let mut history: HashMap<String, Vec<(DateTime<Utc>, f64)>> = HashMap::new();
+            for (device, value, created_at) in rows {
+                if rule.device.as_ref().is_none_or(|d| *d == device)
+                    && let Some(created_at) = created_at.as_deref().and_then(parse_timestamp)
+                {
+                    history.entry(device).or_default().push((created_at.and_utc(), value));
+                }
+            }
+            // devices without data in the window recover as well
+            let mut devices: HashSet<String> = history.keys().cloned().collect();
+            devices.extend(active.iter().filter_map(|key| key.strip_prefix(&rule.key("")).map(str::to_string)));
+
+            for device in devices {
+                let readings = history.get(&device).map(Vec::as_slice).unwrap_or_default();
+                let outside = time_outside(readings, now, |value| rule.is_outside(value));
+                let key = rule.key(&device);
+                if outside >= TimeDelta::seconds(rule.min_secs as i64) {
+                    if active.insert(key) {
+                        alerts.push(rule.alert(&device, outside));
+                    }
+                } else if active.remove(&key) {
+                    println!("{} on {} recovered", rule.alert.as_str(), device);
+                }
+            }
+        }
+        Ok(alerts)
+    }
+}
+
+impl SustainedRule {
+    fn is_outside(&self, value: f64) -> bool {
+        self.above.is_some_and(|above| value > above) || self.below.is_some_and(|below| value < below)
+    }
+
+    // identifies the rule and device in the set of active rules
+    fn key(&self, device: &str) -> String {
+        format!("{}/{}/{:?}/{:?}/{}", self.alert.as_str(), self.sensor.as_str(), self.above, self.below, device)
+    }
+
+    fn alert(&self, device: &str, outside: TimeDelta) -> Alert {
+        let subject = match self.alert {
+            AlertKind::Condensation => "Condensation risk",
+            _ => "Mold risk",
+        };
+        let limit = match (self.above, self.below) {
+            (Some(above), Some(below)) => format!("outside {} to {}", below, above),
+            (Some(above), None) => format!("above {}", above),
+            (_, below) => format!("below {}", below.unwrap_or_default()),
+        };
+        Alert::new(
+            self.alert,
+            device,
+            subject,
+            &format!(
+                "{} on {} was {} for {:.1} hours in the last {:.1} hours",
+                self.sensor.as_str(),
+                device,
+                limit,
+                outside.num_seconds() as f64 / 3600.0,
+                self.window_secs as f64 / 3600.0,
+            ),
+        )
+        .with_rule(self.name.as_deref())
+    }
+}
+
+// values of a temperature or humidity series over the flatline period
+#[derive(FromRow)]
+struct SeriesRange {
+    device: String,
+    readings: i64,
+    low: f64,
+    high: f64,
+    first: String,
+}
+
+// first and last times a device sent its sensors, compared with the fault periods
+#[derive(FromRow)]
+struct SensorTimes {
+    device: String,
+    first: String,
+    last: String,
+    last_dht: Option<String>,
+    first_motion: Option<String>,
+    last_motion: Option<String>,
+    last_detected: Option<String>,
+}
+
+// marks a fault as active or recovered, returning true when it just became active
+fn update_fault(active: &mut HashSet<String>, key: String, faulty: bool) -> bool {
+    if faulty {
+        return active.insert(key);
+    }
+    if active.remove(&key) {
+        println!("Sensor fault {} recovered", key);
+    }
+    false
+}
+
+// marks a rate or trend rule as matching or not, returning true when it just started matching
+fn update_active(active: &mut HashSet<String>, key: String, matching: bool) -> bool {
+    if matching {
+        active.insert(key)
+    } else {
+        active.remove(&key);
+        false
+    }
+}
+
+// readings of a device and metric within the window ending at `until`, oldest first
+pub(crate) async fn history(
+    db_pool: &AnyPool,
+    device: &str,
+    metric: Metric,
+    until: DateTime<Utc>,
+    window_secs: u64,
+) -> Result<Vec<(DateTime<Utc>, f64)>, sqlx::Error> {
+    let sensor = metric.as_str();
+    let (since, until) = (timestamp(until - TimeDelta::seconds(window_secs as i64)), timestamp(until));
+    let rows: Vec<(f64, Option<String>)> = sqlx::query_as(
+        "select value, cast(created_at as text) from readings
+        where device = $1 and sensor = $2 and created_at >= datetime($3) and created_at <= datetime($4) order by created_at, id",
+    )
+    .bind(device)
+    .bind(sensor)
+    .bind(since)
+    .bind(until)
+    .fetch_all(db_pool)
+    .await?;
+    Ok(rows.into_iter().filter_map(|(value, created_at)| Some((parse_timestamp(&created_at?)?.and_utc(), value))).collect())
+}
+
+impl RateRule {
+    fn key(&self, device: &str, direction: Direction) -> String {
+        format!("rate/{}/{:?}/{:?}/{}/{}/{}", self.sensor.as_str(), self.rise, self.fall, self.window_secs, direction.as_str(), device)
+    }
+}
+
+impl TrendRule {
+    fn key(&self, device: &str) -> String {
+        format!("trend/{}/{}/{}/{}", self.sensor.as_str(), self.direction.as_str(), self.window_secs, device)
+    }
+}
+
+// plain alert for motion or a door opening
+fn event_alert(device: &str, sensor: SensorKind) -> Alert {
+    match sensor {
+        SensorKind::Motion => Alert::new(AlertKind::Motion, device, "Motion alert", "Motion was detected!"),
+        _ => Alert::new(AlertKind::Contact, device, "Contact alert", "Contact sensor was detected!"),
+    }
+}
+
+// sensor faults are a kind of their own, so they can be routed apart from security alerts
+fn fault_alert(device: &str, body: &str) -> Alert {
+    Alert::new(AlertKind::Fault, device, "Sensor fault", body)
+}
+
+// total time the readings were outside the limits, each reading counting until the next one,
+// the end of the window or MAX_HOLD, whichever comes first
+pub fn time_outside(readings: &[(DateTime<Utc>, f64)], until: DateTime<Utc>, is_outside: impl Fn(f64) -> bool) -> TimeDelta {
+    readings
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, value))| is_outside(*value))
+        .map(|(i, (at, _))| {
+            let next = readings.get(i + 1).map_or(until, |(next, _)| *next);
+            (next.min(until) - *at).clamp(TimeDelta::zero(), MAX_HOLD)
+        })
+        .sum()
+}
+
+// periodically raises sustained rule alerts, runs for the whole lifetime of the server
+pub async fn watch_sustained(db_pool: AnyPool, rules: Arc<Rules>, alerter: Arc<Alerter>) {
+    loop {
+        tokio::time::sleep(SUSTAINED_INTERVAL).await;
+        match rules.check_sustained(&db_pool, Utc::now()).await {
+            Ok(alerts) => {
+                for alert in alerts {
+                    alerter.raise(&alert).await;
+                }
+            }
+            Err(e) => eprintln!("Failed to evaluate sustained rules: {}", e),
+        }
+    }
+}
+
+// periodically raises sensor fault alerts, runs for the whole lifetime of the server
+pub async fn watch_faults(db_pool: AnyPool, rules: Arc<Rules>, alerter: Arc<Alerter>) {
+    loop {
+        tokio::time::sleep(SUSTAINED_INTERVAL).await;
+        match rules.check_faults(&db_pool, Utc::now()).await {
+            Ok(alerts) => {
+                for alert in alerts {
+                    alerter.raise(&alert).await;
+                }
+            }
+            Err(e) => eprintln!("Failed to evaluate fault rules: {}", e),
+        }
+    }
+}
+
+// raises the contact alerts held back by the intrusion rule once nobody entered, runs for the whole lifetime of the server
+pub async fn watch_events(rules: Arc<Rules>, alerter: Arc<Alerter>) {
+    loop {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        for alert in rules.expire_events(Utc::now()).await {
+            alerter.raise(&alert).await;
+        }
+    }
+}
+
+// periodically raises offline alerts, runs for the whole lifetime of the server
+pub async fn watch_offline(rules: Arc<Rules>, alerter: Arc<Alerter>) {
+    loop {
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        for alert in rules.check_offline().await {
+            alerter.raise(&alert).await;
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+use sqlx::AnyPool;
+
+use crate::AppError;
+use crate::actions::{check_topic, default_min_interval_secs};
+use crate::alerts::Severity;
+use crate::config::{Config, Metric, ScriptRule};
+use crate::rules::history;
+use crate::timestamp;
+
+// something a script asked for while handling a reading, carried out by the subscriber once the script finished
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptAction {
+    Alert { subject: String, body: String, severity: Severity },
+    // `script` is the path of the script, which keeps the rate limits of scripts with the same file name apart
+    Publish { script: PathBuf, topic: String, payload: String, retain: bool },
+    Annotate { text: String },
+}
+
+// stored reading the scripts run for
+#[derive(Debug, Clone, Copy)]
+pub struct ScriptReading<'a> {
+    pub device: &'a str,
+    pub metric: Metric,
+    pub value: f64,
+    pub at: DateTime<Utc>,
+}
+
+// compiled script with an engine of its own, so limits and collected actions are never shared between scripts
+struct Script {
+    name: String,
+    rule: ScriptRule,
+    engine: Engine,
+    ast: AST,
+    actions: Arc<Mutex<Vec<ScriptAction>>>,
+}
+
+impl Script {
+    fn load(rule: &ScriptRule, subscriptions: &Arc<HashSet<String>>) -> Result<Self, String> {
+        let name = rule.path.file_stem().map_or_else(|| rule.path.display().to_string(), |stem| stem.to_string_lossy().to_string());
+        let source = std::fs::read_to_string(&rule.path).map_err(|e| format!("{}: {}", rule.path.display(), e))?;
+        let actions = Arc::new(Mutex::new(Vec::new()));
+        let engine = engine(&name, &rule.path, rule.max_operations, &actions, subscriptions);
+        let ast = engine.compile(&source).map_err(|e| format!("{}: {}", rule.path.display(), e))?;
+        Ok(Self { name, rule: rule.clone(), engine, ast, actions })
+    }
+
+    fn applies(&self, reading: &ScriptReading) -> bool {
+        self.rule.device.as_ref().is_none_or(|d| d == reading.device)
+            && (self.rule.sensors.is_empty() || self.rule.sensors.contains(&reading.metric))
+    }
+
+    // actions are only returned when the script ran to the end
+    fn run(&self, mut scope: Scope) -> Result<Vec<ScriptAction>, String> {
+        self.actions.lock().unwrap().clear();
+        let result = self.engine.run_ast_with_scope(&mut scope, &self.ast);
+        let actions = std::mem::take(&mut *self.actions.lock().unwrap());
+        result.map(|_| actions).map_err(|e| e.to_string())
+    }
+}
+
+// sandboxed engine, scripts have no access to files, the network or the database, and are limited in time and size
+// publishing on a topic the server subscribes to fails the script, each reading stored from it would run the script again
+fn engine(name: &str, path: &Path, max_operations: u64, actions: &Arc<Mutex<Vec<ScriptAction>>>, subscriptions: &Arc<HashSet<String>>) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(max_operations);
+    engine.set_max_call_levels(32);
+    engine.set_max_expr_depths(64, 32);
+    engine.set_max_string_size(10_000);
+    engine.set_max_array_size(10_000);
+    engine.set_max_map_size(1_000);
+
+    let prefix = name.to_string();
+    engine.on_print(move |text| println!("[script {}] {}", prefix, text));
+    let prefix = name.to_string();
+    engine.on_debug(move |text, _, position| println!("[script {}] {}: {}", prefix, position, text));
+
+    let sink = actions.clone();
+    engine.register_fn("alert", move |subject: &str, body: &str| {
+        push(&sink, ScriptAction::Alert { subject: subject.to_string(), body: body.to_string(), severity: Severity::Normal });
+    });
+    let sink = actions.clone();
+    engine.register_fn("alert", move |subject: &str, body: &str, severity: &str| -> Result<(), Box<EvalAltResult>> {
+        let severity = match severity {
+            "normal" => Severity::Normal,
+            "high" => Severity::High,
+            other => return Err(format!("unknown severity '{}', use \"normal\" or \"high\"", other).into()),
+        };
+        push(&sink, ScriptAction::Alert { subject: subject.to_string(), body: body.to_string(), severity });
+        Ok(())
+    });
+    let (sink, topics, script) = (actions.clone(), subscriptions.clone(), path.to_path_buf());
+    engine.register_fn("publish", move |topic: &str, payload: &str| -> Result<(), Box<EvalAltResult>> {
+        check_topic(topic, &topics)?;
+        push(&sink, ScriptAction::Publish { script: script.clone(), topic: topic.to_string(), payload: payload.to_string(), retain: false });
+        Ok(())
+    });
+    let (sink, topics, script) = (actions.clone(), subscriptions.clone(), path.to_path_buf());
+    engine.register_fn("publish", move |topic: &str, payload: &str, retain: bool| -> Result<(), Box<EvalAltResult>> {
+        check_topic(topic, &topics)?;
+        push(&sink, ScriptAction::Publish { script: script.clone(), topic: topic.to_string(), payload: payload.to_string(), retain });
+        Ok(())
+    });
+    let sink = actions.clone();
+    engine.register_fn("annotate", move |text: &str| {
+        push(&sink, ScriptAction::Annotate { text: text.to_string() });
+    });
+    engine
+}
+
+fn push(actions: &Mutex<Vec<ScriptAction>>, action: ScriptAction) {
+    actions.lock().unwrap().push(action);
+}
+
+// scripts loaded from the config, swapped as a whole when the config gets reloaded
+pub struct ScriptSettings {
+    scripts: Vec<Arc<Script>>,
+}
+
+impl ScriptSettings {
+    // fails when a script cannot be read or does not compile, so a broken script is noticed before it is needed
+    pub fn new(config: &Config) -> Result<Self, AppError> {
+        let subscriptions = Arc::new(config.subscriptions());
+        let scripts = config.rules.script.iter()
+            .map(|rule| Script::load(rule, &subscriptions).map(Arc::new))
+            .collect::<Result<_, _>>()
+            .map_err(AppError::Script)?;
+        Ok(Self { scripts })
+    }
+}
+
+// runs the user rule scripts for each stored reading
+// a script sees the reading as `reading` (device, sensor, value, unit, at), the readings of the same device and metric
+// within `history_secs` as `history` (value, at and secs_ago each, oldest first and ending with this reading),
+// and the last value of every metric of the device as `latest`
+// it can call alert(subject, body[, severity]), publish(topic, payload[, retain]) and annotate(text),
+// publishes go out through the actions, at most once per `min_interval_secs` for each topic
+pub struct Scripts {
+    settings: RwLock<Arc<ScriptSettings>>,
+}
+
+impl Scripts {
+    pub fn new(config: &Config) -> Result<Self, AppError> {
+        Ok(Self { settings: RwLock::new(Arc::new(ScriptSettings::new(config)?)) })
+    }
+
+    pub fn reload(&self, settings: ScriptSettings) {
+        *self.settings.write().unwrap() = Arc::new(settings);
+    }
+
+    pub fn applies(&self, reading: &ScriptReading<'_>) -> bool {
+        self.settings.read().unwrap().scripts.iter().any(|script| script.applies(reading))
+    }
+
+    // how often the script at `path` may publish on the same topic, the default for scripts no longer loaded
+    pub fn min_interval(&self, path: &Path) -> Duration {
+        let settings = self.settings.read().unwrap().clone();
+        let secs = settings.scripts.iter().find(|s| s.rule.path == path).map(|s| s.rule.min_interval_secs);
+        Duration::from_secs(secs.unwrap_or_else(default_min_interval_secs))
+    }
+
+    // returns the actions of every script that applies, by script name
+    // a failing script is logged and its actions dropped, the other scripts are not affected
+    pub async fn run(&self, db_pool: &AnyPool, reading: &ScriptReading<'_>) -> Result<Vec<(String, ScriptAction)>, sqlx::Error> {
+        let settings = self.settings.read().unwrap().clone();
+        let scripts: Vec<_> = settings.scripts.iter().filter(|script| script.applies(reading)).collect();
+        if scripts.is_empty() {
+            return Ok(Vec::new());
+        }
+        let latest = latest(db_pool, reading.device).await?;
+        let mut actions = Vec::new();
+        for script in scripts {
+            let history = history(db_pool, reading.device, reading.metric, reading.at, script.rule.history_secs).await?;
+            let history: Array = history
+                .into_iter()
+                .map(|(at, value)| {
+                    let mut entry = Map::new();
+                    entry.insert("value".into(), Dynamic::from(value));
+                    entry.insert("at".into(), Dynamic::from(timestamp(at)));
+                    entry.insert("secs_ago".into(), Dynamic::from((reading.at - at).num_seconds()));
+                    Dynamic::from(entry)
+                })
+                .collect();
+            let mut scope = Scope::new();
+            scope.push_constant("reading", reading_map(reading));
+            scope.push_constant("history", history);
+            scope.push_constant("latest", latest.clone());
+            match script.run(scope) {
+                Ok(script_actions) => actions.extend(script_actions.into_iter().map(|action| (script.name.clone(), action))),
+                Err(e) => eprintln!("Script {} failed on {} {}: {}", script.name, reading.device, reading.metric.as_str(), e),
+            }
+        }
+        Ok(actions)
+    }
+}
+
+fn reading_map(reading: &ScriptReading) -> Map {
+    let mut map = Map::new();
+    map.insert("device".into(), Dynamic::from(reading.device.to_string()));
+    map.insert("sensor".into(), Dynamic::from(reading.metric.as_str().to_string()));
+    map.insert("value".into(), Dynamic::from(reading.value));
+    map.insert("unit".into(), Dynamic::from(reading.metric.unit().to_string()));
+    map.insert("at".into(), Dynamic::from(timestamp(reading.at)));
+    map
+}
+
+// last value of each metric of the device
+async fn latest(db_pool: &AnyPool, device: &str) -> Result<Map, sqlx::Error> {
+    let rows: Vec<(String, f64)> = sqlx::query_as(
+        "select sensor, value from readings r
+        where device = $1 and id = (select max(id) from readings where device = r.device and sensor = r.sensor)",
+    )
+    .bind(device)
+    .fetch_all(db_pool)
+    .await?;
+    Ok(rows.into_iter().map(|(sensor, value)| (sensor.into(), Dynamic::from(value))).collect())
+}
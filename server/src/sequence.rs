@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use chrono::Utc;
+use sqlx::AnyPool;
+use tokio::sync::Mutex;
+
+use crate::timestamp;
+use crate::writer::{Write, Writer};
+
+// outcome of checking a sequence number against the last one seen for the device and sensor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequenceCheck {
+    // first message from the device and sensor
+    First,
+    // next message, `missing` messages were skipped since the last one
+    InOrder { missing: u32 },
+    // the device rebooted and started numbering again
+    Restarted,
+    // same number as the last message, e.g. resent after a reconnect
+    Duplicate,
+    // lower number than the last message without a reboot in between
+    Replay,
+}
+
+impl SequenceCheck {
+    pub fn classify(last_seq: Option<u32>, last_uptime: Option<Duration>, seq: u32, uptime: Option<Duration>) -> Self {
+        let Some(last_seq) = last_seq else {
+            return Self::First;
+        };
+        // the uptime only goes back after a reboot, a resent message is always sent later than the original
+        if let (Some(last_uptime), Some(uptime)) = (last_uptime, uptime) && uptime < last_uptime {
+            return Self::Restarted;
+        }
+        match seq.cmp(&last_seq) {
+            std::cmp::Ordering::Greater => Self::InOrder { missing: seq - last_seq - 1 },
+            std::cmp::Ordering::Equal => Self::Duplicate,
+            std::cmp::Ordering::Less => Self::Replay,
+        }
+    }
+
+    // duplicates and replays are dropped instead of being stored again
+    pub fn is_accepted(&self) -> bool {
+        !matches!(self, Self::Duplicate | Self::Replay)
+    }
+}
+
+// last sequence number and uptime of each device and sensor, read from sequence_stats the first time they are seen
+// the subscriber is the only writer of sequence_stats, so the counters can go through the write queue
+#[derive(Default)]
+pub struct Sequences {
+    last: Mutex<HashMap<(String, String), LastSeen>>,
+}
+
+// sequence number and uptime of the last accepted message
+type LastSeen = (u32, Option<Duration>);
+
+impl Sequences {
+    // checks the sequence number against the last one and queues the updated counters
+    pub async fn check(
+        &self,
+        db_pool: &AnyPool,
+        writer: &Writer,
+        device: &str,
+        sensor: &str,
+        seq: u32,
+        uptime: Option<Duration>,
+    ) -> Result<SequenceCheck, sqlx::Error> {
+        let mut last = self.last.lock().await;
+        let key = (device.to_string(), sensor.to_string());
+        let previous = match last.get(&key) {
+            Some(previous) => Some(*previous),
+            None => stored(db_pool, device, sensor).await?,
+        };
+        let (last_seq, last_uptime) = (previous.map(|(seq, _)| seq), previous.and_then(|(_, uptime)| uptime));
+        let check = SequenceCheck::classify(last_seq, last_uptime, seq, uptime);
+
+        // the last number only moves forward with accepted messages
+        let stored = if check.is_accepted() { Some((seq, uptime)) } else { previous };
+        if let Some(stored) = stored {
+            last.insert(key, stored);
+        }
+        let missing = match check {
+            SequenceCheck::InOrder { missing } => i64::from(missing),
+            _ => 0,
+        };
+        writer.write(Write::Sequence {
+            device: device.to_string(),
+            sensor: sensor.to_string(),
+            last_seq: stored.map(|(seq, _)| i64::from(seq)),
+            last_uptime_ms: stored.and_then(|(_, uptime)| uptime).map(|uptime| uptime.as_millis() as i64),
+            received: i64::from(check.is_accepted()),
+            missing,
+            duplicates: i64::from(check == SequenceCheck::Duplicate),
+            replays: i64::from(check == SequenceCheck::Replay),
+            restarts: i64::from(check == SequenceCheck::Restarted),
+            updated_at: timestamp(Utc::now()),
+        }).await;
+        Ok(check)
+    }
+}
+
+async fn stored(db_pool: &AnyPool, device: &str, sensor: &str) -> Result<Option<LastSeen>, sqlx::Error> {
+    let row: Option<(i64, Option<i64>)> = sqlx::query_as("select last_seq, last_uptime_ms from sequence_stats where device = $1 and sensor = $2")
+        .bind(device)
+        .bind(sensor)
+        .fetch_optional(db_pool)
+        .await?;
+    Ok(row.map(|(seq, uptime_ms)| (seq as u32, uptime_ms.map(|ms| Duration::from_millis(ms as u64)))))
+}
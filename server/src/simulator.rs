@@ -0,0 +1,362 @@
+use std::path::Path;
+use std::time::Duration;
+use iiot_protocol::{Command, Reading, SensorKind, Topic};
+use serde::Deserialize;
+
+use crate::config::ConfigError;
+
+// virtual time between two ticks, the firmware ui loop runs at a similar rate
+pub const TICK_MS: u64 = 100;
+
+// real time between two ticks at the given speed, None unless the speed is a finite number above zero
+// and slow enough to leave a tick some time, as the interval it paces cannot have a zero period
+pub fn tick_period(speed: f64) -> Option<Duration> {
+    if !(speed.is_finite() && speed > 0.0) {
+        return None;
+    }
+    Duration::try_from_secs_f64(TICK_MS as f64 / 1000.0 / speed).ok().filter(|period| !period.is_zero())
+}
+
+// device settings, the defaults match the firmware
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeviceSettings {
+    pub name: String,
+    pub firmware: String,
+    pub dht_delay_secs: u64,
+    pub motion_delay_secs: u64,
+    pub contact_delay_secs: u64,
+    // starting environment
+    pub temperature: f64,
+    pub humidity: f64,
+}
+
+impl Default for DeviceSettings {
+    fn default() -> Self {
+        Self {
+            name: iiot_protocol::DEFAULT_DEVICE.to_string(),
+            firmware: "simulator".to_string(),
+            dht_delay_secs: 2,
+            motion_delay_secs: 2,
+            contact_delay_secs: 2,
+            temperature: 21.0,
+            humidity: 45.0,
+        }
+    }
+}
+
+// random traffic, generated on top of the scripted steps
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RandomSettings {
+    pub seed: Option<u64>,
+    pub motion_per_hour: f64,
+    pub contact_per_hour: f64,
+    // largest change between two dht reads
+    pub temperature_drift: f64,
+    pub humidity_drift: f64,
+}
+
+impl Default for RandomSettings {
+    fn default() -> Self {
+        Self { seed: None, motion_per_hour: 12.0, contact_per_hour: 4.0, temperature_drift: 0.2, humidity_drift: 0.5 }
+    }
+}
+
+// scripted change at a point of the simulated uptime, every field is optional
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Step {
+    pub at_secs: f64,
+    // triggers the sensor, like the interrupt on the board
+    pub motion: bool,
+    pub contact: bool,
+    // sets the environment, random drift continues from there
+    pub temperature: Option<f64>,
+    pub humidity: Option<f64>,
+    // same switches as in the firmware menu
+    pub mqtt: Option<bool>,
+    pub dht_enabled: Option<bool>,
+    pub motion_enabled: Option<bool>,
+    pub contact_enabled: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scenario {
+    pub device: DeviceSettings,
+    // no random traffic when missing
+    pub random: Option<RandomSettings>,
+    // runs until stopped when missing
+    pub duration_secs: Option<f64>,
+    #[serde(rename = "step")]
+    pub steps: Vec<Step>,
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let content = std::fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+        Self::parse(&content).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+    }
+
+    pub fn parse(content: &str) -> Result<Self, toml::de::Error> {
+        let mut scenario: Self = toml::from_str(content)?;
+        scenario.steps.sort_by(|a, b| a.at_secs.total_cmp(&b.at_secs));
+        Ok(scenario)
+    }
+}
+
+// message the device would publish
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub topic: String,
+    pub payload: String,
+}
+
+// small xorshift generator, so random scenarios can be repeated from a seed
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // splitmix64 step, xorshift output stays tiny for a while when started from a small seed
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        Self((z ^ (z >> 31)).max(1))
+    }
+
+    // uniform in [0, 1)
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn between(&mut self, low: f64, high: f64) -> f64 {
+        low + (high - low) * self.next()
+    }
+}
+
+// range the firmware menu and commands keep the delays in
+const MIN_DELAY_SECS: u64 = 2;
+const MAX_DELAY_SECS: u64 = 30;
+
+// values are sent with one decimal place, like the dht driver reports them
+fn tenths(value: f64) -> f64 {
+    (value * 10.0).round() / 10.0
+}
+
+// simulated board, following the timing of the firmware ui loop:
+// - dht values are read and sent every dht delay
+// - motion and contact send 1 when triggered, then 0 when nothing happened for their delay plus a second,
+//   repeated for as long as they stay idle
+// - a triggered sensor ignores new triggers for its delay
+// - nothing is sent while mqtt is disabled, and sequence numbers only count sent messages
+// - commands on {device}/command change the delays and switches like the menu does
+pub struct Device {
+    settings: DeviceSettings,
+    random: Option<(RandomSettings, Rng)>,
+    duration_ms: Option<u64>,
+    steps: Vec<Step>,
+    next_step: usize,
+    uptime_ms: u64,
+    temperature: f64,
+    humidity: f64,
+    mqtt_enabled: bool,
+    dht_enabled: bool,
+    motion_enabled: bool,
+    contact_enabled: bool,
+    last_dht: u64,
+    last_motion: u64,
+    last_contact: u64,
+    last_motion_trigger: Option<u64>,
+    last_contact_trigger: Option<u64>,
+    seq: [u32; 4],
+}
+
+impl Device {
+    // `seed` is used when the scenario does not set one
+    pub fn new(scenario: &Scenario, seed: u64) -> Self {
+        let random = scenario.random.clone().map(|random| {
+            let rng = Rng::new(random.seed.unwrap_or(seed));
+            (random, rng)
+        });
+        Self {
+            settings: scenario.device.clone(),
+            random,
+            duration_ms: scenario.duration_secs.map(|secs| (secs * 1000.0) as u64),
+            steps: scenario.steps.clone(),
+            next_step: 0,
+            uptime_ms: 0,
+            temperature: scenario.device.temperature,
+            humidity: scenario.device.humidity,
+            mqtt_enabled: true,
+            dht_enabled: true,
+            motion_enabled: true,
+            contact_enabled: true,
+            last_dht: 0,
+            last_motion: 0,
+            last_contact: 0,
+            last_motion_trigger: None,
+            last_contact_trigger: None,
+            seq: [0; 4],
+        }
+    }
+
+    pub fn uptime_ms(&self) -> u64 {
+        self.uptime_ms
+    }
+
+    pub fn finished(&self) -> bool {
+        self.duration_ms.is_some_and(|duration| self.uptime_ms >= duration)
+    }
+
+    // topic the device takes commands on, like the firmware it listens while mqtt is disabled
+    pub fn command_topic(&self) -> String {
+        Topic::Command { device: &self.settings.name }.to_string()
+    }
+
+    // applies a command the way the firmware does, temperature and humidity share the dht settings
+    pub fn command(&mut self, command: Command) {
+        match command {
+            Command::SetDelay { sensor, secs } => {
+                let secs = u64::from(secs).clamp(MIN_DELAY_SECS, MAX_DELAY_SECS);
+                match sensor {
+                    SensorKind::Temperature | SensorKind::Humidity => self.settings.dht_delay_secs = secs,
+                    SensorKind::Motion => self.settings.motion_delay_secs = secs,
+                    SensorKind::Contact => self.settings.contact_delay_secs = secs,
+                }
+            }
+            Command::SetEnabled { sensor, enabled } => match sensor {
+                SensorKind::Temperature | SensorKind::Humidity => self.dht_enabled = enabled,
+                SensorKind::Motion => self.motion_enabled = enabled,
+                SensorKind::Contact => self.contact_enabled = enabled,
+            },
+            Command::SetMqtt { enabled } => self.mqtt_enabled = enabled,
+        }
+    }
+
+    // advances the simulated uptime by one tick, returning the messages sent during it
+    pub fn tick(&mut self) -> Vec<Message> {
+        self.uptime_ms += TICK_MS;
+        let mut messages = Vec::new();
+
+        while let Some(step) = self.steps.get(self.next_step) {
+            if (step.at_secs * 1000.0) as u64 > self.uptime_ms {
+                break;
+            }
+            let step = step.clone();
+            self.next_step += 1;
+            self.apply(&step, &mut messages);
+        }
+        self.random_events(&mut messages);
+
+        if self.dht_enabled && self.elapsed_secs(self.last_dht) >= self.settings.dht_delay_secs {
+            self.drift();
+            self.last_dht = self.uptime_ms;
+            self.send(SensorKind::Temperature, tenths(self.temperature), &mut messages);
+            self.send(SensorKind::Humidity, tenths(self.humidity), &mut messages);
+        }
+        if self.motion_enabled && self.elapsed_secs(self.last_motion) > self.settings.motion_delay_secs {
+            self.last_motion = self.uptime_ms;
+            self.send(SensorKind::Motion, 0.0, &mut messages);
+        }
+        if self.contact_enabled && self.elapsed_secs(self.last_contact) > self.settings.contact_delay_secs {
+            self.last_contact = self.uptime_ms;
+            self.send(SensorKind::Contact, 0.0, &mut messages);
+        }
+        messages
+    }
+
+    fn elapsed_secs(&self, since: u64) -> u64 {
+        (self.uptime_ms - since) / 1000
+    }
+
+    fn apply(&mut self, step: &Step, messages: &mut Vec<Message>) {
+        if let Some(temperature) = step.temperature {
+            self.temperature = temperature;
+        }
+        if let Some(humidity) = step.humidity {
+            self.humidity = humidity;
+        }
+        if let Some(enabled) = step.mqtt {
+            self.mqtt_enabled = enabled;
+        }
+        if let Some(enabled) = step.dht_enabled {
+            self.dht_enabled = enabled;
+        }
+        if let Some(enabled) = step.motion_enabled {
+            self.motion_enabled = enabled;
+        }
+        if let Some(enabled) = step.contact_enabled {
+            self.contact_enabled = enabled;
+        }
+        if step.motion {
+            self.trigger(SensorKind::Motion, messages);
+        }
+        if step.contact {
+            self.trigger(SensorKind::Contact, messages);
+        }
+    }
+
+    fn random_events(&mut self, messages: &mut Vec<Message>) {
+        let Some((random, rng)) = &mut self.random else {
+            return;
+        };
+        let tick_hours = TICK_MS as f64 / 3_600_000.0;
+        let motion = rng.next() < random.motion_per_hour * tick_hours;
+        let contact = rng.next() < random.contact_per_hour * tick_hours;
+        if motion {
+            self.trigger(SensorKind::Motion, messages);
+        }
+        if contact {
+            self.trigger(SensorKind::Contact, messages);
+        }
+    }
+
+    fn drift(&mut self) {
+        if let Some((random, rng)) = &mut self.random {
+            self.temperature += rng.between(-random.temperature_drift, random.temperature_drift);
+            self.humidity = (self.humidity + rng.between(-random.humidity_drift, random.humidity_drift)).clamp(0.0, 100.0);
+        }
+    }
+
+    fn trigger(&mut self, sensor: SensorKind, messages: &mut Vec<Message>) {
+        let (enabled, delay, last_trigger) = match sensor {
+            SensorKind::Motion => (self.motion_enabled, self.settings.motion_delay_secs, &mut self.last_motion_trigger),
+            _ => (self.contact_enabled, self.settings.contact_delay_secs, &mut self.last_contact_trigger),
+        };
+        // the sensor task sleeps for the delay after each trigger
+        if !enabled || last_trigger.is_some_and(|last| (self.uptime_ms - last) / 1000 < delay) {
+            return;
+        }
+        *last_trigger = Some(self.uptime_ms);
+        match sensor {
+            SensorKind::Motion => self.last_motion = self.uptime_ms,
+            _ => self.last_contact = self.uptime_ms,
+        }
+        self.send(sensor, 1.0, messages);
+    }
+
+    fn send(&mut self, sensor: SensorKind, value: f64, messages: &mut Vec<Message>) {
+        if !self.mqtt_enabled {
+            return;
+        }
+        let seq = &mut self.seq[sensor as usize];
+        *seq += 1;
+        let reading = Reading {
+            ts: Some(self.uptime_ms),
+            up: Some(self.uptime_ms),
+            seq: Some(*seq),
+            fw: Some(&self.settings.firmware),
+            ..Reading::new(sensor, value)
+        };
+        let mut buf = [0u8; iiot_protocol::PAYLOAD_LEN];
+        let len = reading.encode(&mut buf).expect("reading fits the payload buffer");
+        messages.push(Message {
+            topic: Topic::Reading { device: &self.settings.name, sensor }.to_string(),
+            payload: String::from_utf8_lossy(&buf[..len]).to_string(),
+        });
+    }
+}
@@ -0,0 +1,219 @@
+// forwards stored readings to other storage backends, each sink runs in a task of its own with a buffer in front of it,
+// so a slow or unreachable backend never holds up the subscriber
+use std::collections::VecDeque;
+use std::future::Future;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use reqwest::StatusCode;
+use thiserror::Error;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::AppError;
+use crate::config::{Config, InfluxConfig};
+
+// readings waiting for a sink task, which moves them into its own buffer right away
+const CHANNEL_SIZE: usize = 1000;
+
+// reading handed to the sinks once it was stored
+#[derive(Debug, Clone, PartialEq)]
+pub struct Point {
+    pub device: String,
+    // sensor or derived metric
+    pub sensor: String,
+    pub unit: String,
+    pub value: f64,
+    pub at: DateTime<Utc>,
+}
+
+// why a write failed, which decides whether the batch is sent again
+#[derive(Debug, Error)]
+pub enum SinkError {
+    // the backend is unreachable or failing, the batch is kept and retried
+    #[error("{0}")]
+    Retry(String),
+    // the backend refused the batch itself, e.g. a malformed point or a bad token, sending it again cannot succeed
+    #[error("{0}")]
+    Rejected(String),
+}
+
+// storage backend readings are forwarded to, a write either takes the whole batch or fails
+pub trait Sink: Send + Sync + 'static {
+    fn name(&self) -> &str;
+    fn write(&self, points: &[Point]) -> impl Future<Output = Result<(), SinkError>> + Send;
+}
+
+// how a sink task batches readings and holds them while the backend is down
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Buffering {
+    pub batch_size: usize,
+    // how long readings may wait for a batch to fill up
+    pub flush: Duration,
+    // readings kept while the backend is down, the oldest are dropped beyond this
+    pub capacity: usize,
+    pub retry: Duration,
+}
+
+// senders to the running sink tasks, dropping them lets the tasks write what is left and stop
+#[derive(Clone, Default)]
+pub struct Sinks {
+    senders: Vec<mpsc::Sender<Point>>,
+}
+
+impl Sinks {
+    // starts a task for every configured sink
+    pub fn start(config: &Config) -> Result<(Self, Vec<JoinHandle<()>>), AppError> {
+        let mut sinks = Self::default();
+        let mut tasks = Vec::new();
+        if let Some(influx) = &config.sinks.influxdb {
+            tasks.push(sinks.spawn(InfluxDb::new(influx)?, influx.buffering()));
+        }
+        Ok((sinks, tasks))
+    }
+
+    pub fn spawn<S: Sink>(&mut self, sink: S, buffering: Buffering) -> JoinHandle<()> {
+        let (sender, receiver) = mpsc::channel(CHANNEL_SIZE);
+        self.senders.push(sender);
+        tokio::spawn(run_sink(sink, receiver, buffering))
+    }
+
+    pub fn send(&self, point: &Point) {
+        for sender in &self.senders {
+            if let Err(e) = sender.try_send(point.clone()) {
+                eprintln!("Dropping reading for a sink: {}", e);
+            }
+        }
+    }
+}
+
+// writes the buffered readings in batches until the senders are gone, then writes what is left once
+pub async fn run_sink<S: Sink>(sink: S, mut receiver: mpsc::Receiver<Point>, buffering: Buffering) {
+    let mut buffer: VecDeque<Point> = VecDeque::new();
+    let mut dropped = 0;
+    let mut open = true;
+    // set while the backend is down, nothing is written before it
+    let mut retry_at = None;
+    let mut flush = tokio::time::interval(buffering.flush);
+    loop {
+        // waits for a full batch, the flush interval or the end of the readings
+        tokio::select! {
+            point = receiver.recv(), if open => match point {
+                Some(point) => {
+                    buffer.push_back(point);
+                    if buffer.len() > buffering.capacity {
+                        buffer.pop_front();
+                        dropped += 1;
+                    }
+                    if buffer.len() < buffering.batch_size {
+                        continue;
+                    }
+                }
+                None => open = false,
+            },
+            _ = flush.tick() => {}
+        }
+        // the last attempt on shutdown does not wait for the retry time
+        if open && retry_at.is_some_and(|at| tokio::time::Instant::now() < at) {
+            continue;
+        }
+        if dropped > 0 {
+            eprintln!("Sink {} dropped {} readings, its buffer is full", sink.name(), dropped);
+            dropped = 0;
+        }
+        while !buffer.is_empty() {
+            let batch: Vec<Point> = buffer.iter().take(buffering.batch_size).cloned().collect();
+            match sink.write(&batch).await {
+                Ok(()) => {
+                    buffer.drain(..batch.len());
+                    retry_at = None;
+                }
+                // dropped rather than retried, so the readings behind it are not stuck until the buffer overflows
+                Err(SinkError::Rejected(e)) => {
+                    eprintln!("Sink {} rejected {} readings, dropping them: {}", sink.name(), batch.len(), e);
+                    buffer.drain(..batch.len());
+                }
+                Err(SinkError::Retry(e)) => {
+                    eprintln!("Sink {} failed to write {} readings, retrying in {}s: {}", sink.name(), batch.len(), buffering.retry.as_secs(), e);
+                    retry_at = Some(tokio::time::Instant::now() + buffering.retry);
+                    break;
+                }
+            }
+        }
+        if !open {
+            if !buffer.is_empty() {
+                eprintln!("Sink {} stopped with {} readings that could not be written", sink.name(), buffer.len());
+            }
+            return;
+        }
+    }
+}
+
+// influxdb line protocol over http, also accepted by other time-series databases such as victoriametrics and questdb
+pub struct InfluxDb {
+    client: reqwest::Client,
+    url: String,
+    token: Option<String>,
+    measurement: String,
+}
+
+impl InfluxDb {
+    pub fn new(config: &InfluxConfig) -> Result<Self, AppError> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()
+            .map_err(|e| AppError::Sink(e.to_string()))?;
+        Ok(Self { client, url: config.url.clone(), token: config.token.clone(), measurement: config.measurement.clone() })
+    }
+}
+
+impl Sink for InfluxDb {
+    fn name(&self) -> &str {
+        "influxdb"
+    }
+
+    async fn write(&self, points: &[Point]) -> Result<(), SinkError> {
+        let body: Vec<String> = points.iter().map(|point| line(&self.measurement, point)).collect();
+        let mut request = self.client.post(&self.url).body(body.join("\n"));
+        if let Some(token) = &self.token {
+            request = request.header("Authorization", format!("Token {}", token));
+        }
+        let response = request.send().await.map_err(|e| SinkError::Retry(e.to_string()))?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        let error = format!("{} {}", status, response.text().await.unwrap_or_default().trim());
+        // client errors other than a timeout or rate limit are answers about the batch, not about the backend
+        if status.is_client_error() && !matches!(status, StatusCode::REQUEST_TIMEOUT | StatusCode::TOO_MANY_REQUESTS) {
+            Err(SinkError::Rejected(error))
+        } else {
+            Err(SinkError::Retry(error))
+        }
+    }
+}
+
+// one reading in line protocol with nanosecond precision, the default of every influxdb version
+// e.g. readings,device=esp32,sensor=temperature,unit=°C value=-5.3 1748779200000000000
+pub fn line(measurement: &str, point: &Point) -> String {
+    let mut line = escape(measurement, &[',', ' ']);
+    for (key, value) in [("device", &point.device), ("sensor", &point.sensor), ("unit", &point.unit)] {
+        // empty tag values are not allowed, binary sensors have no unit
+        if !value.is_empty() {
+            line.push_str(&format!(",{}={}", key, escape(value, &[',', '=', ' '])));
+        }
+    }
+    let nanos = point.at.timestamp_nanos_opt().unwrap_or_default();
+    line.push_str(&format!(" value={:?} {}", point.value, nanos));
+    line
+}
+
+fn escape(text: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c == '\\' || special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
@@ -0,0 +1,36 @@
+use chrono::{DateTime, Utc};
+
+// least squares line through a series of readings
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fit {
+    // change per hour
+    pub slope: f64,
+    // share of the variation explained by the line, 1 for readings exactly on it, 0 for a flat or random series
+    pub r_squared: f64,
+}
+
+// fits a line through the readings, none with fewer than 3 readings or when they were all read at once
+pub fn fit(readings: &[(DateTime<Utc>, f64)]) -> Option<Fit> {
+    let (start, _) = *readings.first()?;
+    if readings.len() < 3 {
+        return None;
+    }
+    let points: Vec<(f64, f64)> = readings
+        .iter()
+        .map(|(at, value)| ((*at - start).num_milliseconds() as f64 / 3_600_000.0, *value))
+        .collect();
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let (mut var_x, mut var_y, mut cov) = (0.0, 0.0, 0.0);
+    for (x, y) in &points {
+        var_x += (x - mean_x).powi(2);
+        var_y += (y - mean_y).powi(2);
+        cov += (x - mean_x) * (y - mean_y);
+    }
+    if var_x == 0.0 {
+        return None;
+    }
+    let r_squared = if var_y == 0.0 { 0.0 } else { cov * cov / (var_x * var_y) };
+    Some(Fit { slope: cov / var_x, r_squared })
+}
@@ -0,0 +1,225 @@
+// write queue in front of the database, rows are stored in short transactions instead of one autocommit per message,
+// as the disk sync of every commit is what limits how many messages sqlite can take
+use std::time::Duration;
+use sqlx::{Any, AnyPool, Executor};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::config::DatabaseConfig;
+
+// writes waiting for the writer task, the subscriber waits for room beyond this
+const QUEUE_SIZE: usize = 1000;
+
+// row stored through the queue
+#[derive(Debug, Clone, PartialEq)]
+pub enum Write {
+    Reading {
+        device: String,
+        sensor: String,
+        value: f64,
+        unit: String,
+        seq: Option<i64>,
+        firmware: Option<String>,
+        created_at: String,
+    },
+    Annotation {
+        device: String,
+        sensor: String,
+        script: String,
+        text: String,
+        created_at: String,
+    },
+    DeadLetter {
+        topic: String,
+        payload: String,
+        error: String,
+    },
+    // counters of a sequence check, added to the stored ones
+    Sequence {
+        device: String,
+        sensor: String,
+        last_seq: Option<i64>,
+        last_uptime_ms: Option<i64>,
+        received: i64,
+        missing: i64,
+        duplicates: i64,
+        replays: i64,
+        restarts: i64,
+        updated_at: String,
+    },
+}
+
+impl Write {
+    pub async fn execute<'e, E: Executor<'e, Database = Any>>(&self, executor: E) -> Result<(), sqlx::Error> {
+        match self {
+            Self::Reading { device, sensor, value, unit, seq, firmware, created_at } => {
+                sqlx::query("insert into readings (device, sensor, value, unit, seq, firmware, created_at) values ($1, $2, $3, $4, $5, $6, datetime($7))")
+                    .bind(device)
+                    .bind(sensor)
+                    .bind(value)
+                    .bind(unit)
+                    .bind(seq)
+                    .bind(firmware)
+                    .bind(created_at)
+                    .execute(executor)
+                    .await?;
+            }
+            Self::Annotation { device, sensor, script, text, created_at } => {
+                sqlx::query("insert into annotations (device, sensor, script, text, created_at) values ($1, $2, $3, $4, datetime($5))")
+                    .bind(device)
+                    .bind(sensor)
+                    .bind(script)
+                    .bind(text)
+                    .bind(created_at)
+                    .execute(executor)
+                    .await?;
+            }
+            Self::DeadLetter { topic, payload, error } => {
+                sqlx::query("insert into dead_letters (topic, payload, error) values ($1, $2, $3)")
+                    .bind(topic)
+                    .bind(payload)
+                    .bind(error)
+                    .execute(executor)
+                    .await?;
+            }
+            Self::Sequence { device, sensor, last_seq, last_uptime_ms, received, missing, duplicates, replays, restarts, updated_at } => {
+                // the counters are qualified, postgresql would not know whether they are the stored or the excluded ones
+                sqlx::query(
+                    "insert into sequence_stats (device, sensor, last_seq, last_uptime_ms, received, missing, duplicates, replays, restarts, updated_at)
+                    values ($1, $2, $3, $4, $5, $6, $7, $8, $9, datetime($10))
+                    on conflict (device, sensor) do update set
+                        last_seq = excluded.last_seq,
+                        last_uptime_ms = excluded.last_uptime_ms,
+                        received = sequence_stats.received + excluded.received,
+                        missing = sequence_stats.missing + excluded.missing,
+                        duplicates = sequence_stats.duplicates + excluded.duplicates,
+                        replays = sequence_stats.replays + excluded.replays,
+                        restarts = sequence_stats.restarts + excluded.restarts,
+                        updated_at = excluded.updated_at",
+                )
+                .bind(device)
+                .bind(sensor)
+                .bind(last_seq)
+                .bind(last_uptime_ms)
+                .bind(received)
+                .bind(missing)
+                .bind(duplicates)
+                .bind(replays)
+                .bind(restarts)
+                .bind(updated_at)
+                .execute(executor)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn table(&self) -> &'static str {
+        match self {
+            Self::Reading { .. } => "readings",
+            Self::Annotation { .. } => "annotations",
+            Self::DeadLetter { .. } => "dead_letters",
+            Self::Sequence { .. } => "sequence_stats",
+        }
+    }
+}
+
+enum Request {
+    Write(Write),
+    // answered once everything queued before it was committed
+    Flush(oneshot::Sender<()>),
+}
+
+// how writes are grouped into transactions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Batching {
+    pub batch_size: usize,
+    // how long a write may wait for the batch to fill up
+    pub flush: Duration,
+}
+
+// sender to the writer task, dropping every clone lets it commit what is left and stop
+#[derive(Clone)]
+pub struct Writer {
+    sender: mpsc::Sender<Request>,
+}
+
+impl Writer {
+    pub fn start(db_pool: AnyPool, config: &DatabaseConfig) -> (Self, JoinHandle<()>) {
+        let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+        (Self { sender }, tokio::spawn(run_writer(db_pool, receiver, config.batching())))
+    }
+
+    pub async fn write(&self, write: Write) {
+        if self.sender.send(Request::Write(write)).await.is_err() {
+            eprintln!("Write queue is closed, dropping a write");
+        }
+    }
+
+    // waits until everything written so far is committed, for reads that have to see it
+    pub async fn flush(&self) {
+        let (ack, done) = oneshot::channel();
+        if self.sender.send(Request::Flush(ack)).await.is_ok() {
+            let _ = done.await;
+        }
+    }
+}
+
+// commits a batch when it is full, when its first write waited for the flush time, or when asked to
+async fn run_writer(db_pool: AnyPool, mut receiver: mpsc::Receiver<Request>, batching: Batching) {
+    let mut batch = Vec::with_capacity(batching.batch_size);
+    let mut deadline = Instant::now();
+    loop {
+        let request = tokio::select! {
+            request = receiver.recv() => request,
+            _ = tokio::time::sleep_until(deadline), if !batch.is_empty() => {
+                commit(&db_pool, &mut batch).await;
+                continue;
+            }
+        };
+        match request {
+            Some(Request::Write(write)) => {
+                if batch.is_empty() {
+                    deadline = Instant::now() + batching.flush;
+                }
+                batch.push(write);
+                if batch.len() >= batching.batch_size {
+                    commit(&db_pool, &mut batch).await;
+                }
+            }
+            Some(Request::Flush(ack)) => {
+                commit(&db_pool, &mut batch).await;
+                let _ = ack.send(());
+            }
+            None => {
+                commit(&db_pool, &mut batch).await;
+                return;
+            }
+        }
+    }
+}
+
+async fn commit(db_pool: &AnyPool, batch: &mut Vec<Write>) {
+    if batch.is_empty() {
+        return;
+    }
+    if let Err(e) = write_batch(db_pool, batch).await {
+        // one bad row must not cost the others, they are stored on their own instead
+        eprintln!("Failed to store a batch of {} writes, storing them one by one: {}", batch.len(), e);
+        for write in batch.iter() {
+            if let Err(e) = write.execute(db_pool).await {
+                eprintln!("Failed to store a row in {}: {}", write.table(), e);
+            }
+        }
+    }
+    batch.clear();
+}
+
+async fn write_batch(db_pool: &AnyPool, batch: &[Write]) -> Result<(), sqlx::Error> {
+    let mut transaction = db_pool.begin().await?;
+    for write in batch {
+        write.execute(&mut *transaction).await?;
+    }
+    transaction.commit().await
+}
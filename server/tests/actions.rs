@@ -0,0 +1,156 @@
+mod common;
+
+use chrono::{Local, NaiveTime, TimeDelta};
+use common::{Harness, Message};
+
+use iiot_webserver::actions::TimeWindow;
+use iiot_webserver::config::Config;
+
+const LOG_ROUTE: &str = r#"
+    [[route]]
+    channels = ["log"]
+"#;
+
+fn message(topic: &str, payload: &str) -> Message {
+    Message { topic: topic.to_string(), payload: payload.to_string(), retain: false }
+}
+
+fn time(time: &str) -> NaiveTime {
+    NaiveTime::parse_from_str(time, "%H:%M").unwrap()
+}
+
+#[tokio::test]
+async fn named_threshold_rules_switch_a_plug_with_rate_limits() {
+    let harness = Harness::start(&format!(r#"
+        {LOG_ROUTE}
+
+        [[rules.threshold]]
+        name = "humid"
+        sensor = "humidity"
+        above = 65
+
+        [[rules.threshold]]
+        name = "dry"
+        sensor = "humidity"
+        below = 55
+
+        [[action]]
+        rules = ["humid"]
+        topic = "home/dehumidifier/set"
+        payload = "on"
+        min_interval_secs = 600
+
+        [[action]]
+        rules = ["dry"]
+        topic = "home/dehumidifier/set"
+        payload = "off"
+    "#)).await;
+    harness.subscribe("home/#").await;
+
+    harness.publish("esp32/humidity", "70").await;
+    harness.publish("esp32/humidity", "72").await;
+    harness.publish("esp32/humidity", "60").await;
+    harness.publish("esp32/humidity", "50").await;
+    let messages = harness.wait_for_messages("home/dehumidifier/set", 2).await;
+    harness.wait_for_readings("humidity", 4).await;
+
+    // the second reading above the limit is within the interval of the first one
+    assert_eq!(messages, vec![message("home/dehumidifier/set", "on"), message("home/dehumidifier/set", "off")]);
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn motion_turns_on_the_light_of_the_device_within_the_time_window() {
+    let now = Local::now().time();
+    let window = |from: i64, to: i64| {
+        let format = |time: NaiveTime| time.format("%H:%M").to_string();
+        format!(r#"["{}", "{}"]"#, format(now + TimeDelta::hours(from)), format(now + TimeDelta::hours(to)))
+    };
+    let harness = Harness::start(&format!(r#"
+        {LOG_ROUTE}
+
+        [[action]]
+        kinds = ["motion"]
+        devices = ["esp32"]
+        topic = "{{device}}/light/set"
+        payload = "on"
+        retain = true
+        between = {}
+
+        [[action]]
+        kinds = ["motion"]
+        topic = "{{device}}/siren/set"
+        payload = "on"
+        between = {}
+    "#, window(-1, 1), window(2, 3))).await;
+    harness.subscribe("esp32/+/set").await;
+
+    harness.publish("esp32/motion", "1").await;
+    let messages = harness.wait_for_messages("esp32/light/set", 1).await;
+
+    assert_eq!(messages, vec![message("esp32/light/set", "on")]);
+    // both actions were matched against the same alert, so the siren would have been sent by now
+    assert!(harness.messages().iter().all(|m| m.topic != "esp32/siren/set"));
+    // the broker kept the retained message for clients subscribing later
+    harness.subscribe("esp32/light/set").await;
+    let messages = harness.wait_for_messages("esp32/light/set", 2).await;
+    assert_eq!(messages[1], Message { retain: true, ..message("esp32/light/set", "on") });
+    harness.stop().await;
+}
+
+#[test]
+fn time_windows_wrap_around_midnight() {
+    let evening = TimeWindow { start: time("18:00"), end: time("07:00") };
+    assert!(evening.contains(time("18:00")));
+    assert!(evening.contains(time("23:59")));
+    assert!(evening.contains(time("00:00")));
+    assert!(evening.contains(time("06:59")));
+    assert!(!evening.contains(time("07:00")));
+    assert!(!evening.contains(time("12:00")));
+
+    let day = TimeWindow { start: time("07:00"), end: time("18:00") };
+    assert!(day.contains(time("12:00")));
+    assert!(!day.contains(time("18:00")));
+    assert!(!day.contains(time("03:00")));
+}
+
+#[test]
+fn invalid_actions_are_rejected() {
+    let config = Config::parse(r#"
+        [[rules.threshold]]
+        name = "humid"
+        sensor = "humidity"
+        above = 65
+
+        [[action]]
+        topic = "home/plug"
+        payload = "on"
+
+        [[action]]
+        rules = ["humid", "missing"]
+        topic = "home/+/set"
+        payload = "on"
+
+        [[action]]
+        kinds = ["motion"]
+        topic = "esp32/temperature"
+        payload = "on"
+    "#).unwrap();
+    let errors = config.validate();
+    for expected in [
+        "action #1: needs `kinds`, `rules` or both",
+        "action #2: no rule or rule script named 'missing'",
+        "action #2: topic 'home/+/set' must be a non-empty topic without wildcards",
+        "action #3: topic 'esp32/temperature' is subscribed to by the server",
+    ] {
+        assert!(errors.iter().any(|e| e == expected), "missing {:?} in {:?}", expected, errors);
+    }
+
+    assert!(Config::parse(r#"
+        [[action]]
+        kinds = ["motion"]
+        topic = "home/light"
+        payload = "on"
+        between = ["18:00", "25:00"]
+    "#).is_err());
+}
@@ -0,0 +1,95 @@
+mod common;
+
+use common::{assert_invalid, at_minute as at, insert_reading, rules};
+use iiot_webserver::alerts::AlertKind;
+use iiot_webserver::anomaly::Baseline;
+use iiot_webserver::config::{Metric, SensorKind};
+
+const TEMPERATURE: Metric = Metric::Sensor(SensorKind::Temperature);
+
+// alternates around 21 degrees, mean 21 and standard deviation 0.5
+fn normal(i: i64) -> f64 {
+    if i % 2 == 0 { 20.5 } else { 21.5 }
+}
+
+#[test]
+fn baseline_keeps_the_last_values() {
+    let mut baseline = Baseline::new(3);
+    assert_eq!(baseline.z_score(10.0, 2, 0.0), None);
+    for value in [100.0, 1.0, 2.0, 3.0] {
+        baseline.push(value);
+    }
+    assert_eq!(baseline.len(), 3);
+    assert_eq!(baseline.mean(), 2.0);
+    let z_score = baseline.z_score(2.0 + baseline.std_dev() * 3.0, 2, 0.0).unwrap();
+    assert!((z_score - 3.0).abs() < 1e-9, "{}", z_score);
+    // a flat series is measured against `min_std`
+    let mut flat = Baseline::new(3);
+    (0..3).for_each(|_| flat.push(20.0));
+    assert_eq!(flat.z_score(21.0, 2, 0.5), Some(2.0));
+}
+
+#[tokio::test]
+async fn flags_readings_far_from_the_rolling_mean() {
+    let pool = common::memory_database().await;
+    let rules = rules(r#"
+        [[rules.anomaly]]
+        sensor = "temperature"
+        z_score = 4
+        window = 20
+        min_samples = 10
+    "#);
+
+    // nothing is flagged while the baseline is learned
+    assert!(rules.check_anomaly(&pool, "esp32", TEMPERATURE, 35.0, at(0)).await.unwrap().is_empty());
+    for i in 1..20 {
+        assert!(rules.check_anomaly(&pool, "esp32", TEMPERATURE, normal(i), at(i)).await.unwrap().is_empty());
+    }
+    assert!(rules.check_anomaly(&pool, "esp32", TEMPERATURE, 22.5, at(20)).await.unwrap().is_empty());
+
+    let alerts = rules.check_anomaly(&pool, "esp32", TEMPERATURE, 30.0, at(21)).await.unwrap();
+    assert_eq!(alerts.len(), 1);
+    assert_eq!(alerts[0].kind, AlertKind::Anomaly);
+    assert_eq!(alerts[0].subject, "temperature anomaly");
+    assert!(alerts[0].body.starts_with("temperature on esp32 is 30"), "{}", alerts[0].body);
+
+    // other devices and metrics have their own series
+    assert!(rules.check_anomaly(&pool, "attic", TEMPERATURE, 30.0, at(21)).await.unwrap().is_empty());
+    let humidity = Metric::Sensor(SensorKind::Humidity);
+    assert!(rules.check_anomaly(&pool, "esp32", humidity, 90.0, at(21)).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn learns_the_baseline_from_stored_readings() {
+    let pool = common::memory_database().await;
+    for i in 0..40 {
+        insert_reading(&pool, "esp32", "temperature", normal(i), at(i)).await;
+    }
+    let rules = rules(r#"
+        [[rules.anomaly]]
+        sensor = "temperature"
+        device = "esp32"
+    "#);
+
+    // the first reading after a restart is already compared to the history
+    let alerts = rules.check_anomaly(&pool, "esp32", TEMPERATURE, 5.0, at(40)).await.unwrap();
+    assert_eq!(alerts.len(), 1);
+    assert!(rules.check_anomaly(&pool, "attic", TEMPERATURE, 5.0, at(40)).await.unwrap().is_empty());
+}
+
+#[test]
+fn rejects_invalid_anomaly_rules() {
+    assert_invalid(r#"
+        [[rules.anomaly]]
+        sensor = "motion"
+        z_score = 0
+        window = 10
+        min_samples = 20
+        min_std = -1
+    "#, &[
+        "rules.anomaly #1: anomaly rules do not apply to motion and contact",
+        "rules.anomaly #1: `z_score`",
+        "rules.anomaly #1: `min_samples`",
+        "rules.anomaly #1: `min_std`",
+    ]);
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use sqlx::AnyPool;
+
+use common::{at, insert_reading};
+use iiot_webserver::alerts::AlertKind;
+use iiot_webserver::backtest::{self, parse_time, Counts, Fired, Outcome, Summary};
+use iiot_webserver::config::Config;
+
+async fn run(pool: &AnyPool, toml: &str, from: i64, to: i64, device: Option<&str>) -> Vec<Fired> {
+    let config = Config::parse(toml).unwrap();
+    backtest::run(&config, pool, at(from), at(to), device).await.unwrap()
+}
+
+fn outcomes(fired: &[Fired]) -> Vec<(DateTime<Utc>, AlertKind, Outcome)> {
+    fired.iter().map(|fired| (fired.at, fired.alert.kind, fired.outcome)).collect()
+}
+
+#[tokio::test]
+async fn shows_which_alerts_cooldowns_would_have_suppressed() {
+    let pool = common::memory_database().await;
+    for (value, secs) in [(25.0, 0), (31.0, 60), (32.0, 120), (33.0, 700)] {
+        insert_reading(&pool, "esp32", "temperature", value, at(secs)).await;
+    }
+    let fired = run(&pool, r#"
+        [cooldowns]
+        default_secs = 600
+
+        [[route]]
+        kinds = ["threshold"]
+        channels = ["log"]
+
+        [[rules.threshold]]
+        sensor = "temperature"
+        above = 30
+    "#, 0, 800, None).await;
+
+    assert_eq!(outcomes(&fired), vec![
+        (at(60), AlertKind::Threshold, Outcome::Sent),
+        (at(120), AlertKind::Threshold, Outcome::Cooldown(TimeDelta::seconds(540))),
+        (at(700), AlertKind::Threshold, Outcome::Sent),
+    ]);
+    assert_eq!(fired[1].alert.body, "temperature on esp32 is 32, above 30");
+    assert_eq!(Summary::new(&fired), Summary { kinds: vec![(AlertKind::Threshold, Counts { sent: 2, cooldown: 1, unrouted: 0 })] });
+}
+
+#[tokio::test]
+async fn unrouted_alerts_do_not_start_a_cooldown() {
+    let pool = common::memory_database().await;
+    insert_reading(&pool, "esp32", "motion", 1.0, at(0)).await;
+    insert_reading(&pool, "esp32", "contact", 1.0, at(10)).await;
+    insert_reading(&pool, "esp32", "contact", 1.0, at(20)).await;
+    let fired = run(&pool, r#"
+        [[route]]
+        kinds = ["contact"]
+        channels = ["log"]
+    "#, 0, 30, None).await;
+
+    assert_eq!(outcomes(&fired), vec![
+        (at(0), AlertKind::Motion, Outcome::Unrouted),
+        (at(10), AlertKind::Contact, Outcome::Sent),
+        (at(20), AlertKind::Contact, Outcome::Cooldown(TimeDelta::seconds(590))),
+    ]);
+}
+
+#[tokio::test]
+async fn raises_offline_alerts_for_gaps_and_uses_earlier_readings_as_history() {
+    let pool = common::memory_database().await;
+    // a steady temperature for an hour before the backtest, then a jump well outside the baseline
+    for i in 0..60 {
+        insert_reading(&pool, "esp32", "temperature", if i % 2 == 0 { 20.5 } else { 21.5 }, at(i * 60 - 3600)).await;
+        insert_reading(&pool, "attic", "temperature", 30.0, at(i * 60 - 3600)).await;
+    }
+    insert_reading(&pool, "esp32", "temperature", 30.0, at(0)).await;
+    insert_reading(&pool, "esp32", "temperature", 21.0, at(1000)).await;
+    let config = r#"
+        [[route]]
+        channels = ["log"]
+
+        [[rules.anomaly]]
+        sensor = "temperature"
+        window = 60
+        min_samples = 30
+
+        [rules.offline]
+        after_secs = 300
+    "#;
+    let fired = run(&pool, config, 0, 1000, Some("esp32")).await;
+
+    // the baseline is learned from the readings before the range, the device is offline after the first tick past 300 seconds
+    // and the attic, which has no readings in the range of the esp32 filter, is left out
+    assert_eq!(outcomes(&fired), vec![
+        (at(0), AlertKind::Anomaly, Outcome::Sent),
+        (at(300), AlertKind::Offline, Outcome::Sent),
+    ]);
+    assert_eq!(fired[1].alert.device, "esp32");
+    assert!(run(&pool, config, 1, 999, None).await.is_empty());
+}
+
+#[test]
+fn parses_times_in_utc() {
+    assert_eq!(parse_time("2025-06-01 12:00:30"), Ok(at(12 * 3600 + 30)));
+    assert_eq!(parse_time("2025-06-01T12:00:30"), Ok(at(12 * 3600 + 30)));
+    assert_eq!(parse_time("2025-06-01"), Ok(at(0)));
+    assert!(parse_time("01.06.2025").is_err());
+}
@@ -0,0 +1,472 @@
+// test harness running the subscriber against an in-process broker, an in-memory database and a fake smtp server
+#![allow(dead_code)]
+
+use std::future::Future;
+use std::net::TcpListener as StdTcpListener;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, TimeDelta, TimeZone, Utc};
+use rumqttc::{AsyncClient, Event, Incoming, MqttOptions, QoS};
+use sqlx::AnyPool;
+use sqlx::any::AnyPoolOptions;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+
+use iiot_webserver::{db, start_mqtt_subscriber, timestamp, Services};
+use iiot_webserver::actions::OUTBOX_SIZE;
+use iiot_webserver::alerts::Alerter;
+use iiot_webserver::config::{Config, SensorKind};
+use iiot_webserver::notify::{run_notifier, QUEUE_SIZE};
+use iiot_webserver::reload::reload;
+use iiot_webserver::rules::Rules;
+use iiot_webserver::scripts::Scripts;
+use iiot_webserver::sinks::Sinks;
+use iiot_webserver::writer::Writer;
+
+pub const WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// topic only used to find out when the subscriber is ready, its readings are stored for the `harness` device
+const PROBE_TOPIC: &str = "harness/motion";
+// device of the esp32/... topics used by the tests
+pub const DEVICE: &str = "esp32";
+
+fn free_port() -> u16 {
+    StdTcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+// starts an embedded rumqttd broker on a random port
+pub fn start_broker() -> u16 {
+    let port = free_port();
+    let config = format!(r#"
+        id = 0
+
+        [router]
+        id = 0
+        max_connections = 100
+        max_outgoing_packet_count = 200
+        max_segment_size = 1048576
+        max_segment_count = 10
+
+        [v4.1]
+        name = "v4-1"
+        listen = "127.0.0.1:{port}"
+        next_connection_delay_ms = 1
+            [v4.1.connections]
+            connection_timeout_ms = 60000
+            max_payload_size = 20480
+            max_inflight_count = 100
+            dynamic_filters = true
+    "#);
+    let config: rumqttd::Config = toml::from_str(&config).unwrap();
+    std::thread::spawn(move || {
+        let mut broker = rumqttd::Broker::new(config);
+        broker.start().unwrap();
+    });
+    // a client connecting before the broker listens would lose the requests it queued
+    let started = std::time::Instant::now();
+    while std::net::TcpStream::connect(("127.0.0.1", port)).is_err() {
+        assert!(started.elapsed() < WAIT_TIMEOUT, "Timed out waiting for the broker to listen");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    port
+}
+
+// email captured by the fake smtp server
+#[derive(Debug, Clone, Default)]
+pub struct Email {
+    pub from: String,
+    pub to: Vec<String>,
+    pub data: String,
+}
+
+impl Email {
+    pub fn subject(&self) -> Option<&str> {
+        self.data.lines().find_map(|line| line.strip_prefix("Subject: "))
+    }
+}
+
+// minimal smtp server accepting every message without authentication, after the delay it is set to
+#[derive(Clone)]
+pub struct FakeSmtp {
+    pub port: u16,
+    emails: Arc<Mutex<Vec<Email>>>,
+    delay_ms: Arc<AtomicU64>,
+}
+
+impl FakeSmtp {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let smtp = Self { port, emails: Arc::new(Mutex::new(Vec::new())), delay_ms: Arc::new(AtomicU64::new(0)) };
+        let server = smtp.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(server.clone().session(stream));
+            }
+        });
+        smtp
+    }
+
+    async fn session(self, stream: tokio::net::TcpStream) {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        let mut email = Email::default();
+        let _ = writer.write_all(b"220 fake-smtp ESMTP\r\n").await;
+        while let Ok(Some(line)) = lines.next_line().await {
+            let command = line.to_ascii_uppercase();
+            let reply: &[u8] = if command.starts_with("EHLO") || command.starts_with("HELO") {
+                b"250 fake-smtp\r\n"
+            } else if command.starts_with("MAIL FROM:") {
+                email = Email { from: address(&line), ..Email::default() };
+                b"250 OK\r\n"
+            } else if command.starts_with("RCPT TO:") {
+                email.to.push(address(&line));
+                b"250 OK\r\n"
+            } else if command == "DATA" {
+                let _ = writer.write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n").await;
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if line == "." {
+                        break;
+                    }
+                    email.data.push_str(&line);
+                    email.data.push('\n');
+                }
+                tokio::time::sleep(Duration::from_millis(self.delay_ms.load(Ordering::SeqCst))).await;
+                self.emails.lock().unwrap().push(std::mem::take(&mut email));
+                b"250 OK\r\n"
+            } else if command == "QUIT" {
+                let _ = writer.write_all(b"221 Bye\r\n").await;
+                return;
+            } else {
+                b"250 OK\r\n"
+            };
+            let _ = writer.write_all(reply).await;
+        }
+    }
+
+    pub fn emails(&self) -> Vec<Email> {
+        self.emails.lock().unwrap().clone()
+    }
+
+    // how long each message takes to be accepted, so emails can be kept queued in the notifier
+    pub fn set_delay(&self, delay: Duration) {
+        self.delay_ms.store(delay.as_millis() as u64, Ordering::SeqCst);
+    }
+}
+
+fn address(line: &str) -> String {
+    let start = line.find('<').map(|i| i + 1).unwrap_or(0);
+    let end = line.rfind('>').unwrap_or(line.len());
+    line[start..end].to_string()
+}
+
+// in-memory database, a single connection keeps it alive for the whole test
+pub async fn memory_database() -> AnyPool {
+    let options = AnyPoolOptions::new().max_connections(1).idle_timeout(None).max_lifetime(None);
+    db::connect_with(options, "sqlite::memory:").await.unwrap()
+}
+
+// polls the condition until it holds, panicking after WAIT_TIMEOUT
+pub async fn wait_until<F, Fut>(what: &str, mut condition: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let deadline = tokio::time::Instant::now() + WAIT_TIMEOUT;
+    while !condition().await {
+        if tokio::time::Instant::now() > deadline {
+            panic!("Timed out waiting for {}", what);
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+// stored test series are timed from midnight utc on 2025-06-01, `at` counts seconds from there
+pub fn at(secs: i64) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2025, 6, 1, 0, 0, 0).unwrap() + TimeDelta::seconds(secs)
+}
+
+pub fn at_minute(minute: i64) -> DateTime<Utc> {
+    at(minute * 60)
+}
+
+pub fn rules(toml: &str) -> Rules {
+    Rules::new(&Config::parse(toml).unwrap().rules)
+}
+
+// stores a reading the way the subscriber would have at that time, with the unit of its sensor
+pub async fn insert_reading(pool: &AnyPool, device: &str, sensor: &str, value: f64, at: DateTime<Utc>) {
+    sqlx::query("insert into readings (device, sensor, value, unit, created_at) values ($1, $2, $3, $4, datetime($5))")
+        .bind(device)
+        .bind(sensor)
+        .bind(value)
+        .bind(SensorKind::parse(sensor).map_or("", |kind| kind.unit()))
+        .bind(timestamp(at))
+        .execute(pool)
+        .await
+        .unwrap();
+}
+
+// errors of a config that parses but does not validate, each expected text has to be part of one of them
+pub fn assert_invalid(toml: &str, expected: &[&str]) {
+    let errors = Config::parse(toml).unwrap().validate();
+    for expected in expected {
+        assert!(errors.iter().any(|e| e.contains(expected)), "{}: {:?}", expected, errors);
+    }
+}
+
+// writes a config file into the temp dir, named after the test so tests running in parallel do not share it
+pub fn config_file(name: &str, content: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("iiot-{}-{}.toml", std::process::id(), name));
+    std::fs::write(&path, content).unwrap();
+    path
+}
+
+// message received by the test client on a topic it subscribed to
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub topic: String,
+    pub payload: String,
+    pub retain: bool,
+}
+
+// subscriber config of the harness, connecting to its broker and smtp server
+// `database` holds further settings of the [database] section, which `extra` cannot open again
+fn config_text(broker_port: u16, smtp_port: u16, database: &str, extra: &str) -> String {
+    format!(r#"
+        [mqtt]
+        host = "127.0.0.1"
+        port = {broker_port}
+        client_id = "iiot-test-subscriber"
+
+        [database]
+        url = "sqlite::memory:"
+        {database}
+
+        [notifiers.email]
+        smtp_host = "127.0.0.1"
+        smtp_port = {smtp_port}
+        security = "none"
+        from = "alerts@iiot.test"
+
+        [[topics]]
+        topic = "esp32/temperature"
+        sensor = "temperature"
+
+        [[topics]]
+        topic = "esp32/humidity"
+        sensor = "humidity"
+
+        [[topics]]
+        topic = "esp32/motion"
+        sensor = "motion"
+
+        [[topics]]
+        topic = "esp32/contact"
+        sensor = "contact"
+
+        [[topics]]
+        topic = "{PROBE_TOPIC}"
+        sensor = "motion"
+
+        {extra}
+    "#)
+}
+
+pub struct Harness {
+    pub pool: AnyPool,
+    pub smtp: FakeSmtp,
+    pub config: watch::Sender<Arc<Config>>,
+    pub broker_port: u16,
+    alerter: Arc<Alerter>,
+    rules: Arc<Rules>,
+    scripts: Arc<Scripts>,
+    client: AsyncClient,
+    messages: Arc<Mutex<Vec<Message>>>,
+    shutdown: watch::Sender<bool>,
+    subscriber: JoinHandle<()>,
+    notifier: JoinHandle<()>,
+    writer: JoinHandle<()>,
+    close_notifier: oneshot::Sender<()>,
+}
+
+impl Harness {
+    // starts the subscriber with the base config extended by `extra` (routes, rules, cooldowns...)
+    pub async fn start(extra: &str) -> Self {
+        Self::start_with(extra, memory_database().await).await
+    }
+
+    // same as `start`, storing into the given database
+    pub async fn start_with(extra: &str, pool: AnyPool) -> Self {
+        Self::launch("", extra, pool).await
+    }
+
+    // same as `start`, with write batching settings (batch_size, flush_ms) for the [database] section
+    pub async fn start_with_database(database: &str, extra: &str) -> Self {
+        Self::launch(database, extra, memory_database().await).await
+    }
+
+    async fn launch(database: &str, extra: &str, pool: AnyPool) -> Self {
+        let broker_port = start_broker();
+        let smtp = FakeSmtp::start().await;
+
+        let config = Config::parse(&config_text(broker_port, smtp.port, database, extra)).unwrap();
+        let errors = config.validate();
+        assert!(errors.is_empty(), "invalid test config: {:?}", errors);
+
+        let (queue_tx, queue_rx) = mpsc::channel(QUEUE_SIZE);
+        let (close_notifier, close_rx) = oneshot::channel();
+        let notifier = tokio::spawn(run_notifier(queue_rx, close_rx));
+        let (outbox_tx, mut outbox_rx) = mpsc::channel(OUTBOX_SIZE);
+        let alerter = Arc::new(Alerter::new(&config, queue_tx, outbox_tx).unwrap());
+        let rules = Arc::new(Rules::new(&config.rules));
+        let scripts = Arc::new(Scripts::new(&config).unwrap());
+        let (sinks, _) = Sinks::start(&config).unwrap();
+        let (writer, writer_task) = Writer::start(pool.clone(), &config.database);
+
+        // publisher used by the tests
+        let mut options = MqttOptions::new("iiot-test-publisher", "127.0.0.1", broker_port);
+        options.set_keep_alive(Duration::from_secs(5));
+        let (client, mut event_loop) = AsyncClient::new(options, 100);
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let received = messages.clone();
+        tokio::spawn(async move {
+            loop {
+                match event_loop.poll().await {
+                    Ok(Event::Incoming(Incoming::Publish(publish))) => received.lock().unwrap().push(Message {
+                        topic: publish.topic,
+                        payload: String::from_utf8_lossy(&publish.payload).to_string(),
+                        retain: publish.retain,
+                    }),
+                    Ok(_) => {}
+                    Err(_) => tokio::time::sleep(Duration::from_millis(50)).await,
+                }
+            }
+        });
+        // retained, so the subscriber gets it as soon as its subscriptions are active
+        client.publish(PROBE_TOPIC, QoS::AtLeastOnce, true, "0").await.unwrap();
+
+        let (config_tx, config_rx) = watch::channel(Arc::new(config));
+        let (shutdown, shutdown_rx) = watch::channel(false);
+        let services = Services {
+            db_pool: pool.clone(),
+            alerter: alerter.clone(),
+            rules: rules.clone(),
+            scripts: scripts.clone(),
+            sinks,
+            writer,
+            sequences: Default::default(),
+        };
+        let subscriber = tokio::spawn(async move {
+            start_mqtt_subscriber(config_rx, &services, &mut outbox_rx, shutdown_rx).await.unwrap();
+        });
+
+        let harness = Self {
+            pool,
+            smtp,
+            config: config_tx,
+            broker_port,
+            alerter,
+            rules,
+            scripts,
+            client,
+            messages,
+            shutdown,
+            subscriber,
+            notifier,
+            writer: writer_task,
+            close_notifier,
+        };
+        wait_until("subscriber to be ready", || async { harness.probes().await > 0 }).await;
+        harness
+    }
+
+    // writes the harness config extended by `extra` to a file and reloads it the way SIGHUP does, without env overrides
+    pub fn reload(&self, name: &str, extra: &str) -> Result<(), String> {
+        let path = config_file(name, &config_text(self.broker_port, self.smtp.port, "", extra));
+        let result = self.reload_from(&path, |_| None);
+        std::fs::remove_file(path).unwrap();
+        result
+    }
+
+    pub fn reload_from(&self, path: &std::path::Path, env: impl Fn(&str) -> Option<String>) -> Result<(), String> {
+        reload(path, env, &self.alerter, &self.rules, &self.scripts, &self.config)
+    }
+
+    // readings of the probe, the retained probe message is stored again whenever the subscriber reconnects
+    pub async fn probes(&self) -> i64 {
+        self.device_count("harness", "motion").await
+    }
+
+    pub async fn publish(&self, topic: &str, payload: &str) {
+        self.client.publish(topic, QoS::AtLeastOnce, false, payload).await.unwrap();
+    }
+
+    // lets the test client receive what the server publishes on the topics matching the filter
+    pub async fn subscribe(&self, filter: &str) {
+        self.client.subscribe(filter, QoS::AtLeastOnce).await.unwrap();
+    }
+
+    pub fn messages(&self) -> Vec<Message> {
+        self.messages.lock().unwrap().clone()
+    }
+
+    // waits until `count` messages were received on the topic
+    pub async fn wait_for_messages(&self, topic: &str, count: usize) -> Vec<Message> {
+        let on_topic = || self.messages().into_iter().filter(|m| m.topic == topic).collect::<Vec<_>>();
+        wait_until(&format!("{} messages on {}", count, topic), || async { on_topic().len() >= count }).await;
+        on_topic()
+    }
+
+    async fn device_count(&self, device: &str, sensor: &str) -> i64 {
+        sqlx::query_scalar("select count(*) from readings where device = $1 and sensor = $2")
+            .bind(device)
+            .bind(sensor)
+            .fetch_one(&self.pool)
+            .await
+            .unwrap()
+    }
+
+    // number of readings stored for a sensor of the esp32 device
+    pub async fn count(&self, sensor: &str) -> i64 {
+        self.device_count(DEVICE, sensor).await
+    }
+
+    pub async fn values(&self, sensor: &str) -> Vec<f64> {
+        sqlx::query_scalar("select value from readings where device = $1 and sensor = $2 order by id")
+            .bind(DEVICE)
+            .bind(sensor)
+            .fetch_all(&self.pool)
+            .await
+            .unwrap()
+    }
+
+    pub async fn dead_letters(&self) -> Vec<(String, String, String)> {
+        sqlx::query_as("select topic, payload, error from dead_letters order by id")
+            .fetch_all(&self.pool)
+            .await
+            .unwrap()
+    }
+
+    // waits until `count` readings of the sensor were stored
+    pub async fn wait_for_readings(&self, sensor: &str, count: i64) {
+        wait_until(&format!("{} {} readings", count, sensor), || async { self.count(sensor).await >= count }).await;
+    }
+
+    pub async fn wait_for_emails(&self, count: usize) -> Vec<Email> {
+        wait_until(&format!("{} emails", count), || async { self.smtp.emails().len() >= count }).await;
+        self.smtp.emails()
+    }
+
+    // shuts the subscriber down the same way the server does, returning once everything was drained
+    pub async fn stop(self) {
+        self.shutdown.send(true).unwrap();
+        tokio::time::timeout(WAIT_TIMEOUT, self.subscriber).await.unwrap().unwrap();
+        tokio::time::timeout(WAIT_TIMEOUT, self.writer).await.unwrap().unwrap();
+        let _ = self.close_notifier.send(());
+        tokio::time::timeout(WAIT_TIMEOUT, self.notifier).await.unwrap().unwrap();
+    }
+}
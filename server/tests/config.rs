@@ -0,0 +1,117 @@
+mod common;
+
+use std::path::PathBuf;
+
+use common::config_file;
+use iiot_webserver::alerts::Channel;
+use iiot_webserver::config::{Config, ConfigError};
+
+const FILE: &str = r#"
+    [mqtt]
+    host = "broker.lan"
+    port = 1884
+
+    [database]
+    url = "sqlite://file.db"
+
+    [notifiers.email]
+    username = "file@example.com"
+    password = "from-file"
+"#;
+
+const ROUTE: &str = r#"
+    [[route]]
+    channels = ["log"]
+"#;
+
+fn with_route() -> String {
+    format!("{}{}", FILE, ROUTE)
+}
+
+// environment of the test, every other variable is unset
+fn env<'a>(vars: &'a [(&str, &str)]) -> impl Fn(&str) -> Option<String> + 'a {
+    move |name| vars.iter().find(|(key, _)| *key == name).map(|(_, value)| value.to_string())
+}
+
+fn invalid(result: Result<Config, ConfigError>) -> Vec<String> {
+    match result {
+        Err(ConfigError::Invalid(errors)) => errors,
+        other => panic!("expected an invalid config, got {:?}", other.map(|config| config.mqtt)),
+    }
+}
+
+#[test]
+fn file_values_are_kept_without_environment() {
+    let path = config_file("config-file", &with_route());
+    let config = Config::load_from(&path, env(&[])).unwrap();
+    assert_eq!((config.mqtt.host.as_str(), config.mqtt.port), ("broker.lan", 1884));
+    assert_eq!(config.database.url, "sqlite://file.db");
+    // the batching defaults apply to a [database] table that only sets the url
+    assert_eq!((config.database.batch_size, config.database.flush_ms), (200, 100));
+    assert_eq!(config.routes.len(), 1);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn environment_overrides_the_file() {
+    let path = config_file("config-env", &with_route());
+    let config = Config::load_from(&path, env(&[
+        ("DATABASE_URL", "postgres://db.lan/iiot"),
+        ("MQTT_HOST", "10.0.0.2"),
+        ("MQTT_PORT", "8883"),
+        ("EMAIL_USERNAME", "env@example.com"),
+        ("EMAIL_PASSWORD", "from-env"),
+    ])).unwrap();
+    assert_eq!((config.mqtt.host.as_str(), config.mqtt.port), ("10.0.0.2", 8883));
+    assert_eq!(config.database.url, "postgres://db.lan/iiot");
+    let email = config.notifiers.email.unwrap();
+    assert_eq!((email.username.as_str(), email.password.as_str()), ("env@example.com", "from-env"));
+    assert_eq!(email.sender(), "env@example.com");
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn email_recipient_only_routes_when_no_routes_are_configured() {
+    let path = config_file("config-recipient", FILE);
+    let config = Config::load_from(&path, env(&[("EMAIL_RECIPIENT", "alice@example.com")])).unwrap();
+    assert_eq!(config.routes.len(), 1);
+    assert!(config.routes[0].kinds.is_empty() && config.routes[0].devices.is_empty());
+    assert_eq!(config.routes[0].recipients, vec!["alice@example.com"]);
+    assert_eq!(config.routes[0].channels, vec![Channel::Email]);
+
+    std::fs::write(&path, format!("{}\n[[route]]\nrecipients = [\"bob@example.com\"]", FILE)).unwrap();
+    let config = Config::load_from(&path, env(&[("EMAIL_RECIPIENT", "alice@example.com")])).unwrap();
+    assert_eq!(config.routes.len(), 1);
+    assert_eq!(config.routes[0].recipients, vec!["bob@example.com"]);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn rejects_an_unparsable_port() {
+    let path = config_file("config-port", &with_route());
+    let errors = invalid(Config::load_from(&path, env(&[("MQTT_PORT", "18 83")])));
+    assert_eq!(errors, vec!["MQTT_PORT: '18 83' is not a valid port"]);
+    assert!(invalid(Config::load_from(&path, env(&[("MQTT_PORT", "65536")])))[0].contains("'65536' is not a valid port"));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn a_missing_file_is_configured_from_the_environment() {
+    let path = PathBuf::from("/nonexistent/iiot/config.toml");
+    let config = Config::load_from(&path, env(&[
+        ("DATABASE_URL", "sqlite::memory:"),
+        ("MQTT_HOST", "localhost"),
+        ("EMAIL_USERNAME", "alerts@example.com"),
+        ("EMAIL_PASSWORD", "secret"),
+        ("EMAIL_RECIPIENT", "alice@example.com"),
+    ])).unwrap();
+    assert_eq!((config.mqtt.host.as_str(), config.mqtt.port), ("localhost", 1883));
+    assert_eq!(config.database.url, "sqlite::memory:");
+    assert_eq!(config.notifiers.email.unwrap().smtp_host, "smtp.gmail.com");
+    assert_eq!(config.routes[0].recipients, vec!["alice@example.com"]);
+
+    let errors = invalid(Config::load_from(&path, env(&[])));
+    assert!(errors.iter().any(|e| e.contains("mqtt.host is not set")), "{:?}", errors);
+    assert!(errors.iter().any(|e| e.contains("database.url is not set")), "{:?}", errors);
+    assert!(errors.iter().any(|e| e.contains("no alert routes configured")), "{:?}", errors);
+}
@@ -0,0 +1,51 @@
+use chrono::{TimeDelta, TimeZone, Utc};
+
+use iiot_webserver::config::SensorKind;
+use iiot_webserver::derived::{absolute_humidity, dew_point, heat_index, Correlator, DerivedKind};
+
+fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+    assert!((actual - expected).abs() <= tolerance, "{} is not within {} of {}", actual, tolerance, expected);
+}
+
+#[test]
+fn dew_point_matches_reference_values() {
+    assert_close(dew_point(25.0, 60.0), 16.7, 0.1);
+    assert_close(dew_point(20.0, 100.0), 20.0, 0.01);
+    assert_close(dew_point(-5.3, 80.0), -8.1, 0.2);
+    assert_eq!(DerivedKind::DewPoint.compute(20.0, 0.0), None);
+}
+
+#[test]
+fn heat_index_matches_nws_table() {
+    // 90°F at 70% is 106°F in the NWS table
+    assert_close(heat_index(32.2, 70.0), 41.1, 0.5);
+    // 80°F at 40% is 80°F
+    assert_close(heat_index(26.7, 40.0), 26.7, 0.5);
+    // below 80°F the simple formula stays close to the temperature
+    assert_close(heat_index(20.0, 50.0), 19.4, 0.1);
+}
+
+#[test]
+fn absolute_humidity_matches_reference_values() {
+    assert_close(absolute_humidity(25.0, 60.0), 13.8, 0.1);
+    assert_close(absolute_humidity(0.0, 100.0), 4.8, 0.1);
+}
+
+#[test]
+fn pairs_values_read_close_together() {
+    let correlator = Correlator::default();
+    let at = Utc.with_ymd_and_hms(2025, 6, 1, 10, 0, 0).unwrap();
+
+    assert_eq!(correlator.add("esp32", SensorKind::Temperature, 25.0, at), None);
+    // other devices and sensors do not interfere
+    assert_eq!(correlator.add("kitchen", SensorKind::Humidity, 40.0, at), None);
+    assert_eq!(correlator.add("esp32", SensorKind::Motion, 1.0, at), None);
+    let pair = correlator.add("esp32", SensorKind::Humidity, 60.0, at + TimeDelta::seconds(1)).unwrap();
+    assert_eq!((pair.temperature, pair.humidity, pair.read_at), (25.0, 60.0, at + TimeDelta::seconds(1)));
+    assert_eq!(pair.derived().len(), 3);
+
+    // each value is used once, and values read too far apart are not paired
+    assert_eq!(correlator.add("esp32", SensorKind::Humidity, 61.0, at + TimeDelta::seconds(2)), None);
+    assert_eq!(correlator.add("esp32", SensorKind::Temperature, 26.0, at + TimeDelta::seconds(30)), None);
+    assert!(correlator.add("esp32", SensorKind::Humidity, 62.0, at + TimeDelta::seconds(32)).is_some());
+}
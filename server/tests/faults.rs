@@ -0,0 +1,94 @@
+mod common;
+
+use sqlx::AnyPool;
+
+use common::{assert_invalid, at_minute as at, insert_reading, rules};
+use iiot_webserver::alerts::AlertKind;
+use iiot_webserver::config::SensorKind;
+
+// stores a reading every minute from `from` to `to` minutes, `value` picks the value from the minute
+async fn readings(pool: &AnyPool, device: &str, sensor: &str, from: i64, to: i64, value: impl Fn(i64) -> f64) {
+    for minute in from..to {
+        insert_reading(pool, device, sensor, value(minute), at(minute)).await;
+    }
+}
+
+#[tokio::test]
+async fn reports_values_outside_the_sensor_range_once() {
+    let rules = rules("[rules.fault]");
+
+    assert!(rules.check_range("esp32", SensorKind::Temperature, 25.0).await.is_empty());
+    let alerts = rules.check_range("esp32", SensorKind::Humidity, 95.0).await;
+    assert_eq!(alerts.len(), 1);
+    assert_eq!(alerts[0].kind, AlertKind::Fault);
+    assert_eq!(alerts[0].subject, "Sensor fault");
+    assert_eq!(alerts[0].body, "humidity on esp32 is 95, outside the sensor range of 20 to 90");
+    assert!(rules.check_range("esp32", SensorKind::Humidity, 99.0).await.is_empty());
+
+    // raised again after the sensor recovered
+    assert!(rules.check_range("esp32", SensorKind::Humidity, 50.0).await.is_empty());
+    assert_eq!(rules.check_range("esp32", SensorKind::Humidity, 10.0).await.len(), 1);
+    assert_eq!(rules.check_range("esp32", SensorKind::Temperature, -1.0).await.len(), 1);
+    assert!(rules.check_range("esp32", SensorKind::Motion, 1.0).await.is_empty());
+}
+
+#[tokio::test]
+async fn detects_flatlined_series() {
+    let pool = common::memory_database().await;
+    let rules = rules("[rules.fault]\nflatline_secs = 3600");
+
+    // a changing series, then one that only started recently
+    readings(&pool, "esp32", "temperature", 0, 120, |minute| 20.0 + (minute % 3) as f64).await;
+    readings(&pool, "attic", "temperature", 100, 120, |_| 18.0).await;
+    assert!(rules.check_faults(&pool, at(120)).await.unwrap().is_empty());
+
+    // an hour of the same value
+    readings(&pool, "esp32", "temperature", 120, 200, |_| 21.0).await;
+    let alerts = rules.check_faults(&pool, at(200)).await.unwrap();
+    assert_eq!(alerts.len(), 1);
+    assert_eq!(alerts[0].device, "esp32");
+    assert!(alerts[0].body.starts_with("temperature on esp32 has not changed from 21 for 1.0 hours"), "{}", alerts[0].body);
+    assert!(rules.check_faults(&pool, at(201)).await.unwrap().is_empty());
+
+    // the attic has been flat for an hour by now as well
+    readings(&pool, "attic", "temperature", 120, 170, |_| 18.0).await;
+    let alerts = rules.check_faults(&pool, at(170)).await.unwrap();
+    assert_eq!(alerts.iter().map(|a| a.device.as_str()).collect::<Vec<_>>(), vec!["attic"]);
+}
+
+#[tokio::test]
+async fn detects_a_silent_dht_and_missing_motion() {
+    let pool = common::memory_database().await;
+    let rules = rules(r#"
+        [rules.fault]
+        silent_secs = 600
+        no_motion_secs = 7200
+    "#);
+
+    // the dht stops after 30 minutes, motion and contact keep sending, motion was last detected at minute 10
+    readings(&pool, "esp32", "temperature", 0, 30, |_| 21.0).await;
+    readings(&pool, "esp32", "contact", 0, 200, |_| 0.0).await;
+    readings(&pool, "esp32", "motion", 0, 200, |minute| if minute == 10 { 1.0 } else { 0.0 }).await;
+    // a device without a dht is not silent
+    readings(&pool, "door", "contact", 0, 200, |_| 0.0).await;
+
+    let alerts = rules.check_faults(&pool, at(35)).await.unwrap();
+    assert!(alerts.is_empty(), "{:?}", alerts);
+    let alerts = rules.check_faults(&pool, at(45)).await.unwrap();
+    assert_eq!(alerts.len(), 1);
+    assert!(alerts[0].body.starts_with("esp32 has not sent temperature or humidity for 10 minutes"), "{}", alerts[0].body);
+
+    let alerts = rules.check_faults(&pool, at(131)).await.unwrap();
+    assert_eq!(alerts.len(), 1);
+    assert_eq!(alerts[0].kind, AlertKind::Fault);
+    assert!(alerts[0].body.starts_with("no motion was detected on esp32 for 2.0 hours"), "{}", alerts[0].body);
+}
+
+#[test]
+fn rejects_invalid_fault_rules() {
+    assert_invalid(r#"
+        [rules.fault]
+        humidity_range = [90, 20]
+        flatline_secs = 0
+    "#, &["rules.fault.humidity_range: the lower bound", "rules.fault.flatline_secs must be greater than 0"]);
+}
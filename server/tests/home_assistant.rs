@@ -0,0 +1,127 @@
+mod common;
+
+use serde_json::{json, Value};
+use common::Harness;
+
+use iiot_webserver::actions::Publish;
+use iiot_webserver::config::{Config, SensorKind};
+use iiot_webserver::home_assistant::{discovery, removed, state};
+
+const HOME_ASSISTANT: &str = r#"
+    [home_assistant]
+    discovery_prefix = "homeassistant"
+    state_prefix = "iiot"
+"#;
+
+fn config(topics: &str) -> Config {
+    Config::parse(&format!("{}\n{}", HOME_ASSISTANT, topics)).unwrap()
+}
+
+fn payload(publish: &Publish) -> Value {
+    serde_json::from_str(&publish.payload).unwrap()
+}
+
+#[test]
+fn discovery_configs_describe_each_device_and_sensor() {
+    let config = config(r#"
+        [[topics]]
+        topic = "esp32/temperature"
+        sensor = "temperature"
+
+        [[topics]]
+        topic = "hall/door"
+        device = "front door"
+        sensor = "contact"
+
+        [[topics]]
+        topic = "legacy/hall/door"
+        device = "front door"
+        sensor = "contact"
+    "#);
+    let published = discovery(config.home_assistant.as_ref().unwrap(), &config.topics);
+
+    // both topics of the door are the same entity
+    let topics: Vec<&str> = published.iter().map(|p| p.topic.as_str()).collect();
+    assert_eq!(topics, vec!["homeassistant/sensor/esp32/temperature/config", "homeassistant/binary_sensor/front_door/contact/config"]);
+    assert!(published.iter().all(|p| p.retain));
+    assert_eq!(payload(&published[0]), json!({
+        "name": "temperature",
+        "unique_id": "iiot_esp32_temperature",
+        "state_topic": "iiot/esp32/temperature",
+        "device_class": "temperature",
+        "unit_of_measurement": "°C",
+        "state_class": "measurement",
+        "device": { "identifiers": ["iiot_esp32"], "name": "esp32", "manufacturer": "iiot" },
+    }));
+    let door = payload(&published[1]);
+    assert_eq!(door["device_class"], "door");
+    assert_eq!(door["state_topic"], "iiot/front door/contact");
+    assert_eq!(door["unique_id"], "iiot_front_door_contact");
+    assert_eq!((&door["payload_on"], &door["payload_off"]), (&json!("ON"), &json!("OFF")));
+}
+
+#[test]
+fn removed_sensors_get_an_empty_config() {
+    let before = config(r#"
+        [[topics]]
+        topic = "esp32/motion"
+        sensor = "motion"
+
+        [[topics]]
+        topic = "esp32/contact"
+        sensor = "contact"
+    "#);
+    let after = config(r#"
+        [[topics]]
+        topic = "esp32/motion"
+        sensor = "motion"
+    "#);
+    let home_assistant = before.home_assistant.as_ref().unwrap();
+    let removed = removed(&discovery(home_assistant, &before.topics), &discovery(home_assistant, &after.topics));
+    assert_eq!(removed, vec![Publish {
+        topic: "homeassistant/binary_sensor/esp32/contact/config".to_string(),
+        payload: String::new(),
+        retain: true,
+    }]);
+}
+
+#[test]
+fn states_use_on_and_off_for_binary_sensors() {
+    let config = config("");
+    let home_assistant = config.home_assistant.as_ref().unwrap();
+    assert_eq!(state(home_assistant, "esp32", SensorKind::Temperature, -5.3).payload, "-5.3");
+    assert_eq!(state(home_assistant, "esp32", SensorKind::Motion, 1.0).payload, "ON");
+    assert_eq!(state(home_assistant, "esp32", SensorKind::Contact, 0.0).payload, "OFF");
+    assert_eq!(state(home_assistant, "esp32", SensorKind::Humidity, 40.0).topic, "iiot/esp32/humidity");
+
+    let invalid = Config::parse(r#"
+        [home_assistant]
+        state_prefix = "iiot/#"
+    "#).unwrap();
+    assert!(invalid.validate().contains(&"home_assistant.state_prefix 'iiot/#' must be a non-empty topic without wildcards".to_string()));
+}
+
+#[tokio::test]
+async fn publishes_discovery_on_connect_and_the_state_of_stored_readings() {
+    let harness = Harness::start(&format!("[[route]]\nchannels = [\"log\"]\n{}", HOME_ASSISTANT)).await;
+    // the configs were published before the test client subscribed, the broker kept them
+    harness.subscribe("homeassistant/#").await;
+    let configs = harness.wait_for_messages("homeassistant/binary_sensor/esp32/motion/config", 1).await;
+    assert!(configs[0].retain);
+    assert_eq!(serde_json::from_str::<Value>(&configs[0].payload).unwrap()["device_class"], "occupancy");
+
+    harness.subscribe("iiot/#").await;
+    harness.publish("esp32/temperature", r#"{"v":1,"value":-5.3,"unit":"°C"}"#).await;
+    harness.publish("esp32/motion", "1").await;
+    harness.publish("esp32/humidity", "200").await;
+    let temperature = harness.wait_for_messages("iiot/esp32/temperature", 1).await;
+    let motion = harness.wait_for_messages("iiot/esp32/motion", 1).await;
+    harness.wait_for_readings("motion", 1).await;
+    assert_eq!(temperature[0].payload, "-5.3");
+    assert_eq!(motion[0].payload, "ON");
+
+    // dead-lettered values are not published
+    common::wait_until("the impossible humidity", || async { !harness.dead_letters().await.is_empty() }).await;
+    assert!(harness.messages().iter().all(|m| m.topic != "iiot/esp32/humidity"));
+    harness.stop().await;
+}
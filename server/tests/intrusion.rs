@@ -0,0 +1,125 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{at, rules, Harness};
+use iiot_webserver::alerts::{AlertKind, Severity};
+use iiot_webserver::config::{Config, SensorKind};
+
+const ARMED: &str = r#"
+    [rules.intrusion]
+    armed = true
+    entry_secs = 30
+    presence_secs = 600
+"#;
+
+fn kinds(alerts: &[iiot_webserver::alerts::Alert]) -> Vec<(AlertKind, Severity)> {
+    alerts.iter().map(|alert| (alert.kind, alert.severity)).collect()
+}
+
+#[tokio::test]
+async fn door_then_motion_is_one_intrusion() {
+    let rules = rules(ARMED);
+
+    assert!(rules.check_event("hall", SensorKind::Contact, at(0)).await.is_empty());
+    let alerts = rules.check_event("living-room", SensorKind::Motion, at(12)).await;
+    assert_eq!(kinds(&alerts), vec![(AlertKind::Intrusion, Severity::High)]);
+    assert_eq!(alerts[0].subject, "Probable intrusion");
+    assert_eq!(alerts[0].device, "living-room");
+    assert_eq!(alerts[0].body, "contact on hall opened, then motion was detected on living-room 12 seconds later while armed");
+
+    // the door was part of the intrusion, so it is not reported on its own
+    assert!(rules.expire_events(at(60)).await.is_empty());
+    // more motion after someone came in through the door is a plain motion alert
+    let alerts = rules.check_event("living-room", SensorKind::Motion, at(120)).await;
+    assert_eq!(kinds(&alerts), vec![(AlertKind::Motion, Severity::Normal)]);
+}
+
+#[tokio::test]
+async fn door_without_motion_is_a_contact_alert_after_the_entry_time() {
+    let rules = rules(ARMED);
+
+    assert!(rules.check_event("hall", SensorKind::Contact, at(0)).await.is_empty());
+    assert!(rules.expire_events(at(30)).await.is_empty());
+    let alerts = rules.expire_events(at(31)).await;
+    assert_eq!(kinds(&alerts), vec![(AlertKind::Contact, Severity::Normal)]);
+    assert!(rules.expire_events(at(32)).await.is_empty());
+
+    // motion after the entry time is not tied to the door, but it did open recently
+    let alerts = rules.check_event("hall", SensorKind::Motion, at(45)).await;
+    assert_eq!(kinds(&alerts), vec![(AlertKind::Motion, Severity::Normal)]);
+}
+
+#[tokio::test]
+async fn motion_without_a_door_means_someone_is_inside() {
+    let rules = rules(ARMED);
+
+    let alerts = rules.check_event("hall", SensorKind::Motion, at(0)).await;
+    assert_eq!(kinds(&alerts), vec![(AlertKind::Intrusion, Severity::High)]);
+    assert_eq!(alerts[0].subject, "Motion without entry");
+
+    // once a door opened, motion is explained for `presence_secs`
+    rules.check_event("hall", SensorKind::Contact, at(100)).await;
+    rules.expire_events(at(200)).await;
+    assert_eq!(kinds(&rules.check_event("hall", SensorKind::Motion, at(700)).await), vec![(AlertKind::Motion, Severity::Normal)]);
+    assert_eq!(kinds(&rules.check_event("hall", SensorKind::Motion, at(701)).await), vec![(AlertKind::Intrusion, Severity::High)]);
+}
+
+#[tokio::test]
+async fn plain_alerts_while_disarmed_or_for_other_devices() {
+    let rules = rules(r#"
+        [rules.intrusion]
+        devices = ["hall"]
+    "#);
+    assert!(!rules.is_armed().await);
+    assert_eq!(kinds(&rules.check_event("hall", SensorKind::Contact, at(0)).await), vec![(AlertKind::Contact, Severity::Normal)]);
+    assert_eq!(kinds(&rules.check_event("hall", SensorKind::Motion, at(5)).await), vec![(AlertKind::Motion, Severity::Normal)]);
+
+    rules.set_armed(true).await;
+    assert!(rules.check_event("hall", SensorKind::Contact, at(10)).await.is_empty());
+    assert_eq!(kinds(&rules.check_event("garage", SensorKind::Motion, at(15)).await), vec![(AlertKind::Motion, Severity::Normal)]);
+
+    // a reload keeps the armed state received over mqtt
+    rules.reload(&Config::parse("[rules.intrusion]\ndevices = [\"hall\"]").unwrap().rules);
+    assert!(rules.is_armed().await);
+}
+
+#[tokio::test]
+async fn arms_over_mqtt_and_sends_one_urgent_email() {
+    let harness = Harness::start(r#"
+        [[route]]
+        min_severity = "high"
+        recipients = ["phone@example.com"]
+
+        [[route]]
+        kinds = ["contact", "motion"]
+        recipients = ["household@example.com"]
+
+        [rules.intrusion]
+        armed_topic = "home/armed"
+    "#).await;
+
+    // disarmed, the door is reported right away
+    harness.publish("esp32/contact", "1").await;
+    let emails = harness.wait_for_emails(1).await;
+    assert_eq!(emails[0].subject(), Some("Contact alert"));
+    assert_eq!(emails[0].to, vec!["household@example.com"]);
+
+    harness.publish("home/armed", "on").await;
+    harness.publish("home/armed", "maybe").await;
+    common::wait_until("invalid armed state", || async { !harness.dead_letters().await.is_empty() }).await;
+    harness.publish("esp32/contact", "1").await;
+    // topics are not ordered against each other, the door has to be seen first
+    harness.wait_for_readings("contact", 2).await;
+    harness.publish("esp32/motion", "1").await;
+    harness.wait_for_readings("motion", 1).await;
+
+    let emails = harness.wait_for_emails(2).await;
+    assert_eq!(emails[1].subject(), Some("[URGENT] Probable intrusion"));
+    assert_eq!(emails[1].to, vec!["phone@example.com"]);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(harness.smtp.emails().len(), 2);
+    assert_eq!(harness.dead_letters().await[0].2, "invalid armed state");
+
+    harness.stop().await;
+}
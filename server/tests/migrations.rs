@@ -0,0 +1,82 @@
+mod common;
+
+use sqlx::{AnyPool, SqlitePool};
+use sqlx::sqlite::SqlitePoolOptions;
+
+use iiot_webserver::db::{Backend, POSTGRES_MIGRATOR, SQLITE_MIGRATOR};
+
+// applies the migrations with versions in the range, without recording them
+async fn apply(pool: &SqlitePool, versions: std::ops::RangeInclusive<i64>) {
+    for migration in SQLITE_MIGRATOR.iter().filter(|m| versions.contains(&m.version)) {
+        sqlx::raw_sql(&migration.sql).execute(pool).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn moves_legacy_rows_into_readings() {
+    let pool = SqlitePoolOptions::new().max_connections(1).connect("sqlite::memory:").await.unwrap();
+    apply(&pool, 1..=2).await;
+    sqlx::raw_sql(r#"
+        insert into temperature (value, created_at) values (21, '2025-06-01 10:00:00'), (22, '2025-06-01 10:01:00');
+        insert into humidity (value, created_at) values (40, '2025-06-01 10:00:00');
+        insert into motion (value, created_at) values (1, '2025-06-01 10:02:00');
+        insert into contact (value, created_at) values (0, '2025-06-01 10:03:00');
+    "#).execute(&pool).await.unwrap();
+
+    apply(&pool, 3..=i64::MAX).await;
+
+    let readings: Vec<(String, String, f64, String, String)> = sqlx::query_as(
+        "select device, sensor, value, unit, created_at from readings order by sensor, created_at"
+    ).fetch_all(&pool).await.unwrap();
+    assert_eq!(readings, vec![
+        ("esp32".into(), "contact".into(), 0.0, "".into(), "2025-06-01 10:03:00".into()),
+        ("esp32".into(), "humidity".into(), 40.0, "%".into(), "2025-06-01 10:00:00".into()),
+        ("esp32".into(), "motion".into(), 1.0, "".into(), "2025-06-01 10:02:00".into()),
+        ("esp32".into(), "temperature".into(), 21.0, "°C".into(), "2025-06-01 10:00:00".into()),
+        ("esp32".into(), "temperature".into(), 22.0, "°C".into(), "2025-06-01 10:01:00".into()),
+    ]);
+
+    // the old table names keep working as views
+    let temperatures: Vec<f64> = sqlx::query_scalar("select value from temperature order by created_at")
+        .fetch_all(&pool).await.unwrap();
+    assert_eq!(temperatures, vec![21.0, 22.0]);
+    let motion: i64 = sqlx::query_scalar("select count(*) from motion").fetch_one(&pool).await.unwrap();
+    assert_eq!(motion, 1);
+}
+
+#[tokio::test]
+async fn stores_new_readings_in_views() {
+    let pool = common::memory_database().await;
+    sqlx::query("insert into readings (device, sensor, value, unit) values ('esp32', 'humidity', 55.5, '%')")
+        .execute(&pool).await.unwrap();
+    let humidity: Vec<f64> = sqlx::query_scalar("select value from humidity").fetch_all(&pool).await.unwrap();
+    assert_eq!(humidity, vec![55.5]);
+}
+
+// details of the query plan sqlite picks, one line per step
+async fn plan(pool: &AnyPool, query: &str) -> String {
+    let rows: Vec<(i64, i64, i64, String)> = sqlx::query_as(&format!("explain query plan {}", query)).fetch_all(pool).await.unwrap();
+    rows.into_iter().map(|(_, _, _, detail)| detail).collect::<Vec<_>>().join("\n")
+}
+
+#[tokio::test]
+async fn time_range_scans_use_an_index() {
+    let pool = common::memory_database().await;
+    let range = plan(&pool, "select * from readings where created_at >= '2025-06-01' and created_at <= '2025-06-02'").await;
+    assert!(range.contains("USING INDEX readings_created_at"), "{}", range);
+    let sensor = plan(&pool, "select * from readings where sensor = 'temperature' and created_at >= '2025-06-01'").await;
+    assert!(sensor.contains("USING INDEX readings_sensor_created_at"), "{}", sensor);
+}
+
+#[test]
+fn both_backends_have_the_same_migrations() {
+    let versions = |migrator: &sqlx::migrate::Migrator| {
+        migrator.iter().map(|m| (m.version, m.description.to_string())).collect::<Vec<_>>()
+    };
+    assert_eq!(versions(&SQLITE_MIGRATOR), versions(&POSTGRES_MIGRATOR));
+
+    assert_eq!(Backend::from_url("sqlite://iot.sqlite"), Some(Backend::Sqlite));
+    assert_eq!(Backend::from_url("postgres://iiot@localhost/iiot"), Some(Backend::Postgres));
+    assert_eq!(Backend::from_url("postgresql://iiot@localhost/iiot"), Some(Backend::Postgres));
+    assert_eq!(Backend::from_url("mysql://iiot@localhost/iiot"), None);
+}
@@ -0,0 +1,39 @@
+use std::time::Duration;
+
+use iiot_webserver::payload::Payload;
+
+#[test]
+fn parses_plain_values() {
+    let payload = Payload::parse(" -5.3\n").unwrap();
+    assert_eq!(payload, Payload { value: -5.3, unit: None, age: Duration::ZERO, uptime: None, seq: None, firmware: None });
+}
+
+#[test]
+fn parses_json_payload() {
+    let payload = Payload::parse(r#"{"v":1,"value":21.5,"unit":"°C","ts":1200,"up":4200,"seq":7,"fw":"0.1.0"}"#).unwrap();
+    assert_eq!(payload, Payload {
+        value: 21.5,
+        unit: Some("°C".into()),
+        age: Duration::from_secs(3),
+        uptime: Some(Duration::from_millis(4200)),
+        seq: Some(7),
+        firmware: Some("0.1.0".into()),
+    });
+}
+
+#[test]
+fn optional_json_fields_and_unknown_fields() {
+    let payload = Payload::parse(r#"{"v":1,"value":1,"rssi":-60}"#).unwrap();
+    assert_eq!(payload, Payload { value: 1.0, unit: None, age: Duration::ZERO, uptime: None, seq: None, firmware: None });
+}
+
+#[test]
+fn rejects_invalid_payloads() {
+    let error = |payload: &str| Payload::parse(payload).unwrap_err();
+    assert!(error("warm").starts_with("invalid value"));
+    assert!(error("NaN").starts_with("invalid value"));
+    assert!(error(r#"{"value":1}"#).starts_with("invalid payload"));
+    assert!(error(r#"{"v":1,"value":"1"}"#).starts_with("invalid payload"));
+    assert_eq!(error(r#"{"v":2,"value":1}"#), "unsupported payload version 2");
+    assert!(error(r#"{"v":1,"value":1,"ts":10,"up":5}"#).starts_with("invalid payload"));
+}
@@ -0,0 +1,102 @@
+// the server against postgresql, ignored by default as they need a server the tests may create databases on:
+// POSTGRES_TEST_URL=postgres://postgres@localhost/postgres cargo test --test postgres -- --ignored
+mod common;
+
+use chrono::{DateTime, Utc};
+use sqlx::AnyPool;
+
+use common::{at_minute as at, insert_reading, wait_until, Harness};
+use iiot_webserver::alerts::AlertKind;
+use iiot_webserver::backtest;
+use iiot_webserver::config::Config;
+use iiot_webserver::db;
+use iiot_webserver::rules::Rules;
+
+// fresh, migrated database named after the test
+async fn database(name: &str) -> AnyPool {
+    let url = std::env::var("POSTGRES_TEST_URL").expect("POSTGRES_TEST_URL has to point at a postgresql server");
+    sqlx::any::install_default_drivers();
+    let server = AnyPool::connect(&url).await.unwrap();
+    let database = format!("iiot_test_{}", name);
+    for statement in [format!("drop database if exists {} with (force)", database), format!("create database {}", database)] {
+        sqlx::raw_sql(&statement).execute(&server).await.unwrap();
+    }
+    let (server_url, _) = url.rsplit_once('/').unwrap();
+    db::connect(&format!("{}/{}", server_url, database)).await.unwrap()
+}
+
+#[tokio::test]
+#[ignore = "needs POSTGRES_TEST_URL"]
+async fn stores_readings_sequences_and_dead_letters() {
+    let pool = database("subscriber").await;
+    let harness = Harness::start_with("[[route]]\nchannels = [\"log\"]", pool.clone()).await;
+
+    harness.publish("esp32/temperature", r#"{"v":1,"value":-5.3,"unit":"°C","seq":1,"fw":"0.1.0"}"#).await;
+    harness.publish("esp32/humidity", r#"{"v":1,"value":80,"seq":1}"#).await;
+    harness.publish("esp32/humidity", r#"{"v":1,"value":81,"seq":1}"#).await;
+    harness.publish("esp32/humidity", "150").await;
+    harness.wait_for_readings("dew_point", 1).await;
+    wait_until("the impossible humidity", || async { !harness.dead_letters().await.is_empty() }).await;
+
+    assert_eq!(harness.values("temperature").await, vec![-5.3]);
+    // the second humidity reading repeats the sequence number
+    assert_eq!(harness.values("humidity").await, vec![80.0]);
+    let (received, duplicates): (i64, i64) = sqlx::query_as("select received, duplicates from sequence_stats where sensor = 'humidity'")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!((received, duplicates), (1, 1));
+    let (seq, firmware, created_at): (i64, String, String) =
+        sqlx::query_as("select seq, firmware, cast(created_at as text) from readings where sensor = 'temperature'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!((seq, firmware.as_str()), (1, "0.1.0"));
+    let stored = db::parse_timestamp(&created_at).unwrap().and_utc();
+    assert!((Utc::now() - stored).num_seconds().abs() < 60, "{}", created_at);
+    // the compatibility views exist on postgresql as well
+    let temperatures: Vec<f64> = sqlx::query_scalar("select value from temperature").fetch_all(&pool).await.unwrap();
+    assert_eq!(temperatures, vec![-5.3]);
+    harness.stop().await;
+}
+
+#[tokio::test]
+#[ignore = "needs POSTGRES_TEST_URL"]
+async fn rules_and_backtests_read_the_stored_history() {
+    let pool = database("history").await;
+    for minute in 0..90 {
+        insert_reading(&pool, "esp32", "temperature", 21.0, at(minute)).await;
+        insert_reading(&pool, "attic", "temperature", 18.0 + (minute % 2) as f64, at(minute)).await;
+    }
+    insert_reading(&pool, "esp32", "temperature", 35.0, at(90)).await;
+
+    let config = Config::parse(r#"
+        [[route]]
+        channels = ["log"]
+
+        [[rules.threshold]]
+        sensor = "temperature"
+        above = 30
+
+        [[rules.anomaly]]
+        sensor = "temperature"
+        z_score = 4
+        window = 20
+        min_samples = 10
+
+        [rules.fault]
+        flatline_secs = 3600
+    "#).unwrap();
+    let alerts = Rules::new(&config.rules).check_faults(&pool, at(89)).await.unwrap();
+    assert_eq!(alerts.iter().map(|a| a.device.as_str()).collect::<Vec<_>>(), vec!["esp32"]);
+
+    // the anomaly baseline is learned from the readings before the range
+    let fired = backtest::run(&config, &pool, at(80), at(120), Some("attic")).await.unwrap();
+    assert!(fired.iter().all(|f| f.alert.kind != AlertKind::Anomaly));
+    let fired = backtest::run(&config, &pool, at(80), at(120), Some("esp32")).await.unwrap();
+    let kinds: Vec<(AlertKind, DateTime<Utc>)> = fired.iter().filter(|f| f.alert.kind != AlertKind::Fault).map(|f| (f.alert.kind, f.at)).collect();
+    assert_eq!(kinds, vec![(AlertKind::Threshold, at(90)), (AlertKind::Anomaly, at(90))]);
+
+    let readings = db::readings(&pool, at(89), at(90), None).await.unwrap();
+    assert_eq!(readings.iter().map(|r| (r.device.as_str(), r.at)).collect::<Vec<_>>(), vec![("esp32", at(89)), ("attic", at(89)), ("esp32", at(90))]);
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{config_file, Harness};
+
+const THRESHOLD: &str = r#"
+    [[route]]
+    recipients = ["admin@example.com"]
+
+    [[rules.threshold]]
+    sensor = "temperature"
+    above = 30
+"#;
+
+#[tokio::test]
+async fn swaps_rules_and_routes_without_reconnecting() {
+    let harness = Harness::start(r#"
+        [[route]]
+        recipients = ["admin@example.com"]
+    "#).await;
+    harness.publish("esp32/temperature", "35").await;
+    harness.wait_for_readings("temperature", 1).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(harness.smtp.emails().is_empty());
+
+    harness.reload("reload-valid", r#"
+        [[route]]
+        kinds = ["threshold"]
+        recipients = ["household@example.com"]
+
+        [[rules.threshold]]
+        sensor = "temperature"
+        above = 30
+
+        [shutdown]
+        timeout_secs = 1
+    "#).unwrap();
+    harness.publish("esp32/temperature", "35").await;
+    let emails = harness.wait_for_emails(1).await;
+    assert_eq!(emails[0].to, vec!["household@example.com"]);
+    assert_eq!(emails[0].subject(), Some("temperature alert"));
+
+    // the retained probe would have been stored again after a reconnect
+    assert_eq!(harness.probes().await, 1);
+    // sections that need a restart keep their running values
+    assert_eq!(harness.config.borrow().shutdown.timeout_secs, 10);
+    assert_eq!(harness.config.borrow().rules.threshold.len(), 1);
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn cooldowns_carry_over_a_reload() {
+    let harness = Harness::start(r#"
+        [[route]]
+        recipients = ["admin@example.com"]
+
+        [cooldowns]
+        default_secs = 600
+    "#).await;
+    harness.publish("esp32/motion", "1").await;
+    harness.wait_for_emails(1).await;
+
+    harness.reload("reload-cooldown", r#"
+        [[route]]
+        recipients = ["household@example.com"]
+
+        [cooldowns]
+        default_secs = 600
+    "#).unwrap();
+    // the motion alert is still cooling down, the contact alert goes out to the new route
+    harness.publish("esp32/motion", "1").await;
+    harness.publish("esp32/contact", "1").await;
+    let emails = harness.wait_for_emails(2).await;
+    assert_eq!(emails[1].to, vec!["household@example.com"]);
+    assert_eq!(emails[1].subject(), Some("Contact alert"));
+    harness.wait_for_readings("motion", 2).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(harness.smtp.emails().len(), 2);
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn rejects_invalid_and_missing_files_keeping_the_running_rules() {
+    let harness = Harness::start(THRESHOLD).await;
+
+    let invalid = harness.reload("reload-invalid", r#"
+        [[route]]
+        recipients = ["household@example.com"]
+
+        [[rules.threshold]]
+        sensor = "temperature"
+    "#).unwrap_err();
+    assert!(invalid.contains("needs `above`, `below` or both"), "{}", invalid);
+    let unparsable = harness.reload("reload-unparsable", "[[rules.threshold]\n").unwrap_err();
+    assert!(unparsable.contains("Cannot parse config file"), "{}", unparsable);
+
+    // a deleted file, or one caught while an editor replaces it, must not turn into the defaults,
+    // even when the environment alone would make them a valid config
+    let path = config_file("reload-deleted", "");
+    std::fs::remove_file(&path).unwrap();
+    let env = |name: &str| match name {
+        "DATABASE_URL" => Some("sqlite::memory:".to_string()),
+        "MQTT_HOST" => Some("127.0.0.1".to_string()),
+        "EMAIL_USERNAME" => Some("alerts@example.com".to_string()),
+        "EMAIL_PASSWORD" => Some("secret".to_string()),
+        "EMAIL_RECIPIENT" => Some("household@example.com".to_string()),
+        _ => None,
+    };
+    let missing = harness.reload_from(&path, env).unwrap_err();
+    assert!(missing.contains("Cannot read config file"), "{}", missing);
+
+    harness.publish("esp32/temperature", "35").await;
+    let emails = harness.wait_for_emails(1).await;
+    assert_eq!(emails[0].to, vec!["admin@example.com"]);
+    assert_eq!(harness.config.borrow().rules.threshold[0].above, Some(30.0));
+    harness.stop().await;
+}
@@ -0,0 +1,106 @@
+mod common;
+
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use iiot_protocol::{Reading, SensorKind};
+use rumqttc::{AsyncClient, MqttOptions};
+use sqlx::AnyPool;
+
+use common::{at, insert_reading, Harness};
+use iiot_webserver::config::Config;
+use iiot_webserver::replay::{self, Pacing};
+use iiot_webserver::simulator::Message;
+
+// marks every stored reading as sent by firmware 0.1.0
+async fn recorded_by_firmware(pool: &AnyPool) {
+    sqlx::query("update readings set firmware = '0.1.0'").execute(pool).await.unwrap();
+}
+
+const TOPICS: &str = r#"
+    [[topics]]
+    topic = "home/kitchen/temp"
+    device = "kitchen"
+    sensor = "temperature"
+
+    [[topics]]
+    topic = "esp32/humidity"
+    sensor = "humidity"
+"#;
+
+#[tokio::test]
+async fn loads_readings_on_their_original_topics() {
+    let pool = common::memory_database().await;
+    insert_reading(&pool, "kitchen", "temperature", -5.3, at(0)).await;
+    insert_reading(&pool, "esp32", "humidity", 55.0, at(1)).await;
+    insert_reading(&pool, "esp32", "dew_point", 12.1, at(1)).await;
+    insert_reading(&pool, "attic", "motion", 1.0, at(2)).await;
+    insert_reading(&pool, "esp32", "humidity", 56.0, at(10)).await;
+    recorded_by_firmware(&pool).await;
+    let config = Config::parse(TOPICS).unwrap();
+
+    let recorded = replay::load(&config, &pool, at(0), at(5), None).await.unwrap();
+    let topics: Vec<(DateTime<Utc>, &str)> = recorded.iter().map(|r| (r.at, r.message.topic.as_str())).collect();
+    // derived metrics are left out, devices without a configured topic use the default layout
+    assert_eq!(topics, vec![(at(0), "home/kitchen/temp"), (at(1), "esp32/humidity"), (at(2), "attic/motion")]);
+    let reading = Reading::decode(recorded[0].message.payload.as_bytes()).unwrap();
+    assert_eq!(reading, Reading { fw: Some("0.1.0"), ..Reading::new(SensorKind::Temperature, -5.3) });
+
+    let recorded = replay::load(&config, &pool, at(0), at(10), Some("esp32")).await.unwrap();
+    assert_eq!(recorded.iter().map(|r| r.at).collect::<Vec<_>>(), vec![at(1), at(10)]);
+}
+
+#[test]
+fn pacing_divides_the_recorded_gaps() {
+    let gap = Duration::from_secs(10);
+    assert_eq!(Pacing::Speed(1.0).delay(gap), gap);
+    assert_eq!(Pacing::Speed(20.0).delay(gap), Duration::from_millis(500));
+    assert_eq!(Pacing::Fast.delay(gap), Duration::ZERO);
+    assert_eq!(Pacing::Speed(1e-300).delay(gap), Duration::MAX);
+}
+
+#[test]
+fn only_finite_positive_speeds_are_accepted() {
+    assert_eq!(Pacing::speed(2.5), Some(Pacing::Speed(2.5)));
+    for speed in [0.0, -1.0, f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+        assert_eq!(Pacing::speed(speed), None, "{}", speed);
+    }
+}
+
+#[tokio::test]
+async fn a_fresh_server_stores_the_replayed_readings() {
+    let recorded_pool = common::memory_database().await;
+    for (i, value) in [21.5, 22.0, 22.5].into_iter().enumerate() {
+        insert_reading(&recorded_pool, "esp32", "temperature", value, at(i as i64)).await;
+        insert_reading(&recorded_pool, "esp32", "motion", 0.0, at(i as i64)).await;
+    }
+    recorded_by_firmware(&recorded_pool).await;
+    let config = Config::parse(r#"
+        [[topics]]
+        topic = "esp32/temperature"
+        sensor = "temperature"
+
+        [[topics]]
+        topic = "esp32/motion"
+        sensor = "motion"
+    "#).unwrap();
+    let recorded = replay::load(&config, &recorded_pool, at(0), at(10), None).await.unwrap();
+    assert_eq!(recorded[1].message, Message {
+        topic: "esp32/motion".to_string(),
+        payload: r#"{"v":1,"value":0.0,"unit":"","fw":"0.1.0"}"#.to_string(),
+    });
+
+    let harness = Harness::start("[[route]]\nchannels = [\"log\"]").await;
+    let mut options = MqttOptions::new("iiot-test-replay", "127.0.0.1", harness.broker_port);
+    options.set_keep_alive(Duration::from_secs(5));
+    let (client, mut event_loop) = AsyncClient::new(options, 100);
+    tokio::spawn(async move { while event_loop.poll().await.is_ok() {} });
+
+    // two gaps of a second each at 10x speed
+    let started = tokio::time::Instant::now();
+    replay::publish(&client, &recorded, Pacing::Speed(10.0)).await.unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(200), "{:?}", started.elapsed());
+    harness.wait_for_readings("temperature", 3).await;
+    harness.wait_for_readings("motion", 3).await;
+    assert_eq!(harness.values("temperature").await, vec![21.5, 22.0, 22.5]);
+    harness.stop().await;
+}
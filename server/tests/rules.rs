@@ -0,0 +1,87 @@
+mod common;
+
+use chrono::TimeDelta;
+use sqlx::AnyPool;
+
+use common::{assert_invalid, at, at_minute, insert_reading, rules};
+use iiot_webserver::alerts::AlertKind;
+use iiot_webserver::rules::{time_outside, MAX_HOLD};
+
+// stores one humidity reading every 5 minutes from `from` to `to` hours
+async fn humidity(pool: &AnyPool, device: &str, from: i64, to: i64, value: f64) {
+    for minute in (from * 60..to * 60).step_by(5) {
+        insert_reading(pool, device, "humidity", value, at_minute(minute)).await;
+    }
+}
+
+const MOLD_RULE: &str = r#"
+    [[rules.sustained]]
+    sensor = "humidity"
+    above = 70
+    min_secs = 21600
+    window_secs = 86400
+"#;
+
+#[test]
+fn counts_time_until_the_next_reading() {
+    let readings = [(at(0), 75.0), (at(3600), 65.0), (at(2 * 3600), 80.0), (at(2 * 3600 + 6 * 60), 80.0)];
+    let outside = time_outside(&readings, at(3 * 3600), |value| value > 70.0);
+    // the first reading counts until the second, the last ones until the next or MAX_HOLD
+    assert_eq!(outside, MAX_HOLD.min(TimeDelta::hours(1)) + TimeDelta::minutes(6) + MAX_HOLD);
+    assert_eq!(time_outside(&[], at(3 * 3600), |_| true), TimeDelta::zero());
+}
+
+#[tokio::test]
+async fn raises_mold_risk_once_while_it_lasts() {
+    let pool = common::memory_database().await;
+    let rules = rules(MOLD_RULE);
+
+    // 5 hours above the limit are not enough
+    humidity(&pool, "esp32", 0, 5, 75.0).await;
+    humidity(&pool, "esp32", 5, 10, 60.0).await;
+    assert!(rules.check_sustained(&pool, at(10 * 3600)).await.unwrap().is_empty());
+
+    // 2 more hours make 7 hours within the last 24
+    humidity(&pool, "esp32", 10, 12, 80.0).await;
+    let alerts = rules.check_sustained(&pool, at(12 * 3600)).await.unwrap();
+    assert_eq!(alerts.len(), 1);
+    assert_eq!(alerts[0].kind, AlertKind::Mold);
+    assert_eq!(alerts[0].subject, "Mold risk");
+    assert!(alerts[0].body.starts_with("humidity on esp32 was above 70 for 7.0 hours"), "{}", alerts[0].body);
+    assert!(rules.check_sustained(&pool, at(12 * 3600 + 30 * 60)).await.unwrap().is_empty());
+
+    // once the damp hours leave the window the rule recovers, and can be raised again later
+    humidity(&pool, "esp32", 12, 30, 60.0).await;
+    assert!(rules.check_sustained(&pool, at(30 * 3600)).await.unwrap().is_empty());
+    humidity(&pool, "esp32", 30, 37, 75.0).await;
+    assert_eq!(rules.check_sustained(&pool, at(37 * 3600)).await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn evaluates_devices_separately() {
+    let pool = common::memory_database().await;
+    let rules = rules(&format!("{}\ndevice = \"cellar\"", MOLD_RULE));
+
+    humidity(&pool, "esp32", 0, 8, 90.0).await;
+    assert!(rules.check_sustained(&pool, at(8 * 3600)).await.unwrap().is_empty());
+    humidity(&pool, "cellar", 0, 8, 90.0).await;
+    let alerts = rules.check_sustained(&pool, at(8 * 3600)).await.unwrap();
+    assert_eq!(alerts.len(), 1);
+    assert_eq!(alerts[0].device, "cellar");
+}
+
+#[test]
+fn validates_sustained_rules() {
+    assert_invalid(r#"
+        [[rules.sustained]]
+        alert = "threshold"
+        sensor = "motion"
+        min_secs = 100
+        window_secs = 50
+    "#, &[
+        "`alert` must be mold or condensation",
+        "do not apply to motion",
+        "needs `above`, `below` or both",
+        "`min_secs` must be greater than 0",
+    ]);
+}
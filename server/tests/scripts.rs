@@ -0,0 +1,205 @@
+mod common;
+
+use std::path::PathBuf;
+
+use common::{at_minute as at, insert_reading};
+use iiot_webserver::alerts::Severity;
+use iiot_webserver::config::{Config, Metric, SensorKind};
+use iiot_webserver::scripts::{ScriptAction, ScriptReading, Scripts};
+
+const HUMIDITY: Metric = Metric::Sensor(SensorKind::Humidity);
+
+// writes a script to a file of its own in the temp directory
+fn script(name: &str, source: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("iiot-{}-{}.rhai", std::process::id(), name));
+    std::fs::write(&path, source).unwrap();
+    path
+}
+
+fn scripts(rules: &str) -> Scripts {
+    Scripts::new(&Config::parse(rules).unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn scripts_see_the_reading_with_history_and_emit_actions() {
+    let pool = common::memory_database().await;
+    let path = script("bathroom", r#"
+        let first = history[0];
+        if reading.value - first.value > 10 && latest.temperature < 18 {
+            alert("Shower in a cold bathroom", `humidity rose ${reading.value - first.value} in ${first.secs_ago / 60} minutes`, "high");
+            publish("esp32/command", `{"set_enabled":{"sensor":"motion","enabled":true}}`);
+            annotate("shower");
+        }
+    "#);
+    let scripts = scripts(&format!("[[rules.script]]\npath = {:?}\nsensors = [\"humidity\"]\nhistory_secs = 1800", path));
+
+    insert_reading(&pool, "esp32", "temperature", 17.0, at(0)).await;
+    for (minute, value) in [(0, 50.0), (10, 55.0), (20, 65.0)] {
+        insert_reading(&pool, "esp32", "humidity", value, at(minute)).await;
+    }
+    let reading = ScriptReading { device: "esp32", metric: HUMIDITY, value: 65.0, at: at(20) };
+    let actions = scripts.run(&pool, &reading).await.unwrap();
+    let name = path.file_stem().unwrap().to_string_lossy().to_string();
+    assert_eq!(actions, vec![
+        (name.clone(), ScriptAction::Alert {
+            subject: "Shower in a cold bathroom".to_string(),
+            body: "humidity rose 15.0 in 20 minutes".to_string(),
+            severity: Severity::High,
+        }),
+        (name.clone(), ScriptAction::Publish {
+            script: path.clone(),
+            topic: "esp32/command".to_string(),
+            payload: r#"{"set_enabled":{"sensor":"motion","enabled":true}}"#.to_string(),
+            retain: false,
+        }),
+        (name, ScriptAction::Annotate { text: "shower".to_string() }),
+    ]);
+
+    // the script only runs for humidity
+    let reading = ScriptReading { device: "esp32", metric: Metric::Sensor(SensorKind::Temperature), value: 17.0, at: at(20) };
+    assert!(scripts.run(&pool, &reading).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn failing_scripts_do_not_affect_the_others() {
+    let pool = common::memory_database().await;
+    insert_reading(&pool, "esp32", "humidity", 50.0, at(0)).await;
+    let endless = script("endless", "loop { }");
+    let broken = script("broken", r#"alert("partial", "sent before the error"); reading.missing.value"#);
+    let severity = script("severity", r#"alert("a", "b", "urgent")"#);
+    let working = script("working", r#"annotate(`${reading.sensor} ${reading.value}${reading.unit}`)"#);
+    // the server subscribes to the topic, the reading would come back and run the script again
+    let feedback = script("feedback", r#"annotate("sent before the error"); publish("esp32/humidity", "99")"#);
+    let scripts = scripts(&format!(r#"
+        [[rules.script]]
+        path = {:?}
+        max_operations = 1000
+
+        [[rules.script]]
+        path = {:?}
+
+        [[rules.script]]
+        path = {:?}
+
+        [[rules.script]]
+        path = {:?}
+
+        [[rules.script]]
+        path = {:?}
+    "#, endless, broken, severity, working, feedback));
+
+    let reading = ScriptReading { device: "esp32", metric: HUMIDITY, value: 50.0, at: at(0) };
+    let actions = scripts.run(&pool, &reading).await.unwrap();
+    assert_eq!(actions.len(), 1);
+    assert_eq!(actions[0].1, ScriptAction::Annotate { text: "humidity 50.0%".to_string() });
+}
+
+#[test]
+fn rejects_scripts_that_do_not_compile() {
+    let path = script("syntax", "if reading.value > { alert(");
+    let config = Config::parse(&format!("[[rules.script]]\npath = {:?}", path)).unwrap();
+    let error = Scripts::new(&config).err().unwrap().to_string();
+    assert!(error.starts_with("Script Error: ") && error.contains("syntax"), "{}", error);
+
+    let config = Config::parse("[[rules.script]]\npath = \"/nonexistent/rule.rhai\"").unwrap();
+    assert!(Scripts::new(&config).is_err());
+
+    // the example shipped with the server compiles
+    let config = Config::parse("[[rules.script]]\npath = \"scripts/example.rhai\"").unwrap();
+    assert!(Scripts::new(&config).is_ok());
+}
+
+#[tokio::test]
+async fn subscriber_raises_script_alerts_and_stores_annotations() {
+    let path = script("freezing", r#"
+        if reading.value < 0 {
+            alert("Freezing", `${reading.device} is at ${reading.value}`);
+            annotate("below zero");
+        }
+    "#);
+    let harness = common::Harness::start(&format!(r#"
+        [[route]]
+        kinds = ["script"]
+        recipients = ["alice@example.com"]
+
+        [[rules.script]]
+        path = {:?}
+        sensors = ["temperature"]
+    "#, path)).await;
+
+    harness.publish("esp32/temperature", "-2.5").await;
+    let emails = harness.wait_for_emails(1).await;
+    assert_eq!(emails[0].subject(), Some("Freezing"));
+    common::wait_until("annotation", || async {
+        sqlx::query_scalar::<_, i64>("select count(*) from annotations").fetch_one(&harness.pool).await.unwrap() > 0
+    }).await;
+    let annotations: Vec<(String, String, String)> = sqlx::query_as("select device, sensor, text from annotations")
+        .fetch_all(&harness.pool).await.unwrap();
+    assert_eq!(annotations, vec![("esp32".to_string(), "temperature".to_string(), "below zero".to_string())]);
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn subscriber_publishes_for_scripts_with_rate_limits() {
+    let path = script("heater", r#"
+        if reading.value < 0 {
+            publish("home/heater/set", "on");
+        }
+        publish(`home/heater/seen/${reading.value}`, "1");
+    "#);
+    let harness = common::Harness::start(&format!(r#"
+        [[route]]
+        channels = ["log"]
+
+        [[rules.script]]
+        path = {:?}
+        sensors = ["temperature"]
+        min_interval_secs = 600
+    "#, path)).await;
+    harness.subscribe("home/#").await;
+
+    harness.publish("esp32/temperature", "-2.5").await;
+    harness.publish("esp32/temperature", "-3").await;
+    // sent after the second reading asked for the heater again, which is within the interval of the first
+    harness.wait_for_messages("home/heater/seen/-3.0", 1).await;
+    let heater: Vec<String> = harness.messages().into_iter().filter(|m| m.topic == "home/heater/set").map(|m| m.payload).collect();
+    assert_eq!(heater, vec!["on"]);
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn scripts_with_the_same_file_name_are_rate_limited_apart() {
+    let paths: Vec<PathBuf> = ["upstairs", "downstairs"].iter().map(|dir| {
+        let dir = std::env::temp_dir().join(format!("iiot-{}-{}", std::process::id(), dir));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("heater.rhai");
+        std::fs::write(&path, r#"
+            publish("home/heater/set", "on");
+            publish(`home/heater/seen/${reading.value}`, "1");
+        "#).unwrap();
+        path
+    }).collect();
+    let harness = common::Harness::start(&format!(r#"
+        [[route]]
+        channels = ["log"]
+
+        [[rules.script]]
+        path = {:?}
+        sensors = ["temperature"]
+        min_interval_secs = 600
+
+        [[rules.script]]
+        path = {:?}
+        sensors = ["temperature"]
+        min_interval_secs = 600
+    "#, paths[0], paths[1])).await;
+    harness.subscribe("home/#").await;
+
+    harness.publish("esp32/temperature", "-2.5").await;
+    harness.publish("esp32/temperature", "-3").await;
+    // sent by both scripts after the second reading, which is within the interval of the first for each of them
+    harness.wait_for_messages("home/heater/seen/-3.0", 2).await;
+    assert_eq!(harness.messages().iter().filter(|m| m.topic == "home/heater/set").count(), 2);
+    harness.stop().await;
+}
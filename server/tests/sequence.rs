@@ -0,0 +1,26 @@
+use std::time::Duration;
+
+use iiot_webserver::sequence::SequenceCheck;
+
+fn ms(ms: u64) -> Option<Duration> {
+    Some(Duration::from_millis(ms))
+}
+
+#[test]
+fn classifies_sequence_numbers() {
+    assert_eq!(SequenceCheck::classify(None, None, 5, ms(100)), SequenceCheck::First);
+    assert_eq!(SequenceCheck::classify(Some(5), ms(100), 6, ms(200)), SequenceCheck::InOrder { missing: 0 });
+    assert_eq!(SequenceCheck::classify(Some(5), ms(100), 9, ms(200)), SequenceCheck::InOrder { missing: 3 });
+    assert_eq!(SequenceCheck::classify(Some(5), ms(100), 5, ms(200)), SequenceCheck::Duplicate);
+    assert_eq!(SequenceCheck::classify(Some(5), ms(100), 2, ms(200)), SequenceCheck::Replay);
+    // without uptimes a lower number cannot be told apart from a replay
+    assert_eq!(SequenceCheck::classify(Some(5), None, 1, None), SequenceCheck::Replay);
+}
+
+#[test]
+fn lower_uptime_means_restart() {
+    assert_eq!(SequenceCheck::classify(Some(500), ms(90_000), 1, ms(3_000)), SequenceCheck::Restarted);
+    assert!(SequenceCheck::Restarted.is_accepted());
+    assert!(!SequenceCheck::Duplicate.is_accepted());
+    assert!(!SequenceCheck::Replay.is_accepted());
+}
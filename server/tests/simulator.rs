@@ -0,0 +1,148 @@
+mod common;
+
+use std::time::Duration;
+use iiot_protocol::{Command, Reading, SensorKind, Topic};
+use iiot_webserver::simulator::{tick_period, Device, Message, Scenario, TICK_MS};
+
+// runs the device for the given simulated time, returning (ms, sensor, value, seq) of each message
+fn run(device: &mut Device, secs: u64) -> Vec<(u64, SensorKind, f64, u32)> {
+    let mut sent = Vec::new();
+    for _ in 0..secs * 1000 / TICK_MS {
+        for Message { topic, payload } in device.tick() {
+            let Some(Topic::Reading { device: "esp32", sensor }) = Topic::parse(&topic) else {
+                panic!("unexpected topic {}", topic);
+            };
+            let reading = Reading::decode(payload.as_bytes()).unwrap();
+            assert_eq!(reading.ts, Some(device.uptime_ms()));
+            sent.push((device.uptime_ms(), sensor, reading.value, reading.seq.unwrap()));
+        }
+    }
+    sent
+}
+
+fn of(sent: &[(u64, SensorKind, f64, u32)], sensor: SensorKind) -> Vec<(u64, f64)> {
+    sent.iter().filter(|m| m.1 == sensor).map(|m| (m.0, m.2)).collect()
+}
+
+#[test]
+fn idle_device_follows_firmware_timing() {
+    let mut device = Device::new(&Scenario::default(), 1);
+    let sent = run(&mut device, 10);
+
+    // dht every 2 seconds, idle binary sensors send 0 every delay + 1 seconds
+    let temperature = of(&sent, SensorKind::Temperature);
+    assert_eq!(temperature, vec![(2000, 21.0), (4000, 21.0), (6000, 21.0), (8000, 21.0), (10000, 21.0)]);
+    assert_eq!(of(&sent, SensorKind::Humidity).len(), 5);
+    assert_eq!(of(&sent, SensorKind::Motion), vec![(3000, 0.0), (6000, 0.0), (9000, 0.0)]);
+    assert_eq!(of(&sent, SensorKind::Contact), vec![(3000, 0.0), (6000, 0.0), (9000, 0.0)]);
+
+    // numbered per sensor
+    let seqs: Vec<u32> = sent.iter().filter(|m| m.1 == SensorKind::Motion).map(|m| m.3).collect();
+    assert_eq!(seqs, vec![1, 2, 3]);
+}
+
+#[test]
+fn scripted_steps() {
+    let scenario = Scenario::parse(r#"
+        duration_secs = 12
+
+        [[step]]
+        at_secs = 1
+        motion = true
+
+        [[step]]
+        at_secs = 2
+        motion = true
+
+        [[step]]
+        at_secs = 5
+        temperature = -5.3
+        mqtt = false
+
+        [[step]]
+        at_secs = 9
+        mqtt = true
+    "#).unwrap();
+    let mut device = Device::new(&scenario, 1);
+    let sent = run(&mut device, 12);
+    assert!(device.finished());
+
+    // the second trigger is within the delay and ignored, then 0 follows after delay + 1 seconds
+    assert_eq!(of(&sent, SensorKind::Motion), vec![(1000, 1.0), (4000, 0.0), (10000, 0.0)]);
+    // nothing is sent while mqtt is disabled
+    assert_eq!(of(&sent, SensorKind::Temperature), vec![(2000, 21.0), (4000, 21.0), (10000, -5.3), (12000, -5.3)]);
+    // numbering continues after the gap, as only sent messages are numbered
+    let seqs: Vec<u32> = sent.iter().filter(|m| m.1 == SensorKind::Temperature).map(|m| m.3).collect();
+    assert_eq!(seqs, vec![1, 2, 3, 4]);
+}
+
+#[test]
+fn commands_change_delays_and_switches() {
+    let mut device = Device::new(&Scenario::default(), 1);
+    assert_eq!(Topic::parse(&device.command_topic()), Some(Topic::Command { device: "esp32" }));
+    let mut sent = run(&mut device, 4);
+
+    // sent the way the bin receives them from the broker
+    for payload in [r#"{"set_delay":{"sensor":"humidity","secs":5}}"#, r#"{"set_enabled":{"sensor":"motion","enabled":false}}"#] {
+        device.command(Command::decode(payload.as_bytes()).unwrap());
+    }
+    sent.extend(run(&mut device, 6));
+    // temperature and humidity share the dht delay
+    assert_eq!(of(&sent, SensorKind::Temperature).iter().map(|m| m.0).collect::<Vec<_>>(), vec![2000, 4000, 9000]);
+    assert_eq!(of(&sent, SensorKind::Motion), vec![(3000, 0.0)]);
+    assert_eq!(of(&sent, SensorKind::Contact).len(), 3);
+
+    // delays are kept in the range of the firmware menu, and nothing is sent with mqtt disabled
+    device.command(Command::SetDelay { sensor: SensorKind::Contact, secs: 0 });
+    device.command(Command::SetMqtt { enabled: false });
+    assert!(run(&mut device, 10).is_empty());
+    device.command(Command::SetMqtt { enabled: true });
+    let sent = run(&mut device, 4);
+    assert_eq!(of(&sent, SensorKind::Contact).iter().map(|m| m.0).collect::<Vec<_>>(), vec![21000, 24000]);
+}
+
+#[test]
+fn random_traffic_is_repeatable() {
+    let scenario = Scenario::parse(r#"
+        [random]
+        motion_per_hour = 600
+        contact_per_hour = 600
+        temperature_drift = 0.5
+    "#).unwrap();
+    let first = run(&mut Device::new(&scenario, 42), 120);
+    let second = run(&mut Device::new(&scenario, 42), 120);
+    assert_eq!(first, second);
+    assert!(of(&first, SensorKind::Motion).iter().any(|(_, value)| *value == 1.0));
+    assert!(of(&first, SensorKind::Temperature).iter().any(|(_, value)| *value != 21.0));
+}
+
+#[tokio::test]
+async fn server_stores_simulated_traffic() {
+    let harness = common::Harness::start(r#"
+        [[route]]
+        recipients = ["alice@example.com"]
+    "#).await;
+    let mut device = Device::new(&Scenario::default(), 1);
+    for _ in 0..10_000 / TICK_MS {
+        for message in device.tick() {
+            harness.publish(&message.topic, &message.payload).await;
+        }
+    }
+    // the broker keeps the order within a topic only
+    harness.wait_for_readings("temperature", 5).await;
+    harness.wait_for_readings("humidity", 5).await;
+    harness.wait_for_readings("motion", 3).await;
+    assert_eq!(harness.values("temperature").await, vec![21.0; 5]);
+    assert!(harness.dead_letters().await.is_empty());
+    harness.stop().await;
+}
+
+#[test]
+fn the_tick_period_follows_the_speed() {
+    assert_eq!(tick_period(1.0), Some(Duration::from_millis(TICK_MS)));
+    assert_eq!(tick_period(10.0), Some(Duration::from_millis(TICK_MS / 10)));
+    // no zero period for the interval, and no panic on speeds the division cannot represent
+    for speed in [0.0, -1.0, f64::NAN, f64::INFINITY, 1e300, 1e-320] {
+        assert_eq!(tick_period(speed), None, "{}", speed);
+    }
+}
@@ -0,0 +1,223 @@
+mod common;
+
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, TimeZone, Utc};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+use common::{wait_until, Harness};
+use iiot_webserver::config::Config;
+use iiot_webserver::sinks::{line, run_sink, Buffering, InfluxDb, Point};
+
+// request received by the stand-in backend
+#[derive(Debug, Clone)]
+struct Request {
+    path: String,
+    authorization: Option<String>,
+    body: String,
+}
+
+// minimal http server answering every request with the status it is set to, like an influxdb write endpoint
+#[derive(Clone)]
+struct FakeInflux {
+    port: u16,
+    status: Arc<AtomicU16>,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl FakeInflux {
+    async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let fake = Self {
+            port: listener.local_addr().unwrap().port(),
+            status: Arc::new(AtomicU16::new(204)),
+            requests: Arc::new(Mutex::new(Vec::new())),
+        };
+        let server = fake.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(server.clone().handle(stream));
+            }
+        });
+        fake
+    }
+
+    async fn handle(self, stream: TcpStream) {
+        let mut reader = BufReader::new(stream);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).await.unwrap();
+        let path = request_line.split(' ').nth(1).unwrap_or_default().to_string();
+        let (mut length, mut authorization) = (0, None);
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).await.unwrap();
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            let (name, value) = header.split_once(": ").unwrap();
+            match name.to_ascii_lowercase().as_str() {
+                "content-length" => length = value.parse().unwrap(),
+                "authorization" => authorization = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).await.unwrap();
+        // read before the request is recorded, so a test changing it after seeing the request cannot race the answer
+        let status = self.status.load(Ordering::SeqCst);
+        self.requests.lock().unwrap().push(Request { path, authorization, body: String::from_utf8(body).unwrap() });
+        let response = format!("HTTP/1.1 {} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status);
+        let _ = reader.get_mut().write_all(response.as_bytes()).await;
+    }
+
+    fn url(&self) -> String {
+        format!("http://127.0.0.1:{}/api/v2/write?org=home&bucket=iiot", self.port)
+    }
+
+    fn set_status(&self, status: u16) {
+        self.status.store(status, Ordering::SeqCst);
+    }
+
+    fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    // lines of every request received so far
+    fn written(&self) -> Vec<String> {
+        self.requests().iter().flat_map(|r| r.body.lines().map(str::to_string).collect::<Vec<_>>()).collect()
+    }
+}
+
+fn at(secs: i64) -> DateTime<Utc> {
+    Utc.timestamp_opt(1_748_779_200 + secs, 0).unwrap()
+}
+
+fn point(sensor: &str, value: f64, secs: i64) -> Point {
+    Point { device: "esp32".to_string(), sensor: sensor.to_string(), unit: "°C".to_string(), value, at: at(secs) }
+}
+
+fn influx(fake: &FakeInflux, extra: &str) -> (InfluxDb, Buffering) {
+    let config = Config::parse(&format!("[sinks.influxdb]\nurl = \"{}\"\n{}", fake.url(), extra)).unwrap();
+    let influx = config.sinks.influxdb.unwrap();
+    (InfluxDb::new(&influx).unwrap(), influx.buffering())
+}
+
+#[test]
+fn readings_are_written_as_line_protocol() {
+    assert_eq!(line("readings", &point("temperature", -5.3, 0)), "readings,device=esp32,sensor=temperature,unit=°C value=-5.3 1748779200000000000");
+    let motion = Point { unit: String::new(), value: 1.0, ..point("motion", 0.0, 1) };
+    assert_eq!(line("readings", &motion), "readings,device=esp32,sensor=motion value=1.0 1748779201000000000");
+    let odd = Point { device: "hall, door=1".to_string(), ..point("temperature", 20.0, 0) };
+    assert_eq!(line("my readings", &odd), r"my\ readings,device=hall\,\ door\=1,sensor=temperature,unit=°C value=20.0 1748779200000000000");
+}
+
+#[tokio::test]
+async fn batches_are_retried_until_the_backend_is_back() {
+    let fake = FakeInflux::start().await;
+    fake.set_status(503);
+    let (sink, buffering) = influx(&fake, "token = \"secret\"\nbatch_size = 2\nflush_secs = 1\nretry_secs = 1");
+    let (sender, receiver) = mpsc::channel(10);
+    let task = tokio::spawn(run_sink(sink, receiver, buffering));
+
+    for i in 0..3 {
+        sender.send(point("temperature", 20.0 + i as f64, i)).await.unwrap();
+    }
+    wait_until("a failed write", || async { !fake.requests().is_empty() }).await;
+    fake.set_status(204);
+    let expected: Vec<String> = (0..3).map(|i| line("readings", &point("temperature", 20.0 + i as f64, i))).collect();
+    wait_until("the readings to be written", || async {
+        let accepted = fake.requests().len() >= 3;
+        accepted && fake.written().ends_with(&expected[1..])
+    }).await;
+
+    let requests = fake.requests();
+    // the failed batch is sent again as it was, the third reading follows on its own
+    assert_eq!(requests[0].body, expected[..2].join("\n"));
+    assert_eq!(requests[requests.len() - 2].body, expected[..2].join("\n"));
+    assert_eq!(requests[requests.len() - 1].body, expected[2]);
+    assert_eq!(requests[0].path, "/api/v2/write?org=home&bucket=iiot");
+    assert_eq!(requests[0].authorization.as_deref(), Some("Token secret"));
+
+    drop(sender);
+    tokio::time::timeout(Duration::from_secs(5), task).await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn the_oldest_readings_are_dropped_when_the_buffer_is_full() {
+    let fake = FakeInflux::start().await;
+    fake.set_status(500);
+    let (sink, buffering) = influx(&fake, "batch_size = 1\nbuffer_size = 2\nflush_secs = 60\nretry_secs = 60");
+    let (sender, receiver) = mpsc::channel(10);
+    let task = tokio::spawn(run_sink(sink, receiver, buffering));
+
+    sender.send(point("temperature", 1.0, 0)).await.unwrap();
+    wait_until("a failed write", || async { !fake.requests().is_empty() }).await;
+    for i in 2..5 {
+        sender.send(point("temperature", i as f64, i)).await.unwrap();
+    }
+    fake.set_status(204);
+    // closing the channel writes what is left right away, without waiting for the retry time
+    drop(sender);
+    tokio::time::timeout(Duration::from_secs(5), task).await.unwrap().unwrap();
+
+    let bodies: Vec<String> = fake.requests().into_iter().skip(1).map(|r| r.body).collect();
+    assert_eq!(bodies, vec![line("readings", &point("temperature", 3.0, 3)), line("readings", &point("temperature", 4.0, 4))]);
+}
+
+#[tokio::test]
+async fn rejected_batches_are_dropped_instead_of_blocking_later_readings() {
+    let fake = FakeInflux::start().await;
+    fake.set_status(400);
+    let (sink, buffering) = influx(&fake, "batch_size = 1\nflush_secs = 1\nretry_secs = 60");
+    let (sender, receiver) = mpsc::channel(10);
+    let task = tokio::spawn(run_sink(sink, receiver, buffering));
+
+    sender.send(point("temperature", 1.0, 0)).await.unwrap();
+    wait_until("the rejected write", || async { !fake.requests().is_empty() }).await;
+    fake.set_status(204);
+    // a retried batch would hold this one back for the retry time
+    sender.send(point("temperature", 2.0, 1)).await.unwrap();
+    wait_until("the next reading", || async { fake.requests().len() >= 2 }).await;
+
+    // rate limits are still retried
+    fake.set_status(429);
+    sender.send(point("temperature", 3.0, 2)).await.unwrap();
+    wait_until("the rate limited write", || async { fake.requests().len() >= 3 }).await;
+    fake.set_status(204);
+    drop(sender);
+    tokio::time::timeout(Duration::from_secs(5), task).await.unwrap().unwrap();
+
+    let bodies: Vec<String> = fake.requests().into_iter().map(|r| r.body).collect();
+    let lines: Vec<String> = [(1.0, 0), (2.0, 1), (3.0, 2), (3.0, 2)].iter().map(|(value, secs)| line("readings", &point("temperature", *value, *secs))).collect();
+    assert_eq!(bodies, lines);
+}
+
+#[tokio::test]
+async fn stored_readings_and_derived_metrics_are_forwarded() {
+    let fake = FakeInflux::start().await;
+    let harness = Harness::start(&format!(r#"
+        [[route]]
+        channels = ["log"]
+
+        [sinks.influxdb]
+        url = "{}"
+        batch_size = 1
+    "#, fake.url())).await;
+
+    harness.publish("esp32/temperature", "21.5").await;
+    harness.publish("esp32/humidity", "50").await;
+    harness.publish("esp32/humidity", "150").await;
+    wait_until("the dew point", || async { fake.written().iter().any(|l| l.contains("sensor=dew_point")) }).await;
+    wait_until("the impossible humidity", || async { !harness.dead_letters().await.is_empty() }).await;
+
+    let written = fake.written();
+    assert!(written.iter().any(|l| l.starts_with("readings,device=esp32,sensor=temperature,unit=°C value=21.5 ")), "{:?}", written);
+    assert!(written.iter().any(|l| l.starts_with("readings,device=esp32,sensor=humidity,unit=% value=50.0 ")), "{:?}", written);
+    // the impossible humidity was dead-lettered, not stored
+    assert!(written.iter().all(|l| !l.contains("value=150")), "{:?}", written);
+    harness.stop().await;
+}
@@ -0,0 +1,305 @@
+mod common;
+
+use std::time::Duration;
+use common::{wait_until, Harness};
+
+const EMAIL_ROUTE: &str = r#"
+    [[route]]
+    recipients = ["alice@example.com", "bob@example.com"]
+"#;
+
+#[tokio::test]
+async fn stores_readings_per_sensor() {
+    let harness = Harness::start(EMAIL_ROUTE).await;
+
+    harness.publish("esp32/temperature", "21").await;
+    harness.publish("esp32/humidity", "45").await;
+    harness.publish("esp32/motion", "0").await;
+    harness.publish("esp32/contact", "0").await;
+    harness.wait_for_readings("contact", 1).await;
+
+    assert_eq!(harness.values("temperature").await, vec![21.0]);
+    assert_eq!(harness.values("humidity").await, vec![45.0]);
+    assert_eq!(harness.values("motion").await, vec![0.0]);
+    assert_eq!(harness.values("contact").await, vec![0.0]);
+    assert!(harness.smtp.emails().is_empty());
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn dead_letters_malformed_payloads_and_keeps_going() {
+    let harness = Harness::start(EMAIL_ROUTE).await;
+
+    harness.publish("esp32/temperature", "warm").await;
+    harness.publish("esp32/temperature", "22").await;
+    harness.wait_for_readings("temperature", 1).await;
+
+    assert_eq!(harness.values("temperature").await, vec![22.0]);
+    let dead_letters = harness.dead_letters().await;
+    assert_eq!(dead_letters.len(), 1);
+    assert_eq!(dead_letters[0].0, "esp32/temperature");
+    assert_eq!(dead_letters[0].1, "warm");
+    assert!(dead_letters[0].2.starts_with("invalid value"), "{}", dead_letters[0].2);
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn stores_signed_and_fractional_values() {
+    let harness = Harness::start(EMAIL_ROUTE).await;
+
+    harness.publish("esp32/temperature", "-5.3").await;
+    harness.publish("esp32/temperature", "inf").await;
+    harness.publish("esp32/humidity", "45.5").await;
+    harness.wait_for_readings("temperature", 1).await;
+    harness.wait_for_readings("humidity", 1).await;
+    wait_until("dead letter", || async { !harness.dead_letters().await.is_empty() }).await;
+
+    assert_eq!(harness.values("temperature").await, vec![-5.3]);
+    assert_eq!(harness.values("humidity").await, vec![45.5]);
+    let dead_letters = harness.dead_letters().await;
+    assert_eq!(dead_letters.len(), 1);
+    assert_eq!(dead_letters[0].1, "inf");
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn dead_letters_impossible_values_and_reports_sensor_faults() {
+    let harness = Harness::start(r#"
+        [[route]]
+        kinds = ["fault"]
+        recipients = ["admin@example.com"]
+
+        [rules.fault]
+    "#).await;
+
+    harness.publish("esp32/humidity", "120").await;
+    harness.publish("esp32/motion", "2").await;
+    harness.publish("esp32/temperature", "55").await;
+    harness.wait_for_readings("temperature", 1).await;
+    wait_until("dead letters", || async { harness.dead_letters().await.len() == 2 }).await;
+
+    // outside the DHT11 range but possible, so the reading is kept
+    assert_eq!(harness.values("temperature").await, vec![55.0]);
+    assert!(harness.values("humidity").await.is_empty());
+    let mut errors: Vec<String> = harness.dead_letters().await.into_iter().map(|d| d.2).collect();
+    errors.sort();
+    assert_eq!(errors, vec!["impossible value: humidity 120", "impossible value: motion 2"]);
+    // both faults share the cooldown of the device
+    let emails = harness.wait_for_emails(1).await;
+    assert_eq!(emails[0].to, vec!["admin@example.com"]);
+    assert_eq!(emails[0].subject(), Some("Sensor fault"));
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn stores_json_payloads_with_device_time() {
+    let harness = Harness::start(EMAIL_ROUTE).await;
+
+    // read an hour before it was sent, e.g. cached during an outage
+    harness.publish("esp32/temperature", r#"{"v":1,"value":-5.3,"unit":"°C","ts":1000,"up":3601000,"seq":3,"fw":"0.1.0"}"#).await;
+    harness.publish("esp32/humidity", r#"{"v":1,"value":45.5,"unit":"°C","ts":1000,"up":1000,"seq":3,"fw":"0.1.0"}"#).await;
+    harness.publish("esp32/humidity", "46").await;
+    harness.wait_for_readings("temperature", 1).await;
+    harness.wait_for_readings("humidity", 1).await;
+
+    let (value, seq, firmware, age): (f64, i64, String, f64) = sqlx::query_as(
+        "select value, seq, firmware, (julianday('now') - julianday(created_at)) * 86400 from readings where sensor = 'temperature'"
+    ).fetch_one(&harness.pool).await.unwrap();
+    assert_eq!((value, seq, firmware.as_str()), (-5.3, 3, "0.1.0"));
+    assert!((3590.0..3610.0).contains(&age), "{}", age);
+
+    // legacy plain values are still accepted, a mismatched unit is dead-lettered
+    assert_eq!(harness.values("humidity").await, vec![46.0]);
+    let dead_letters = harness.dead_letters().await;
+    assert_eq!(dead_letters.len(), 1);
+    assert!(dead_letters[0].2.starts_with("unit mismatch"), "{}", dead_letters[0].2);
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn drops_duplicates_and_counts_gaps() {
+    let harness = Harness::start(EMAIL_ROUTE).await;
+
+    // seq and uptime of each message, the board reboots before the last one
+    let messages = [(1, 1000), (2, 2000), (2, 2500), (5, 5000), (3, 5500), (1, 100)];
+    for (seq, up) in messages {
+        let payload = format!(r#"{{"v":1,"value":{seq},"ts":{up},"up":{up},"seq":{seq}}}"#);
+        harness.publish("esp32/humidity", &payload).await;
+    }
+    wait_until("sequence stats", || async {
+        let updated: i64 = sqlx::query_scalar("select received + duplicates + replays from sequence_stats")
+            .fetch_optional(&harness.pool).await.unwrap().unwrap_or(0);
+        updated == messages.len() as i64
+    }).await;
+
+    assert_eq!(harness.values("humidity").await, vec![1.0, 2.0, 5.0, 1.0]);
+    let stats: (i64, i64, i64, i64, i64, i64) = sqlx::query_as(
+        "select last_seq, received, missing, duplicates, replays, restarts from sequence_stats where device = 'esp32' and sensor = 'humidity'"
+    ).fetch_one(&harness.pool).await.unwrap();
+    assert_eq!(stats, (1, 4, 2, 1, 1, 1));
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn stores_derived_metrics_and_checks_them_against_rules() {
+    let harness = Harness::start(r#"
+        [[route]]
+        recipients = ["alice@example.com"]
+
+        [[rules.threshold]]
+        sensor = "dew_point"
+        above = 15
+    "#).await;
+
+    harness.publish("esp32/temperature", "25").await;
+    harness.publish("esp32/humidity", "60").await;
+    harness.wait_for_readings("absolute_humidity", 1).await;
+
+    assert_eq!(harness.values("dew_point").await, vec![16.7]);
+    assert_eq!(harness.values("heat_index").await, vec![25.1]);
+    assert_eq!(harness.values("absolute_humidity").await, vec![13.8]);
+    let emails = harness.wait_for_emails(1).await;
+    assert_eq!(emails[0].subject(), Some("dew_point alert"));
+
+    // the view pairs each metric with its device
+    let dew_points: Vec<(String, f64)> = sqlx::query_as("select device, value from dew_point")
+        .fetch_all(&harness.pool).await.unwrap();
+    assert_eq!(dew_points, vec![("esp32".to_string(), 16.7)]);
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn motion_sends_one_email_per_cooldown() {
+    let harness = Harness::start(EMAIL_ROUTE).await;
+
+    harness.publish("esp32/motion", "1").await;
+    let emails = harness.wait_for_emails(1).await;
+    assert_eq!(emails[0].from, "alerts@iiot.test");
+    assert_eq!(emails[0].to, vec!["alice@example.com", "bob@example.com"]);
+    assert_eq!(emails[0].subject(), Some("Motion alert"));
+
+    // still within the cooldown, the reading is stored but no email is sent
+    harness.publish("esp32/motion", "1").await;
+    harness.wait_for_readings("motion", 2).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(harness.smtp.emails().len(), 1);
+
+    // cooldowns are per alert kind, so a door alert still goes out
+    harness.publish("esp32/contact", "1").await;
+    let emails = harness.wait_for_emails(2).await;
+    assert_eq!(emails[1].subject(), Some("Contact alert"));
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn cooldown_expires() {
+    let harness = Harness::start(&format!("{}\n[cooldowns]\ndefault_secs = 1", EMAIL_ROUTE)).await;
+
+    harness.publish("esp32/contact", "1").await;
+    harness.wait_for_emails(1).await;
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    harness.publish("esp32/contact", "1").await;
+    harness.wait_for_emails(2).await;
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn routes_alerts_by_kind() {
+    let harness = Harness::start(r#"
+        [[route]]
+        kinds = ["contact"]
+        recipients = ["household@example.com"]
+
+        [[route]]
+        kinds = ["threshold"]
+        recipients = ["admin@example.com"]
+
+        [[rules.threshold]]
+        sensor = "temperature"
+        above = 30
+    "#).await;
+
+    harness.publish("esp32/temperature", "35").await;
+    let emails = harness.wait_for_emails(1).await;
+    assert_eq!(emails[0].to, vec!["admin@example.com"]);
+    assert_eq!(emails[0].subject(), Some("temperature alert"));
+
+    // no route matches motion, so nothing is sent
+    harness.publish("esp32/motion", "1").await;
+    harness.publish("esp32/contact", "1").await;
+    let emails = harness.wait_for_emails(2).await;
+    assert_eq!(emails[1].to, vec!["household@example.com"]);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(harness.smtp.emails().len(), 2);
+
+    harness.stop().await;
+}
+
+#[tokio::test]
+async fn shutdown_stores_messages_received_before_disconnect() {
+    let harness = Harness::start(EMAIL_ROUTE).await;
+
+    for value in 0..20 {
+        harness.publish("esp32/humidity", &value.to_string()).await;
+    }
+    harness.wait_for_readings("humidity", 20).await;
+    harness.publish("esp32/contact", "1").await;
+    harness.wait_for_readings("contact", 1).await;
+    let pool = harness.pool.clone();
+    let smtp = harness.smtp.clone();
+    harness.stop().await;
+
+    // the queued email was sent before the notifier stopped
+    assert_eq!(smtp.emails().len(), 1);
+    let count: i64 = sqlx::query_scalar("select count(*) from readings where sensor = 'humidity'")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(count, 20);
+}
+
+#[tokio::test]
+async fn shutdown_sends_queued_emails_and_commits_pending_writes() {
+    // writes wait for the flush time and every email takes a while, so both are still queued when the shutdown starts
+    let harness = Harness::start_with_database("batch_size = 1000\nflush_ms = 3000", &format!(r#"
+        {EMAIL_ROUTE}
+
+        [[action]]
+        kinds = ["contact"]
+        topic = "home/door/seen"
+        payload = "1"
+    "#)).await;
+    harness.subscribe("home/#").await;
+    harness.smtp.set_delay(Duration::from_millis(300));
+
+    for value in 0..20 {
+        harness.publish("esp32/humidity", &value.to_string()).await;
+    }
+    harness.publish("esp32/motion", "1").await;
+    harness.publish("esp32/contact", "1").await;
+    // the action goes out once the contact alert was raised, after the emails of both alerts were queued
+    harness.wait_for_messages("home/door/seen", 1).await;
+    assert_eq!(harness.count("humidity").await, 0);
+    assert!(harness.smtp.emails().len() < 2);
+    let pool = harness.pool.clone();
+    let smtp = harness.smtp.clone();
+    harness.stop().await;
+
+    let mut subjects: Vec<String> = smtp.emails().iter().filter_map(|e| e.subject().map(str::to_string)).collect();
+    subjects.sort();
+    assert_eq!(subjects, vec!["Contact alert", "Motion alert"]);
+    let stored: Vec<f64> = sqlx::query_scalar("select value from readings where sensor = 'humidity' order by id")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert_eq!(stored, (0..20).map(f64::from).collect::<Vec<_>>());
+}
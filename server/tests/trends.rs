@@ -0,0 +1,100 @@
+mod common;
+
+use sqlx::AnyPool;
+
+use common::{assert_invalid, at_minute as at, insert_reading, rules};
+use iiot_webserver::config::{Metric, SensorKind};
+use iiot_webserver::rules::Rules;
+use iiot_webserver::trend::fit;
+
+const TEMPERATURE: Metric = Metric::Sensor(SensorKind::Temperature);
+const HUMIDITY: Metric = Metric::Sensor(SensorKind::Humidity);
+
+// stores a reading like the subscriber does, then evaluates the rate and trend rules for it
+async fn store(pool: &AnyPool, rules: &Rules, metric: Metric, minute: i64, value: f64) -> Vec<String> {
+    insert_reading(pool, "esp32", metric.as_str(), value, at(minute)).await;
+    rules.check_changes(pool, "esp32", metric, at(minute)).await.unwrap().into_iter().map(|alert| alert.body).collect()
+}
+
+#[test]
+fn fits_a_line_through_the_readings() {
+    let line: Vec<_> = (0..5).map(|i| (at(i * 30), 50.0 + i as f64)).collect();
+    let line = fit(&line).unwrap();
+    assert!((line.slope - 2.0).abs() < 1e-9 && (line.r_squared - 1.0).abs() < 1e-9, "{:?}", line);
+
+    let flat: Vec<_> = (0..5).map(|i| (at(i), 50.0)).collect();
+    assert_eq!(fit(&flat).unwrap().r_squared, 0.0);
+    assert_eq!(fit(&flat[..2]), None);
+}
+
+#[tokio::test]
+async fn raises_rate_alerts_once_per_change() {
+    let pool = common::memory_database().await;
+    let rules = rules(r#"
+        [[rules.rate]]
+        sensor = "temperature"
+        rise = 3
+        fall = 5
+        window_secs = 600
+    "#);
+
+    for (minute, value) in [(0, 21.0), (5, 22.0), (10, 23.0), (15, 24.0)] {
+        assert!(store(&pool, &rules, TEMPERATURE, minute, value).await.is_empty());
+    }
+    // 20.5 to 24.5 within the last 10 minutes
+    assert!(store(&pool, &rules, TEMPERATURE, 16, 20.5).await.is_empty());
+    let alerts = store(&pool, &rules, TEMPERATURE, 17, 24.5).await;
+    assert_eq!(alerts, vec!["temperature on esp32 rose by 4.0 to 24.5 within 10 minutes"]);
+    assert!(store(&pool, &rules, TEMPERATURE, 18, 25.0).await.is_empty());
+
+    // a drop is reported on its own
+    let alerts = store(&pool, &rules, TEMPERATURE, 19, 19.0).await;
+    assert_eq!(alerts, vec!["temperature on esp32 fell by 6.0 to 19 within 10 minutes"]);
+    let alerts = rules.check_changes(&pool, "esp32", HUMIDITY, at(19)).await.unwrap();
+    assert!(alerts.is_empty());
+}
+
+#[tokio::test]
+async fn raises_trend_alerts_for_steady_changes_over_the_window() {
+    let pool = common::memory_database().await;
+    let rules = rules(r#"
+        [[rules.trend]]
+        sensor = "humidity"
+        direction = "rising"
+        window_secs = 7200
+        min_change = 5
+    "#);
+
+    // rising by 3% an hour, with the whole-number steps of the DHT11
+    let mut alerts = Vec::new();
+    for minute in (0..=120).step_by(5) {
+        alerts.push((minute, store(&pool, &rules, HUMIDITY, minute, 50.0 + (minute as f64 * 0.05).round()).await));
+    }
+    let raised: Vec<_> = alerts.iter().filter(|(_, alerts)| !alerts.is_empty()).collect();
+    assert_eq!(raised.len(), 1, "{:?}", raised);
+    // not before the readings cover the 2 hours
+    assert!(raised[0].0 >= 110, "{:?}", raised);
+    assert!(raised[0].1[0].starts_with("humidity on esp32 has been rising steadily for 2.0 hours, by 3.0 per hour"), "{:?}", raised);
+
+    // back and forth is not a trend
+    let pool = common::memory_database().await;
+    for minute in (0..=120).step_by(5) {
+        let value = if minute % 10 == 0 { 50.0 } else { 60.0 } + minute as f64 * 0.05;
+        assert!(store(&pool, &rules, HUMIDITY, minute, value).await.is_empty());
+    }
+}
+
+#[test]
+fn rejects_invalid_rate_and_trend_rules() {
+    assert_invalid(r#"
+        [[rules.rate]]
+        sensor = "contact"
+        window_secs = 0
+
+        [[rules.trend]]
+        sensor = "temperature"
+        direction = "falling"
+        window_secs = 3600
+        min_fit = 1.5
+    "#, &["rules.rate #1: rate rules", "rules.rate #1: needs", "rules.rate #1: `window_secs`", "rules.trend #1: `min_fit`"]);
+}
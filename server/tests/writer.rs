@@ -0,0 +1,99 @@
+mod common;
+
+use std::time::Duration;
+use sqlx::AnyPool;
+
+use common::{memory_database, wait_until};
+use iiot_webserver::config::Config;
+use iiot_webserver::db;
+use iiot_webserver::writer::{Write, Writer};
+
+fn reading(value: f64) -> Write {
+    Write::Reading {
+        device: "esp32".to_string(),
+        sensor: "temperature".to_string(),
+        value,
+        unit: "°C".to_string(),
+        seq: None,
+        firmware: None,
+        created_at: "2025-06-01 00:00:00".to_string(),
+    }
+}
+
+fn start(pool: &AnyPool, batch_size: usize, flush_ms: u64) -> (Writer, tokio::task::JoinHandle<()>) {
+    let config = Config::parse(&format!("[database]\nbatch_size = {}\nflush_ms = {}", batch_size, flush_ms)).unwrap();
+    Writer::start(pool.clone(), &config.database)
+}
+
+async fn values(pool: &AnyPool) -> Vec<f64> {
+    sqlx::query_scalar("select value from readings where device = 'esp32' order by id").fetch_all(pool).await.unwrap()
+}
+
+#[tokio::test]
+async fn writes_are_committed_once_the_batch_is_full_or_flushed() {
+    let pool = memory_database().await;
+    let (writer, task) = start(&pool, 3, 60_000);
+
+    writer.write(reading(1.0)).await;
+    writer.write(reading(2.0)).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(values(&pool).await.is_empty());
+    writer.write(reading(3.0)).await;
+    wait_until("the full batch", || async { values(&pool).await.len() == 3 }).await;
+
+    writer.write(reading(4.0)).await;
+    writer.flush().await;
+    assert_eq!(values(&pool).await, vec![1.0, 2.0, 3.0, 4.0]);
+
+    // what is left is committed when the last sender is gone
+    writer.write(reading(5.0)).await;
+    drop(writer);
+    tokio::time::timeout(Duration::from_secs(5), task).await.unwrap().unwrap();
+    assert_eq!(values(&pool).await.len(), 5);
+}
+
+#[tokio::test]
+async fn a_batch_is_committed_after_the_flush_time() {
+    let pool = memory_database().await;
+    let (writer, _task) = start(&pool, 100, 50);
+
+    writer.write(reading(1.0)).await;
+    writer.write(Write::DeadLetter { topic: "esp32/humidity".to_string(), payload: "150".to_string(), error: "out of range".to_string() }).await;
+    wait_until("the batch to be committed", || async { values(&pool).await == vec![1.0] }).await;
+    let topics: Vec<String> = sqlx::query_scalar("select topic from dead_letters").fetch_all(&pool).await.unwrap();
+    assert_eq!(topics, vec!["esp32/humidity"]);
+}
+
+#[tokio::test]
+async fn a_failing_write_does_not_lose_the_rest_of_its_batch() {
+    let pool = memory_database().await;
+    sqlx::raw_sql("drop table annotations").execute(&pool).await.unwrap();
+    let (writer, _task) = start(&pool, 100, 60_000);
+
+    writer.write(reading(1.0)).await;
+    writer.write(Write::Annotation {
+        device: "esp32".to_string(),
+        sensor: "temperature".to_string(),
+        script: "notes".to_string(),
+        text: "lost".to_string(),
+        created_at: "2025-06-01 00:00:00".to_string(),
+    }).await;
+    writer.write(reading(2.0)).await;
+    writer.flush().await;
+    assert_eq!(values(&pool).await, vec![1.0, 2.0]);
+}
+
+#[tokio::test]
+async fn sqlite_files_are_opened_in_wal_mode() {
+    let path = std::env::temp_dir().join(format!("iiot-{}-wal.db", std::process::id()));
+    let pool = db::connect(&format!("sqlite://{}?mode=rwc", path.display())).await.unwrap();
+
+    let journal_mode: String = sqlx::query_scalar("pragma journal_mode").fetch_one(&pool).await.unwrap();
+    let synchronous: i64 = sqlx::query_scalar("pragma synchronous").fetch_one(&pool).await.unwrap();
+    assert_eq!((journal_mode.as_str(), synchronous), ("wal", 1));
+
+    pool.close().await;
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+}